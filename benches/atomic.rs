@@ -0,0 +1,118 @@
+// Micro-benchmarks of the common operations on a shared `AtomicDouble<Bar>`,
+// both from a single thread and contended by several threads at once.
+//
+// Run with `cargo bench`; results are reported per operation. Comparing a run
+// with `--features outline-asm` against one without shows what inlining the
+// x86_64 `cmpxchg16b` into the caller saves, which is most visible in
+// `cas_loop`.
+
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use atomicdouble::AtomicDouble;
+use atomicdouble::Ordering::{Relaxed, SeqCst};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+struct Bar(u64, u64);
+
+type Op = fn(&AtomicDouble<Bar>);
+
+fn load(a: &AtomicDouble<Bar>) {
+    black_box(a.load(SeqCst));
+}
+
+fn store(a: &AtomicDouble<Bar>) {
+    a.store(black_box(Bar(1, 1)), SeqCst);
+}
+
+fn compare_exchange(a: &AtomicDouble<Bar>) {
+    let current = black_box(Bar(1, 1));
+    let _ = black_box(a.compare_exchange(current, Bar(1, 1), SeqCst, SeqCst));
+}
+
+fn fetch_add(a: &AtomicDouble<Bar>) {
+    black_box(a.fetch_add(black_box(Bar(1, 0)), SeqCst));
+}
+
+fn fetch_sub(a: &AtomicDouble<Bar>) {
+    black_box(a.fetch_sub(black_box(Bar(1, 0)), SeqCst));
+}
+
+// An increment written as a CAS loop in user code, around the exchange.
+fn cas_loop(a: &AtomicDouble<Bar>) {
+    let mut current = a.load(Relaxed);
+    loop {
+        let new = Bar(current.0.wrapping_add(1), current.1);
+        match a.compare_exchange_weak(current, new, SeqCst, Relaxed) {
+            Ok(_) => break,
+            Err(actual) => current = actual,
+        }
+    }
+}
+
+const OPS: [(&str, Op); 6] = [
+    ("load", load),
+    ("store", store),
+    ("compare_exchange", compare_exchange),
+    ("fetch_add", fetch_add),
+    ("fetch_sub", fetch_sub),
+    ("cas_loop", cas_loop),
+];
+
+fn uncontended(c: &mut Criterion) {
+    let mut group = c.benchmark_group("uncontended");
+    group.throughput(Throughput::Elements(1));
+    let a = AtomicDouble::new(Bar::default());
+    for &(name, op) in &OPS {
+        group.bench_function(name, |b| b.iter(|| op(&a)));
+    }
+    group.finish();
+}
+
+fn contended(c: &mut Criterion) {
+    for &(name, op) in &OPS {
+        let mut group = c.benchmark_group(format!("contended/{}", name));
+        for &threads in &[2, 4, 8] {
+            // Each iteration is one operation on every thread.
+            group.throughput(Throughput::Elements(threads as u64));
+            group.bench_with_input(
+                BenchmarkId::from_parameter(threads),
+                &threads,
+                |b, &threads| b.iter_custom(|iters| run_contended(threads, iters, op)),
+            );
+        }
+        group.finish();
+    }
+}
+
+// Runs `iters` operations on each of `threads` threads sharing one atomic, and
+// returns the time from the moment they are all released until the last one is
+// done. Spawning the threads is not measured.
+fn run_contended(threads: usize, iters: u64, op: Op) -> Duration {
+    let a = AtomicDouble::new(Bar::default());
+    let barrier = Barrier::new(threads + 1);
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    for _ in 0..iters {
+                        op(&a);
+                    }
+                })
+            })
+            .collect();
+        barrier.wait();
+        let start = Instant::now();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        start.elapsed()
+    })
+}
+
+criterion_group!(benches, uncontended, contended);
+criterion_main!(benches);
@@ -0,0 +1,114 @@
+// The same `fetch_add` workload on `AtomicDouble<u128>`, on
+// `portable_atomic::AtomicU128` and on a `u128` behind a `std::sync::Mutex`,
+// shared by 1, 2, 4 and 8 threads, as a baseline for comparing the crate with
+// the alternatives. Throughput is reported in operations per second.
+//
+// Needs the `bench-compare` feature, which pulls in `portable-atomic` for the
+// comparison only:
+//
+//     cargo bench --features bench-compare --bench compare
+
+use std::hint::black_box;
+use std::sync::{Barrier, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use atomicdouble::AtomicDouble;
+use atomicdouble::Ordering::SeqCst;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+trait Counter: Sync {
+    const NAME: &'static str;
+
+    fn new() -> Self;
+
+    fn fetch_add(&self, val: u128) -> u128;
+}
+
+impl Counter for AtomicDouble<u128> {
+    const NAME: &'static str = "atomicdouble";
+
+    fn new() -> Self {
+        AtomicDouble::new(0)
+    }
+
+    fn fetch_add(&self, val: u128) -> u128 {
+        AtomicDouble::fetch_add(self, val, SeqCst)
+    }
+}
+
+impl Counter for portable_atomic::AtomicU128 {
+    const NAME: &'static str = "portable-atomic";
+
+    fn new() -> Self {
+        portable_atomic::AtomicU128::new(0)
+    }
+
+    fn fetch_add(&self, val: u128) -> u128 {
+        portable_atomic::AtomicU128::fetch_add(self, val, SeqCst)
+    }
+}
+
+impl Counter for Mutex<u128> {
+    const NAME: &'static str = "mutex";
+
+    fn new() -> Self {
+        Mutex::new(0)
+    }
+
+    fn fetch_add(&self, val: u128) -> u128 {
+        let mut guard = self.lock().unwrap();
+        let prev = *guard;
+        *guard = prev.wrapping_add(val);
+        prev
+    }
+}
+
+// Runs `iters` additions on each of `threads` threads sharing one counter, and
+// returns the time from the moment they are all released until the last one is
+// done. Spawning the threads is not measured.
+fn run<C: Counter>(threads: usize, iters: u64) -> Duration {
+    let counter = C::new();
+    let barrier = Barrier::new(threads + 1);
+    thread::scope(|s| {
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    for _ in 0..iters {
+                        black_box(counter.fetch_add(black_box(1)));
+                    }
+                })
+            })
+            .collect();
+        barrier.wait();
+        let start = Instant::now();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        start.elapsed()
+    })
+}
+
+fn bench<C: Counter>(c: &mut Criterion, threads: usize) {
+    let mut group = c.benchmark_group(format!("fetch_add/{}", C::NAME));
+    // Each iteration is one addition on every thread.
+    group.throughput(Throughput::Elements(threads as u64));
+    group.bench_with_input(
+        BenchmarkId::from_parameter(threads),
+        &threads,
+        |b, &threads| b.iter_custom(|iters| run::<C>(threads, iters)),
+    );
+    group.finish();
+}
+
+fn compare(c: &mut Criterion) {
+    for &threads in &[1, 2, 4, 8] {
+        bench::<AtomicDouble<u128>>(c, threads);
+        bench::<portable_atomic::AtomicU128>(c, threads);
+        bench::<Mutex<u128>>(c, threads);
+    }
+}
+
+criterion_group!(benches, compare);
+criterion_main!(benches);
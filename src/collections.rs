@@ -0,0 +1,217 @@
+//! Lock-free collections built on `AtomicDouble`.
+//!
+//! These double as examples of the ABA-safe patterns the crate is meant for.
+
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::ptr;
+use core::sync::atomic::AtomicPtr;
+
+use crate::{AtomicDouble, Ordering};
+
+struct Node<T> {
+    value: UnsafeCell<MaybeUninit<T>>,
+    // Atomic because a thread holding a stale head may read it while the node
+    // is being pushed again by its new owner.
+    next: AtomicPtr<Node<T>>,
+}
+
+// A list head: the top node and a counter bumped by every exchange, so a head
+// that was popped and pushed back in the meantime doesn't compare equal.
+type Head<T> = AtomicDouble<(*mut Node<T>, usize)>;
+
+/// A lock-free LIFO stack, also known as a Treiber stack.
+///
+/// The head pointer is paired with a counter in a single `AtomicDouble`, which
+/// avoids the ABA problem. Popped nodes are kept on an internal free list and
+/// reused by later pushes rather than freed, so that a thread still looking at
+/// a stale head never reads freed memory. All nodes are freed when the stack is
+/// dropped.
+pub struct TreiberStack<T> {
+    head: Head<T>,
+    free: Head<T>,
+}
+
+// The values are moved in and out by value only, like with a `Mutex<Vec<T>>`.
+unsafe impl<T: Send> Send for TreiberStack<T> {}
+unsafe impl<T: Send> Sync for TreiberStack<T> {}
+
+impl<T> TreiberStack<T> {
+    /// Creates a new, empty stack.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub const fn new() -> TreiberStack<T> {
+        TreiberStack {
+            head: AtomicDouble::new((ptr::null_mut(), 0)),
+            free: AtomicDouble::new((ptr::null_mut(), 0)),
+        }
+    }
+
+    /// Creates a new, empty stack.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn new() -> TreiberStack<T> {
+        TreiberStack {
+            head: AtomicDouble::new((ptr::null_mut(), 0)),
+            free: AtomicDouble::new((ptr::null_mut(), 0)),
+        }
+    }
+
+    /// Pushes a value on top of the stack.
+    pub fn push(&self, value: T) {
+        let node = match unsafe { pop_node(&self.free) } {
+            Some(node) => node,
+            None => Box::into_raw(Box::new(Node {
+                value: UnsafeCell::new(MaybeUninit::uninit()),
+                next: AtomicPtr::new(ptr::null_mut()),
+            })),
+        };
+        unsafe {
+            (*(*node).value.get()).write(value);
+            push_node(&self.head, node);
+        }
+    }
+
+    /// Pops the value on top of the stack, or returns `None` if it is empty.
+    pub fn pop(&self) -> Option<T> {
+        unsafe {
+            let node = pop_node(&self.head)?;
+            let value = (*(*node).value.get()).assume_init_read();
+            push_node(&self.free, node);
+            Some(value)
+        }
+    }
+
+    /// Returns `true` if the stack contains no values.
+    ///
+    /// Other threads may push or pop at any time, so the answer can be out of
+    /// date as soon as it is returned.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Acquire).0.is_null()
+    }
+}
+
+// Only the thread that pushes or pops a node touches its value; the list
+// operations themselves only ever look at `next`.
+unsafe fn push_node<T>(list: &Head<T>, node: *mut Node<T>) {
+    let mut head = list.load(Ordering::Relaxed);
+    loop {
+        (*node).next.store(head.0, Ordering::Relaxed);
+        let new = (node, head.1.wrapping_add(1));
+        match list.compare_exchange_weak(head, new, Ordering::Release, Ordering::Relaxed) {
+            Ok(_) => return,
+            Err(actual) => head = actual,
+        }
+    }
+}
+
+unsafe fn pop_node<T>(list: &Head<T>) -> Option<*mut Node<T>> {
+    let mut head = list.load(Ordering::Acquire);
+    loop {
+        if head.0.is_null() {
+            return None;
+        }
+        // Nodes are never freed while the stack is alive, so this read is safe
+        // even if `head` is stale; the counter then makes the exchange fail.
+        let next = (*head.0).next.load(Ordering::Relaxed);
+        let new = (next, head.1.wrapping_add(1));
+        match list.compare_exchange_weak(head, new, Ordering::Acquire, Ordering::Acquire) {
+            Ok(_) => return Some(head.0),
+            Err(actual) => head = actual,
+        }
+    }
+}
+
+impl<T> Drop for TreiberStack<T> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+        let mut node = self.free.get_mut().0;
+        while !node.is_null() {
+            let next = unsafe { (*node).next.load(Ordering::Relaxed) };
+            drop(unsafe { Box::from_raw(node) });
+            node = next;
+        }
+    }
+}
+
+impl<T> Default for TreiberStack<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> fmt::Debug for TreiberStack<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TreiberStack")
+            .field("is_empty", &self.is_empty())
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::TreiberStack;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn push_pop() {
+        let stack = TreiberStack::new();
+        assert!(stack.is_empty());
+        stack.push(1);
+        stack.push(2);
+        assert!(!stack.is_empty());
+        assert_eq!(stack.pop(), Some(2));
+        stack.push(3);
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), None);
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn drops_remaining_values() {
+        let value = Arc::new(());
+        let stack = TreiberStack::new();
+        for _ in 0..3 {
+            stack.push(value.clone());
+        }
+        drop(stack.pop());
+        assert_eq!(Arc::strong_count(&value), 3);
+        drop(stack);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+
+    #[test]
+    fn concurrent_push_pop() {
+        let stack = Arc::new(TreiberStack::new());
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    for i in 0..n {
+                        stack.push(t * n + i);
+                        popped.extend(stack.pop());
+                    }
+                    popped
+                })
+            })
+            .collect();
+        let mut values = Vec::new();
+        for handle in handles {
+            values.extend(handle.join().unwrap());
+        }
+        while let Some(value) = stack.pop() {
+            values.push(value);
+        }
+        // Every value comes out exactly once.
+        values.sort_unstable();
+        assert_eq!(values, (0..4 * n).collect::<Vec<_>>());
+    }
+}
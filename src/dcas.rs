@@ -0,0 +1,580 @@
+// A two-location compare-and-swap built from the single-location one, after
+// the CASN of Harris, Fraser and Pratt ("A Practical Multi-Word
+// Compare-and-Swap Operation", 2002).
+//
+// Each cell is an `AtomicDouble<[u64; 2]>`: the value, and a tag that is zero
+// or refers to the descriptor of a `dcas` that has claimed the cell. A `dcas`
+// fills in a descriptor, then claims its two cells in address order, each
+// only while it still holds the expected value. Once both are claimed it
+// decides success, or failure if a cell held something else, and then
+// releases both cells, with the new values or the old ones. A thread that
+// runs into a claimed cell helps the `dcas` along instead of waiting for it.
+//
+// Claiming a cell is itself two steps, as in the paper's RDCSS: the cell is
+// first marked `PENDING`, then turned `OWNED` only if the descriptor is still
+// undecided. A slow helper can otherwise claim a cell for a `dcas` that has
+// already succeeded, and another thread would then release it with the new
+// value a second time.
+//
+// Descriptors come from a static pool and are reused, so a tag also carries
+// the descriptor's sequence number, which changes with every use. A tag whose
+// sequence number is out of date is left over from a finished `dcas`, and is
+// cleared without changing the value: it can only be a `PENDING` mark, or an
+// `OWNED` one on a cell of a `dcas` that failed.
+//
+// A helper also reads the other cell of the `dcas`, which may be gone as soon
+// as its owner returns. Helpers therefore register with the descriptor before
+// checking that it is still in use, and the owner waits for them to leave
+// before it returns.
+
+use core::fmt;
+use core::hint;
+use core::marker::PhantomData;
+use core::mem;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
+
+use crate::AtomicDouble;
+use crate::Ordering::{Acquire, Relaxed, Release, SeqCst};
+
+// The value and the tag.
+type Word = [u64; 2];
+
+// The kinds of tag, in its low two bits, under the descriptor's index and
+// its sequence number: `seq << 8 | index << 2 | kind`.
+const PENDING: u64 = 1;
+const OWNED: u64 = 2;
+
+// The states of a descriptor, in the low two bits of `status`, under its
+// sequence number.
+const UNDECIDED: u64 = 0;
+const SUCCEEDED: u64 = 1;
+const FAILED: u64 = 2;
+const IDLE: u64 = 3;
+
+const SEQ_MASK: u64 = (1 << 56) - 1;
+
+const POOL_SIZE: usize = 64;
+
+#[repr(align(64))]
+struct Descriptor {
+    in_use: AtomicBool,
+    status: AtomicU64,
+    // Threads other than the owner that are reading the descriptor.
+    helpers: AtomicUsize,
+    // The addresses of the two cells, lower one first.
+    cells: [AtomicUsize; 2],
+    expected: [AtomicU64; 2],
+    new: [AtomicU64; 2],
+}
+
+impl Descriptor {
+    const fn new() -> Descriptor {
+        Descriptor {
+            in_use: AtomicBool::new(false),
+            status: AtomicU64::new(IDLE),
+            helpers: AtomicUsize::new(0),
+            cells: [AtomicUsize::new(0), AtomicUsize::new(0)],
+            expected: [AtomicU64::new(0), AtomicU64::new(0)],
+            new: [AtomicU64::new(0), AtomicU64::new(0)],
+        }
+    }
+}
+
+#[allow(clippy::declare_interior_mutable_const)]
+const DESCRIPTOR: Descriptor = Descriptor::new();
+
+static POOL: [Descriptor; POOL_SIZE] = [DESCRIPTOR; POOL_SIZE];
+
+// Waits for a free descriptor, starting at a different one on every call so
+// that concurrent callers don't all contend for the first.
+fn claim() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let start = NEXT.fetch_add(1, Relaxed);
+    loop {
+        for i in 0..POOL_SIZE {
+            let index = start.wrapping_add(i) % POOL_SIZE;
+            let in_use = &POOL[index].in_use;
+            if !in_use.load(Relaxed)
+                && in_use
+                    .compare_exchange(false, true, Acquire, Relaxed)
+                    .is_ok()
+            {
+                return index;
+            }
+        }
+        hint::spin_loop();
+    }
+}
+
+// Safety: `addr` must be the address of a live cell.
+unsafe fn cell<'a>(addr: usize) -> &'a AtomicDouble<Word> {
+    &*(addr as *const AtomicDouble<Word>)
+}
+
+// A `dcas` in progress, as read from its descriptor.
+struct Op {
+    index: usize,
+    seq: u64,
+    cells: [usize; 2],
+    expected: [u64; 2],
+    new: [u64; 2],
+}
+
+impl Op {
+    // Safety: the caller must be registered as a helper of the descriptor,
+    // and must have seen it in use with sequence number `seq` since.
+    unsafe fn read(index: usize, seq: u64) -> Op {
+        let d = &POOL[index];
+        // The fields were written before the status was published, and the
+        // owner can't reuse the descriptor while a helper is registered.
+        Op {
+            index,
+            seq,
+            cells: [d.cells[0].load(Relaxed), d.cells[1].load(Relaxed)],
+            expected: [d.expected[0].load(Relaxed), d.expected[1].load(Relaxed)],
+            new: [d.new[0].load(Relaxed), d.new[1].load(Relaxed)],
+        }
+    }
+
+    fn tag(&self, kind: u64) -> u64 {
+        self.seq << 8 | (self.index as u64) << 2 | kind
+    }
+
+    // The state of the `dcas`, or `None` once its owner is done with it.
+    fn state(&self) -> Option<u64> {
+        let status = POOL[self.index].status.load(SeqCst);
+        if status >> 2 == self.seq && status & 3 != IDLE {
+            Some(status & 3)
+        } else {
+            None
+        }
+    }
+
+    fn decide(&self, state: u64) {
+        let _ = POOL[self.index].status.compare_exchange(
+            self.seq << 2 | UNDECIDED,
+            self.seq << 2 | state,
+            SeqCst,
+            SeqCst,
+        );
+    }
+
+    // Turns a `PENDING` mark of this `dcas` into a claim, or removes it if
+    // the `dcas` has been decided in the meantime.
+    fn complete_pending(&self, cell: &AtomicDouble<Word>, current: Word) {
+        let next = match self.state() {
+            Some(UNDECIDED) => [current[0], self.tag(OWNED)],
+            _ => [current[0], 0],
+        };
+        let _ = cell.compare_exchange(current, next, SeqCst, SeqCst);
+    }
+
+    // Claims both cells. Returns `false` if the `dcas` was decided on the
+    // way, by this thread or another.
+    //
+    // Safety: the cells must be live.
+    unsafe fn acquire(&self) -> bool {
+        for i in 0..2 {
+            let cell = cell(self.cells[i]);
+            loop {
+                if self.state() != Some(UNDECIDED) {
+                    return false;
+                }
+                let current = cell.load(SeqCst);
+                if current[1] == self.tag(OWNED) {
+                    break;
+                } else if current[1] == self.tag(PENDING) {
+                    self.complete_pending(cell, current);
+                } else if current[1] != 0 {
+                    help(cell, current);
+                } else if current[0] != self.expected[i] {
+                    self.decide(FAILED);
+                    return false;
+                } else {
+                    let pending = [current[0], self.tag(PENDING)];
+                    if cell
+                        .compare_exchange(current, pending, SeqCst, SeqCst)
+                        .is_ok()
+                    {
+                        self.complete_pending(cell, pending);
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // Runs the `dcas` to completion and returns whether it succeeded.
+    //
+    // Safety: the cells must be live.
+    unsafe fn run(&self) -> bool {
+        if self.acquire() {
+            self.decide(SUCCEEDED);
+        }
+        let state = match self.state() {
+            Some(state) => state,
+            // The owner has already released the cells.
+            None => return false,
+        };
+        for i in 0..2 {
+            let value = if state == SUCCEEDED {
+                self.new[i]
+            } else {
+                self.expected[i]
+            };
+            let _ = cell(self.cells[i]).compare_exchange(
+                [self.expected[i], self.tag(OWNED)],
+                [value, 0],
+                SeqCst,
+                SeqCst,
+            );
+        }
+        state == SUCCEEDED
+    }
+}
+
+// Moves the `dcas` that tagged `cell` with `current[1]` along, so that the
+// tag is gone once it returns.
+fn help(cell: &AtomicDouble<Word>, current: Word) {
+    let tag = current[1];
+    let index = (tag >> 2) as usize % POOL_SIZE;
+    let seq = tag >> 8;
+    let d = &POOL[index];
+    // Registering before checking the status means that either the owner
+    // sees this thread and waits for it, or this thread sees that the owner
+    // is done.
+    d.helpers.fetch_add(1, SeqCst);
+    let status = d.status.load(SeqCst);
+    if status >> 2 == seq && status & 3 != IDLE {
+        // Safety: registered, and the descriptor is in use with `seq`, so
+        // its owner is still borrowing both cells.
+        unsafe {
+            let op = Op::read(index, seq);
+            if tag & 3 == PENDING {
+                op.complete_pending(cell, current);
+            } else {
+                op.run();
+            }
+        }
+    } else {
+        let _ = cell.compare_exchange(current, [current[0], 0], SeqCst, SeqCst);
+    }
+    d.helpers.fetch_sub(1, Release);
+}
+
+// The value, zero-extended to a full word, as in `AtomicStamped`. Zeroing the
+// word first only covers the bytes past the end of `T`: padding inside `T` is
+// uninitialized again once the value is written, which is why `DcasCell`
+// documents that `T` must not have any.
+#[derive(Clone, Copy)]
+#[repr(C)]
+union Bits<T: Copy> {
+    value: T,
+    bits: u64,
+}
+
+#[inline]
+const fn to_bits<T: Copy>(value: T) -> u64 {
+    let mut bits = Bits { bits: 0 };
+    bits.value = value;
+    unsafe { bits.bits }
+}
+
+#[inline]
+fn from_bits<T: Copy>(bits: u64) -> T {
+    unsafe { Bits { bits }.value }
+}
+
+/// A value of up to 8 bytes that can take part in a [`dcas`].
+///
+/// `dcas` needs room next to the value to mark the cell as taken by an
+/// operation in progress, so it can't work on a plain `AtomicDouble`. The
+/// methods of `DcasCell` finish any `dcas` they run into before acting on
+/// the value, which keeps them consistent with it. They are all `SeqCst`.
+///
+/// Values are compared by their bytes, as with `AtomicDouble::compare_exchange`.
+/// A larger `T` is rejected at compile time. As for `AtomicDouble`, `T` must
+/// not have padding bytes (see the crate's notes on layout): those are
+/// uninitialized, and would be compared too.
+pub struct DcasCell<T: Copy> {
+    inner: AtomicDouble<Word>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Copy> DcasCell<T> {
+    // Referenced from the constructors, like `AtomicDouble::SIZE_CHECK`.
+    const SIZE_CHECK: () = assert!(
+        mem::size_of::<T>() <= 8,
+        "`DcasCell<T>` requires a type of at most 8 bytes"
+    );
+
+    /// Creates a new `DcasCell` holding `value`.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub const fn new(value: T) -> DcasCell<T> {
+        let () = Self::SIZE_CHECK;
+        DcasCell {
+            inner: AtomicDouble::new([to_bits(value), 0]),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a new `DcasCell` holding `value`.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn new(value: T) -> DcasCell<T> {
+        let () = Self::SIZE_CHECK;
+        DcasCell {
+            inner: AtomicDouble::new([to_bits(value), 0]),
+            _marker: PhantomData,
+        }
+    }
+
+    // The current word, with no `dcas` in progress on it.
+    #[inline]
+    fn settled(&self) -> Word {
+        loop {
+            let current = self.inner.load(SeqCst);
+            if current[1] == 0 {
+                return current;
+            }
+            help(&self.inner, current);
+        }
+    }
+
+    /// Loads the value.
+    #[inline]
+    pub fn load(&self) -> T {
+        from_bits(self.settled()[0])
+    }
+
+    /// Stores a value.
+    #[inline]
+    pub fn store(&self, value: T) {
+        let new = [to_bits(value), 0];
+        let mut current = self.settled();
+        while self
+            .inner
+            .compare_exchange(current, new, SeqCst, SeqCst)
+            .is_err()
+        {
+            current = self.settled();
+        }
+    }
+
+    /// Stores `new` if the current value is `current`, and returns the
+    /// previous value in `Ok`, or the current value in `Err`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange(&self, current: T, new: T) -> Result<T, T> {
+        let expected = to_bits(current);
+        let new = [to_bits(new), 0];
+        loop {
+            let word = self.settled();
+            if word[0] != expected {
+                return Err(from_bits(word[0]));
+            }
+            if self
+                .inner
+                .compare_exchange(word, new, SeqCst, SeqCst)
+                .is_ok()
+            {
+                return Ok(current);
+            }
+        }
+    }
+
+    /// Consumes the cell and returns the value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        // Taking `self` means no `dcas` is in progress, and a tag left over
+        // from a finished one never changes the value.
+        from_bits(self.inner.into_inner()[0])
+    }
+
+    #[inline]
+    fn addr(&self) -> usize {
+        self.inner.as_ptr() as usize
+    }
+}
+
+impl<T: Copy + Default> Default for DcasCell<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for DcasCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("DcasCell").field(&self.load()).finish()
+    }
+}
+
+/// Stores `new_a` in `a` and `new_b` in `b` if `a` holds `current_a` and `b`
+/// holds `current_b`, as one atomic step. Returns whether it did.
+///
+/// This is experimental. It is built from the single-location
+/// `compare_exchange` with a descriptor that other threads help to
+/// completion, and has these limitations:
+///
+/// - It only works on [`DcasCell`]s, which hold at most 8 bytes, and not on
+///   plain `AtomicDouble`s.
+/// - Single-location operations on a `DcasCell` are slower than on an
+///   `AtomicDouble`: each first checks for, and finishes, a `dcas` in
+///   progress.
+/// - At most 64 calls can be in progress at once; more wait for one of them
+///   to return.
+/// - Before returning, `dcas` waits for the threads that are still helping
+///   it, so a helper that is preempted delays the return of that one call,
+///   though no other operation.
+/// - On failure it doesn't return the values it saw, as there is no single
+///   moment at which it saw both. Load them again to retry.
+/// - It is always `SeqCst`, and the cells are lock-free only where
+///   `AtomicDouble<[u64; 2]>` is.
+/// - It needs 64-bit atomics for its descriptors.
+///
+/// ```
+/// use atomicdouble::{dcas, DcasCell};
+///
+/// // Moves a token from one slot to another, so that no thread ever sees it
+/// // in both or in neither.
+/// let from = DcasCell::new(7u32);
+/// let to = DcasCell::new(0u32);
+/// assert!(dcas(&from, &to, 7, 0, 0, 7));
+/// assert_eq!((from.load(), to.load()), (0, 7));
+/// assert!(!dcas(&from, &to, 7, 0, 0, 7));
+/// ```
+///
+/// # Panics
+///
+/// Panics if `a` and `b` are the same cell.
+#[must_use = "the result of a dcas indicates whether the swap happened and must be checked"]
+pub fn dcas<T: Copy>(
+    a: &DcasCell<T>,
+    b: &DcasCell<T>,
+    current_a: T,
+    new_a: T,
+    current_b: T,
+    new_b: T,
+) -> bool {
+    assert!(a.addr() != b.addr(), "`dcas` requires two different cells");
+    let mut cells = [a.addr(), b.addr()];
+    let mut expected = [to_bits(current_a), to_bits(current_b)];
+    let mut new = [to_bits(new_a), to_bits(new_b)];
+    // Claiming cells in address order means two `dcas` never wait for each
+    // other in a cycle.
+    if cells[1] < cells[0] {
+        cells.swap(0, 1);
+        expected.swap(0, 1);
+        new.swap(0, 1);
+    }
+
+    let index = claim();
+    let d = &POOL[index];
+    let seq = ((d.status.load(Relaxed) >> 2) + 1) & SEQ_MASK;
+    for i in 0..2 {
+        d.cells[i].store(cells[i], Relaxed);
+        d.expected[i].store(expected[i], Relaxed);
+        d.new[i].store(new[i], Relaxed);
+    }
+    d.status.store(seq << 2 | UNDECIDED, SeqCst);
+
+    let op = Op {
+        index,
+        seq,
+        cells,
+        expected,
+        new,
+    };
+    // Safety: `a` and `b` are borrowed until this returns.
+    let succeeded = unsafe { op.run() };
+
+    d.status.store(seq << 2 | IDLE, SeqCst);
+    while d.helpers.load(SeqCst) != 0 {
+        hint::spin_loop();
+    }
+    d.in_use.store(false, Release);
+    succeeded
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::{dcas, DcasCell};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn dcas_compares_both_cells() {
+        let a = DcasCell::new(1u64);
+        let b = DcasCell::new(2u64);
+        assert!(dcas(&a, &b, 1, 3, 2, 4));
+        assert_eq!((a.load(), b.load()), (3, 4));
+        assert!(!dcas(&a, &b, 3, 5, 9, 6));
+        assert!(!dcas(&a, &b, 9, 5, 4, 6));
+        assert_eq!((a.load(), b.load()), (3, 4));
+        // The order of the cells doesn't matter.
+        assert!(dcas(&b, &a, 4, 6, 3, 5));
+        assert_eq!((a.load(), b.load()), (5, 6));
+
+        assert_eq!(a.compare_exchange(5, 7), Ok(5));
+        assert_eq!(a.compare_exchange(5, 8), Err(7));
+        b.store(9);
+        assert_eq!(format!("{:?}", b), "DcasCell(9)");
+        assert_eq!((a.into_inner(), b.into_inner()), (7, 9));
+        assert_eq!(DcasCell::<u8>::default().load(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "two different cells")]
+    fn dcas_same_cell() {
+        let a = DcasCell::new(1u32);
+        let _ = dcas(&a, &a, 1, 2, 1, 2);
+    }
+
+    #[test]
+    fn dcas_moves_between_slots() {
+        // Tokens 1 to 3 move at random between 6 slots; 0 is an empty slot.
+        const SLOTS: usize = 6;
+        let slots: Arc<Vec<DcasCell<u32>>> = Arc::new(
+            (0..SLOTS as u32)
+                .map(|i| DcasCell::new(if i < 3 { i + 1 } else { 0 }))
+                .collect(),
+        );
+        let n = if cfg!(miri) { 20 } else { 20_000 };
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let slots = slots.clone();
+                thread::spawn(move || {
+                    let mut x = t.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+                    let mut moves = 0;
+                    for _ in 0..n {
+                        x ^= x << 13;
+                        x ^= x >> 7;
+                        x ^= x << 17;
+                        let from = x as usize % SLOTS;
+                        let to = (from + 1 + (x >> 32) as usize % (SLOTS - 1)) % SLOTS;
+                        let token = slots[from].load();
+                        if token != 0 && dcas(&slots[from], &slots[to], token, 0, 0, token) {
+                            moves += 1;
+                        }
+                        // A `dcas` that changes nothing confirms both values
+                        // at once, and no token is ever in two slots.
+                        let (x, y) = (slots[from].load(), slots[to].load());
+                        assert!(!(x != 0 && x == y && dcas(&slots[from], &slots[to], x, x, y, y)));
+                    }
+                    moves
+                })
+            })
+            .collect();
+        let moves: u32 = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert!(moves > 0);
+        let mut tokens: Vec<u32> = slots
+            .iter()
+            .map(DcasCell::load)
+            .filter(|&t| t != 0)
+            .collect();
+        tokens.sort_unstable();
+        assert_eq!(tokens, [1, 2, 3]);
+    }
+}
@@ -5,19 +5,24 @@
 // http://opensource.org/licenses/MIT>, at your option. This file may not be
 // copied, modified, or distributed except according to those terms.
 
+#[cfg(any(feature = "fallback", miri))]
+use core::hint;
 use core::mem;
+#[cfg(any(feature = "fallback", miri))]
 use core::ptr;
 use core::slice;
+#[cfg(any(feature = "fallback", miri))]
 use core::sync::atomic::{AtomicUsize, Ordering};
-use std::hint;
 
 // We use an AtomicUsize instead of an AtomicBool because it performs better
 // on architectures that don't have byte-sized atomics.
 //
 // We give each spinlock its own cache line to avoid false sharing.
+#[cfg(any(feature = "fallback", miri))]
 #[repr(align(64))]
 struct SpinLock(AtomicUsize);
 
+#[cfg(any(feature = "fallback", miri))]
 impl SpinLock {
     fn lock(&self) {
         while self
@@ -61,25 +66,50 @@ macro_rules! array {
 
     [$e:expr; $n:tt] => { array!(@accum ($n, $e) -> ()) };
 }
-static SPINLOCKS: [SpinLock; 64] = array![SpinLock(AtomicUsize::new(0)); 64];
+/// Number of locks the fallback implementation shards atomics over.
+pub const SHARD_COUNT: usize = 64;
+
+#[cfg(any(feature = "fallback", miri))]
+static SPINLOCKS: [SpinLock; SHARD_COUNT] = array![SpinLock(AtomicUsize::new(0)); 64];
+
+#[cfg(all(feature = "seqlock-fallback", not(miri)))]
+pub mod seqlock;
+
+#[cfg(all(feature = "mutex-fallback", not(miri)))]
+pub mod mutex;
+
+// compare_exchange compares with memcmp instead of Eq
+#[inline]
+unsafe fn bytes_eq<T>(a: &T, b: &T) -> bool {
+    let a = slice::from_raw_parts(a as *const T as *const u8, mem::size_of::<T>());
+    let b = slice::from_raw_parts(b as *const T as *const u8, mem::size_of::<T>());
+    a == b
+}
 
 // Spinlock pointer hashing function from compiler-rt
 #[inline]
-fn lock_for_addr(addr: usize) -> &'static SpinLock {
+fn shard_for_addr(addr: usize) -> usize {
     // Disregard the lowest 4 bits.  We want all values that may be part of the
     // same memory operation to hash to the same value and therefore use the same
     // lock.
     let mut hash = addr >> 4;
     // Use the next bits as the basis for the hash
-    let low = hash & (SPINLOCKS.len() - 1);
+    let low = hash & (SHARD_COUNT - 1);
     // Now use the high(er) set of bits to perturb the hash, so that we don't
     // get collisions from atomic fields in a single object
     hash >>= 16;
     hash ^= low;
-    // Return a pointer to the lock to use
-    &SPINLOCKS[hash & (SPINLOCKS.len() - 1)]
+    // Return the index of the lock to use
+    hash & (SHARD_COUNT - 1)
 }
 
+#[cfg(any(feature = "fallback", miri))]
+#[inline]
+fn lock_for_addr(addr: usize) -> &'static SpinLock {
+    &SPINLOCKS[shard_for_addr(addr)]
+}
+
+#[cfg(any(feature = "fallback", miri))]
 #[inline]
 fn lock(addr: usize) -> LockGuard {
     let lock = lock_for_addr(addr);
@@ -87,7 +117,11 @@ fn lock(addr: usize) -> LockGuard {
     LockGuard(lock)
 }
 
+// Releasing the lock on drop also releases it while unwinding, so a panic in a
+// critical section can't leave the lock held and deadlock every other thread.
+#[cfg(any(feature = "fallback", miri))]
 struct LockGuard(&'static SpinLock);
+#[cfg(any(feature = "fallback", miri))]
 impl Drop for LockGuard {
     #[inline]
     fn drop(&mut self) {
@@ -95,20 +129,71 @@ impl Drop for LockGuard {
     }
 }
 
+#[cfg(any(feature = "fallback", miri))]
+#[inline]
+pub unsafe fn atomic_load<T>(dst: *mut T) -> T {
+    let _l = lock(dst as usize);
+    ptr::read(dst)
+}
+
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_swap<T>(dst: *mut T, val: T) -> T {
+    let _l = lock(dst as usize);
+    ptr::replace(dst, val)
+}
+
+#[cfg(any(feature = "fallback", miri))]
 #[inline]
 pub unsafe fn atomic_compare_exchange<T>(dst: *mut T, current: T, new: T) -> Result<T, T> {
     let _l = lock(dst as usize);
     let result = ptr::read(dst);
-    // compare_exchange compares with memcmp instead of Eq
-    let a = slice::from_raw_parts(&result as *const _ as *const u8, mem::size_of_val(&result));
-    let b = slice::from_raw_parts(
-        &current as *const _ as *const u8,
-        mem::size_of_val(&current),
-    );
-    if a == b {
+    if bytes_eq(&result, &current) {
         ptr::write(dst, new);
         Ok(result)
     } else {
         Err(result)
     }
 }
+
+#[cfg(all(test, any(feature = "fallback", miri)))]
+mod tests {
+    use super::{atomic_compare_exchange, lock, lock_for_addr};
+    use core::ptr;
+    use std::panic;
+    use std::thread;
+
+    #[test]
+    fn independent_shards() {
+        let mut values = [0u128; 2];
+        let a = &mut values[0] as *mut u128 as usize;
+        let b = &mut values[1] as *mut u128 as usize;
+        assert!(!ptr::eq(lock_for_addr(a), lock_for_addr(b)));
+
+        // Holding the lock of `a` must not stop an exchange on `b`.
+        let guard = lock(a);
+        thread::spawn(move || unsafe {
+            assert_eq!(atomic_compare_exchange(b as *mut u128, 0, 1), Ok(0));
+        })
+        .join()
+        .unwrap();
+        drop(guard);
+        assert_eq!(values, [0, 1]);
+    }
+
+    #[test]
+    fn unlock_on_panic() {
+        let mut value = 0u128;
+        let addr = &mut value as *mut u128 as usize;
+        let res = panic::catch_unwind(|| {
+            let _guard = lock(addr);
+            panic!("panic in the critical section");
+        });
+        assert!(res.is_err());
+        // Without the lock released this would spin forever.
+        unsafe {
+            assert_eq!(atomic_compare_exchange(addr as *mut u128, 0, 1), Ok(0));
+        }
+        assert_eq!(value, 1);
+    }
+}
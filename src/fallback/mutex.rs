@@ -0,0 +1,111 @@
+// A fallback on `std::sync::Mutex`, selected by the `mutex-fallback` feature.
+//
+// Unlike the spin-locks, a contended mutex puts the waiting thread to sleep.
+// With more threads than cores, spinning waiters can use up the time slices
+// the lock holder needs to finish, while sleeping ones leave them to it.
+
+use core::ptr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use super::{bytes_eq, shard_for_addr, SHARD_COUNT};
+
+// Like the spin-locks, each mutex gets its own cache line.
+#[repr(align(64))]
+struct Shard(Mutex<()>);
+
+static MUTEXES: [Shard; SHARD_COUNT] = array![Shard(Mutex::new(())); 64];
+
+// The critical sections only copy plain bytes and can't panic, so a poisoned
+// mutex can only come from a panic elsewhere while it was held, which leaves
+// the value intact.
+#[inline]
+fn lock(addr: usize) -> MutexGuard<'static, ()> {
+    MUTEXES[shard_for_addr(addr)]
+        .0
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+#[inline]
+pub unsafe fn atomic_load<T>(dst: *mut T) -> T {
+    let _l = lock(dst as usize);
+    ptr::read(dst)
+}
+
+#[inline]
+pub unsafe fn atomic_store<T>(dst: *mut T, val: T) {
+    let _l = lock(dst as usize);
+    ptr::write(dst, val);
+}
+
+#[inline]
+pub unsafe fn atomic_compare_exchange<T>(dst: *mut T, current: T, new: T) -> Result<T, T> {
+    let _l = lock(dst as usize);
+    let result = ptr::read(dst);
+    if bytes_eq(&result, &current) {
+        ptr::write(dst, new);
+        Ok(result)
+    } else {
+        Err(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{atomic_compare_exchange, atomic_load, atomic_store, lock};
+    use std::panic;
+    use std::sync::Arc;
+    use std::thread;
+
+    struct SendPtr(*mut u128);
+    unsafe impl Send for SendPtr {}
+    unsafe impl Sync for SendPtr {}
+
+    #[test]
+    fn progress_when_oversubscribed() {
+        let cores = thread::available_parallelism().map_or(1, |n| n.get());
+        let threads = 4 * cores;
+        let n = 1000;
+        let value = Box::into_raw(Box::new(0u128));
+        let ptr = Arc::new(SendPtr(value));
+        let handles: Vec<_> = (0..threads)
+            .map(|_| {
+                let ptr = ptr.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        let mut current = unsafe { atomic_load(ptr.0) };
+                        while let Err(actual) =
+                            unsafe { atomic_compare_exchange(ptr.0, current, current + 1) }
+                        {
+                            current = actual;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let value = *unsafe { Box::from_raw(value) };
+        assert_eq!(value, (threads * n) as u128);
+    }
+
+    #[test]
+    fn usable_after_panic() {
+        let mut value = 1u128;
+        let ptr = &mut value as *mut u128;
+        let addr = ptr as usize;
+        let res = panic::catch_unwind(|| {
+            let _guard = lock(addr);
+            panic!("panic in the critical section");
+        });
+        assert!(res.is_err());
+        // The mutex is poisoned, but the value is still reachable.
+        unsafe {
+            assert_eq!(atomic_load(ptr), 1);
+            atomic_store(ptr, 2);
+            assert_eq!(atomic_compare_exchange(ptr, 2, 3), Ok(2));
+        }
+        assert_eq!(value, 3);
+    }
+}
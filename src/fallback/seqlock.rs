@@ -0,0 +1,248 @@
+// A seqlock alternative to the spin-lock fallback, selected by the
+// `seqlock-fallback` feature.
+//
+// Each shard holds a sequence counter which is even while the shard is
+// unlocked and odd while a writer holds it. Readers never take the lock: they
+// copy the value optimistically and retry if the counter was odd or changed in
+// the meantime, so a read-heavy workload never blocks on other readers.
+//
+// A reader can run at the same time as a writer, so both copy the value with
+// relaxed atomic accesses, a word at a time where it is aligned for that and a
+// byte at a time otherwise. A racing read then sees a torn value, which the
+// counter tells it to throw away, rather than being a data race. Like the
+// native paths, which read `T` as an integer, this needs every byte of `T` to
+// be initialized.
+
+use core::hint;
+use core::mem::{self, MaybeUninit};
+use core::ptr;
+use core::sync::atomic::{self, AtomicU8, AtomicUsize, Ordering};
+
+use super::{bytes_eq, shard_for_addr, SHARD_COUNT};
+
+// Like the spin-locks, each sequence counter gets its own cache line.
+#[repr(align(64))]
+struct SeqLock(AtomicUsize);
+
+impl SeqLock {
+    // Returns the current stamp, or `None` if a writer holds the lock.
+    #[inline]
+    fn optimistic_read(&self) -> Option<usize> {
+        let stamp = self.0.load(Ordering::Acquire);
+        if stamp & 1 == 0 {
+            Some(stamp)
+        } else {
+            None
+        }
+    }
+
+    // Returns `true` if no writer has held the lock since `stamp` was read.
+    #[inline]
+    fn validate_read(&self, stamp: usize) -> bool {
+        // Orders the data reads before the re-check of the counter.
+        atomic::fence(Ordering::Acquire);
+        self.0.load(Ordering::Relaxed) == stamp
+    }
+
+    #[inline]
+    fn write(&'static self) -> SeqLockWriteGuard {
+        loop {
+            let stamp = self.0.load(Ordering::Relaxed);
+            if stamp & 1 == 0
+                && self
+                    .0
+                    .compare_exchange_weak(stamp, stamp + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                // Orders the odd stamp before the data writes, so that a reader
+                // seeing any of them also fails `validate_read`.
+                atomic::fence(Ordering::Release);
+                return SeqLockWriteGuard { lock: self, stamp };
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+// Like the spin-lock guard, this releases the lock while unwinding too.
+struct SeqLockWriteGuard {
+    lock: &'static SeqLock,
+    stamp: usize,
+}
+
+impl SeqLockWriteGuard {
+    // Releases the lock without bumping the counter, for a writer that ended up
+    // not writing anything. Readers that raced with it don't have to retry.
+    #[inline]
+    fn abort(self) {
+        self.lock.0.store(self.stamp, Ordering::Release);
+        core::mem::forget(self);
+    }
+}
+
+impl Drop for SeqLockWriteGuard {
+    #[inline]
+    fn drop(&mut self) {
+        self.lock
+            .0
+            .store(self.stamp.wrapping_add(2), Ordering::Release);
+    }
+}
+
+static SEQLOCKS: [SeqLock; SHARD_COUNT] = array![SeqLock(AtomicUsize::new(0)); 64];
+
+#[inline]
+fn lock_for_addr(addr: usize) -> &'static SeqLock {
+    &SEQLOCKS[shard_for_addr(addr)]
+}
+
+const WORD: usize = mem::size_of::<usize>();
+
+#[inline]
+unsafe fn read_words<T>(src: *const T) -> MaybeUninit<T> {
+    let mut val = MaybeUninit::<T>::uninit();
+    let src = src as *const u8;
+    let out = val.as_mut_ptr() as *mut u8;
+    let mut i = 0;
+    if src as usize % mem::align_of::<usize>() == 0 {
+        while i + WORD <= mem::size_of::<T>() {
+            let word = (*(src.add(i) as *const AtomicUsize)).load(Ordering::Relaxed);
+            ptr::write_unaligned(out.add(i) as *mut usize, word);
+            i += WORD;
+        }
+    }
+    while i < mem::size_of::<T>() {
+        *out.add(i) = (*(src.add(i) as *const AtomicU8)).load(Ordering::Relaxed);
+        i += 1;
+    }
+    val
+}
+
+#[inline]
+unsafe fn write_words<T>(dst: *mut T, val: T) {
+    let val = MaybeUninit::new(val);
+    let src = val.as_ptr() as *const u8;
+    let dst = dst as *mut u8;
+    let mut i = 0;
+    if dst as usize % mem::align_of::<usize>() == 0 {
+        while i + WORD <= mem::size_of::<T>() {
+            let word = ptr::read_unaligned(src.add(i) as *const usize);
+            (*(dst.add(i) as *const AtomicUsize)).store(word, Ordering::Relaxed);
+            i += WORD;
+        }
+    }
+    while i < mem::size_of::<T>() {
+        (*(dst.add(i) as *const AtomicU8)).store(*src.add(i), Ordering::Relaxed);
+        i += 1;
+    }
+}
+
+#[inline]
+pub unsafe fn atomic_load<T>(dst: *mut T) -> T {
+    let lock = lock_for_addr(dst as usize);
+    loop {
+        if let Some(stamp) = lock.optimistic_read() {
+            // The copy may be torn by a concurrent writer, so it is only looked
+            // at once the stamp has been validated.
+            let val = read_words(dst);
+            if lock.validate_read(stamp) {
+                return val.assume_init();
+            }
+        }
+        hint::spin_loop();
+    }
+}
+
+#[inline]
+pub unsafe fn atomic_store<T>(dst: *mut T, val: T) {
+    let _guard = lock_for_addr(dst as usize).write();
+    write_words(dst, val);
+}
+
+#[inline]
+pub unsafe fn atomic_compare_exchange<T>(dst: *mut T, current: T, new: T) -> Result<T, T> {
+    let guard = lock_for_addr(dst as usize).write();
+    // Other writers are locked out, and readers don't write, so this read
+    // races with nothing.
+    let result = ptr::read(dst);
+    if bytes_eq(&result, &current) {
+        write_words(dst, new);
+        drop(guard);
+        Ok(result)
+    } else {
+        guard.abort();
+        Err(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{atomic_compare_exchange, atomic_load, atomic_store, lock_for_addr};
+    use std::panic;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Larger than 16 bytes, so it could never be copied in one instruction.
+    #[derive(Clone, Copy)]
+    #[repr(C)]
+    struct Wide([u64; 4]);
+
+    struct SendPtr(*mut Wide);
+    unsafe impl Send for SendPtr {}
+    unsafe impl Sync for SendPtr {}
+
+    #[test]
+    fn readers_never_see_torn_values() {
+        let value = Box::into_raw(Box::new(Wide([0; 4])));
+        let ptr = Arc::new(SendPtr(value));
+        let done = Arc::new(AtomicBool::new(false));
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let ptr = ptr.clone();
+                let done = done.clone();
+                thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        let Wide(words) = unsafe { atomic_load(ptr.0) };
+                        assert!(words.iter().all(|&w| w == words[0]), "torn read");
+                    }
+                })
+            })
+            .collect();
+        for i in 1..10_000u64 {
+            unsafe {
+                if i % 2 == 0 {
+                    atomic_store(ptr.0, Wide([i; 4]));
+                } else {
+                    let current = atomic_load(ptr.0);
+                    assert!(atomic_compare_exchange(ptr.0, current, Wide([i; 4])).is_ok());
+                }
+            }
+        }
+        done.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        let Wide(words) = *unsafe { Box::from_raw(value) };
+        assert_eq!(words, [9_999; 4]);
+    }
+
+    #[test]
+    fn unlock_on_panic() {
+        let mut value = Wide([1; 4]);
+        let ptr = &mut value as *mut Wide;
+        let addr = ptr as usize;
+        let res = panic::catch_unwind(|| {
+            let _guard = lock_for_addr(addr).write();
+            panic!("panic in the critical section");
+        });
+        assert!(res.is_err());
+        // Neither readers nor writers may be locked out afterwards.
+        unsafe {
+            let Wide(words) = atomic_load(ptr);
+            assert_eq!(words, [1; 4]);
+            atomic_store(ptr, Wide([2; 4]));
+        }
+        assert_eq!(value.0, [2; 4]);
+    }
+}
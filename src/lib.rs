@@ -5,8 +5,148 @@
 //! Double width atomics are an essential part of many lock free algorithms to avoid the ABA problem.
 //!
 //! The library provides a wrapper type `AtomicDouble<T>`. This wrapper provides 128-bit atomic operations
-//! for `T: Copy` types. For types that doesnt support 128-bit atomics, fallback implementation using spin-lock
-//! is provided.
+//! for `T: Copy` types. Types smaller than 16 bytes are zero-padded up to 128 bits, and larger types
+//! fail to compile. For platforms that doesnt
+//! support 128-bit atomics, fallback implementation using spin-lock is provided.
+//! Disabling the default features and enabling `seqlock-fallback` instead
+//! replaces it with a seqlock, whose loads never block each other.
+//! Enabling `mutex-fallback` instead, which needs `std`, replaces it with
+//! `std::sync::Mutex`, which puts waiting threads to sleep rather than
+//! spinning. That suits write-heavy use with more threads than cores, where
+//! spinning waiters can keep the lock holder from running at all.
+//!
+//! On 32-bit x86, where the double width of a pointer is 64 bits, types of up
+//! to 8 bytes are lock-free using `cmpxchg8b` instead.
+//!
+//! On riscv64, 128-bit atomics are only lock-free when the target is compiled
+//! with the Zacas extension (`-C target-feature=+zacas`), which provides
+//! `amocas.q`. The base `A` extension only has 64-bit LR/SC, which can't update
+//! 128 bits atomically, so otherwise the fallback is used.
+//!
+//! Without any of the `fallback`, `seqlock-fallback`, `mutex-fallback` or
+//! `portable-atomic` features, the crate only builds for targets where some
+//! type can be lock-free:
+//!
+//! - x86_64 builds, but the CPU must have `cmpxchg16b`, which is detected at
+//!   run time; on one without it every operation panics. Almost every x86_64
+//!   CPU has it.
+//! - aarch64 builds, and every operation is lock-free.
+//! - riscv64 only builds with the Zacas extension enabled at compile time.
+//! - 32-bit x86 with `cmpxchg8b` builds, but only types of up to 8 bytes are
+//!   supported; operations on larger ones panic.
+//! - Every other target, such as `wasm32` or 32-bit ARM, fails to compile with
+//!   an error naming the features to choose from.
+//!
+//! On x86_64 the check for `cmpxchg16b` trusts CPUID, which is also what
+//! `is_x86_feature_detected!` reads. Processors without the instruction, such
+//! as the first AMD64 ones, and hypervisors that hide it, report it missing,
+//! and the crate then uses the fallback. The `probe` feature checks the
+//! results of the instruction, not whether it runs: the first check also runs
+//! a failing and a succeeding `cmpxchg16b` on a local value, and uses the
+//! fallback if either result is off, as with an emulator that compares or
+//! stores only 64 bits. It does not guard against a processor that reports the
+//! instruction yet raises an invalid-opcode fault on it, such as one where
+//! firmware disabled it. There the process is killed by `SIGILL` during the
+//! probe; catching that would take a process-wide signal handler, which a
+//! library can't install without getting in the way of the application's.
+//! On such a machine the `tsan` feature, which always uses the fallback,
+//! avoids the instruction entirely. Neither the probe nor the fallback applies
+//! to builds with `-C target-feature=+cmpxchg16b`, which use the instruction
+//! unconditionally.
+//!
+//! The arithmetic and bitwise operations treat the bytes of `T` as one
+//! native-endian `u128`, the way `u128::from_ne_bytes` reads them, on
+//! big-endian targets as well. So on a little-endian target a carry runs from
+//! the first bytes of `T` towards the last, and on a big-endian one, such as
+//! `aarch64_be`, from the last towards the first; `fetch_add_u64x2` splits
+//! the value into its first and last eight bytes either way.
+//!
+//! # Layout
+//!
+//! Every operation works on the bytes of `T` as one 128-bit word, so the
+//! crate relies on a few things about `T`:
+//!
+//! - Its layout is fixed. With `#[repr(C)]` the fields are in declaration
+//!   order, and a `#[repr(transparent)]` newtype is laid out exactly like its
+//!   field, so `W(u128)` behaves the same as a plain `u128` and
+//!   `#[repr(C)] Pair(u64, u64)` the same as a `u128` made of the two words in
+//!   native-endian order. Without a `repr` the compiler may reorder fields,
+//!   which changes what the arithmetic, bitwise and min/max operations mean,
+//!   though not `load`, `store`, `swap` or `compare_exchange`.
+//! - It has no padding bytes. `compare_exchange` compares all bytes,
+//!   padding included, so a value equal in every field can still fail to
+//!   match. Bytes past the end of a `T` smaller than 16 bytes are the atomic's
+//!   padding, which operations neither compare nor change.
+//! - Every bit pattern the arithmetic and bitwise operations can produce is a
+//!   valid `T`. That rules out those operations for types with a niche, such
+//!   as `bool`, references or `NonNull`; the other operations only ever store
+//!   values that were passed in.
+//!
+//! With the `bytemuck` feature, `AtomicDoublePod<T>` offers the same
+//! operations for `T: bytemuck::Pod` only, which rules out the types that
+//! break these assumptions at compile time.
+//!
+//! `AtomicDouble::assert_layout` checks at compile time that `T` is exactly
+//! 16 bytes. A 16-byte `T` is lock-free wherever `u128` is.
+//!
+//! The crate is `no_std` unless the `std` feature is enabled. The `std` feature only
+//! switches CPU feature detection over to the cached detection in `std`, and on
+//! aarch64 enables runtime detection of the LSE `casp` instruction.
+//!
+//! The `portable-atomic` feature hands every 128-bit operation to
+//! [`portable_atomic::AtomicU128`](https://docs.rs/portable-atomic) instead of
+//! the crate's own inline assembly and fallback. That covers every target
+//! `portable-atomic` supports, with its own lock-based fallback where there is
+//! no 128-bit instruction; the API stays the same.
+//!
+//! On x86_64 the `cmpxchg16b` sequence is inlined into each operation, so a
+//! compare-exchange loop compiles to a single loop around the instruction
+//! instead of a call per attempt. The locked instruction itself dominates the
+//! cost, so this saves around a nanosecond per operation; the price is a few
+//! dozen bytes of code at every call site. The `outline-asm` feature keeps the
+//! sequence in one out-of-line function instead, for code-size-sensitive
+//! builds. `cargo bench` with and without it shows the difference.
+//!
+//! The `tsan` feature routes every operation through the spin-lock fallback,
+//! for builds instrumented with ThreadSanitizer. The sanitizer can't see the
+//! inline assembly behind the lock-free paths and would report races on the
+//! data they publish; it does understand the fallback's locks. `is_lock_free`
+//! returns `false` with it enabled, and the API is otherwise unchanged. A
+//! sanitized test run looks like:
+//!
+//! ```text
+//! RUSTFLAGS="-Zsanitizer=thread" cargo +nightly test --features tsan \
+//!     -Zbuild-std --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! The `alloc` feature, also enabled by `std`, adds `AtomicDouble::into_box`
+//! and `AtomicDouble::from_box` for atomics allocated on the heap.
+//!
+//! The `collections` feature adds the [`collections`] module, with a lock-free
+//! `TreiberStack<T>` showing how a pointer and a counter in one `AtomicDouble`
+//! avoid the ABA problem. It needs `alloc`.
+//!
+//! `AtomicStamped<T>` pairs a value of up to 8 bytes with a stamp that
+//! changes on every successful `compare_and_set`, like Java's
+//! `AtomicStampedReference`.
+//!
+//! `OnceDouble<T>` is a cell for a value of up to 16 bytes that can be set
+//! once, for lazily published data such as a configuration struct.
+//!
+//! The experimental `dcas` feature adds `dcas`, a compare-and-swap over two
+//! separate `DcasCell<T>`s at once, built from the single-location one.
+//! Other threads that run into a `dcas` in progress help it to completion
+//! rather than wait for it. Its documentation lists what it can't do.
+//!
+//! The `_detailed` variants of `compare_exchange` and `compare_exchange_weak`
+//! fail with a `CasError<T>`, which names the observed value and tells a
+//! spurious failure of a weak exchange from a different value.
+//!
+//! `UnsyncDouble<T>` has the same methods as `AtomicDouble<T>` but uses plain
+//! reads and writes, for code that is generic over whether it runs on one
+//! thread or several.
+//!
+//! The crate builds on stable Rust; the minimum supported Rust version is 1.69.
 //!
 //! Each method takes an `Ordering` which represents the strength of
 //! the memory barrier for that operation. These orderings are the
@@ -14,29 +154,203 @@
 //!
 //! [1]: http://llvm.org/docs/LangRef.html#memory-model-for-concurrent-operations
 
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![warn(rust_2018_idioms)]
 #![warn(missing_docs)]
-#![feature(stdsimd)]
-#![feature(cmpxchg16b_target_feature)]
 
-pub use core::sync::atomic::{fence, Ordering};
+pub use core::sync::atomic::{compiler_fence, fence, Ordering};
 
-use std::panic::RefUnwindSafe;
+use core::panic::RefUnwindSafe;
 
-#[cfg(feature = "fallback")]
+#[cfg(all(feature = "fallback", feature = "seqlock-fallback"))]
+compile_error!("the `fallback` and `seqlock-fallback` features are mutually exclusive; disable the default features to use `seqlock-fallback`");
+#[cfg(all(feature = "fallback", feature = "mutex-fallback"))]
+compile_error!("the `fallback` and `mutex-fallback` features are mutually exclusive; disable the default features to use `mutex-fallback`");
+#[cfg(all(feature = "seqlock-fallback", feature = "mutex-fallback"))]
+compile_error!("the `seqlock-fallback` and `mutex-fallback` features are mutually exclusive");
+
+// Without a fallback, on targets with no double-width CAS at all every
+// operation would panic at run time.
+#[cfg(not(any(
+    feature = "fallback",
+    feature = "seqlock-fallback",
+    feature = "mutex-fallback",
+    feature = "portable-atomic",
+    miri,
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    all(target_arch = "riscv64", target_feature = "zacas"),
+    all(target_arch = "x86", target_has_atomic = "64")
+)))]
+compile_error!("this target has no double-width atomics; enable one of the `fallback`, `seqlock-fallback`, `mutex-fallback` or `portable-atomic` features of `atomicdouble`");
+
+#[cfg(feature = "alloc")]
+extern crate alloc;
+
+#[cfg(feature = "collections")]
+pub mod collections;
+#[cfg(feature = "dcas")]
+mod dcas;
+#[cfg(any(
+    feature = "fallback",
+    feature = "seqlock-fallback",
+    feature = "mutex-fallback",
+    miri
+))]
 mod fallback;
+mod once;
 mod ops;
+#[cfg(feature = "bytemuck")]
+mod pod;
+mod stamped;
+mod tagged;
+mod unsync;
+
+#[cfg(feature = "dcas")]
+pub use dcas::{dcas, DcasCell};
+pub use once::OnceDouble;
+#[cfg(feature = "bytemuck")]
+pub use pod::AtomicDoublePod;
+pub use stamped::AtomicStamped;
+pub use tagged::{AtomicTaggedPtr, DoubleWordPtr, TaggedPtr};
+pub use unsync::UnsyncDouble;
 
 use core::cell::UnsafeCell;
 use core::fmt;
 
+/// Fails if `AtomicDouble<T>` is not lock-free for the given type.
+///
+/// `assert_lock_free!(T)` panics at run time unless
+/// `AtomicDouble::<T>::is_lock_free()` is `true`. It is meant for the startup
+/// path of a program that relies on lock-freedom, for instance to use an
+/// `AtomicDouble` from a signal handler, where the fallback lock could
+/// deadlock: a build that ends up on the fallback then fails right away
+/// instead of much later.
+///
+/// ```
+/// # if atomicdouble::AtomicDouble::<u128>::is_lock_free() {
+/// atomicdouble::assert_lock_free!(u128);
+/// # }
+/// ```
+///
+/// `assert_lock_free!(const T)` is an item that fails to compile unless
+/// `AtomicDouble::<T>::is_always_lock_free()` is `true`. That check can't do
+/// any run-time detection: on x86_64 `cmpxchg16b` is detected at run time, so
+/// the const form only passes when the target feature is enabled at compile
+/// time, for example with `-C target-feature=+cmpxchg16b`. Where that isn't
+/// an option, use the run-time form.
+///
+/// ```ignore
+/// atomicdouble::assert_lock_free!(const u128);
+/// ```
+#[macro_export]
+macro_rules! assert_lock_free {
+    (const $t:ty) => {
+        const _: () = ::core::assert!(
+            $crate::AtomicDouble::<$t>::is_always_lock_free(),
+            ::core::concat!(
+                "`AtomicDouble<",
+                ::core::stringify!($t),
+                ">` is not always lock-free on this target"
+            )
+        );
+    };
+    ($t:ty) => {
+        if !$crate::AtomicDouble::<$t>::is_lock_free() {
+            ::core::panic!(
+                "`AtomicDouble<{}>` is not lock-free on this CPU",
+                ::core::stringify!($t)
+            );
+        }
+    };
+}
+
 /// Wrapper type that provides the 128-bit atomic operations
+///
+/// An `AtomicDouble<T>` is always aligned to 16 bytes, whatever the alignment
+/// of `T`, as the 128-bit atomic instructions fault on a misaligned address.
+/// The type's own alignment is kept wherever it is placed: as a field of any
+/// struct, in an array, in a `Box` or an `Arc`. Only a pointer cast from
+/// memory that is not suitably aligned can break it, which is undefined
+/// behavior, and is caught by a debug assertion in every operation.
+///
+/// As `T: Copy`, values have no destructor: dropping an `AtomicDouble`, or
+/// overwriting its value with `store`, `swap` or an exchange, never runs any
+/// code for the old value. A `T` that stands for owned data, such as a raw
+/// pointer and a tag, must be released by whoever gets the last copy of it,
+/// which `swap`, `take` and `take_inner` are meant for; the atomic itself never
+/// frees anything, so nothing is dropped twice, but it can leak.
 #[repr(C, align(16))]
 pub struct AtomicDouble<T> {
     v: UnsafeCell<T>,
+    // With the `loom` feature every operation is serialized through a loom
+    // mutex, so that loom explores how operations from different threads
+    // interleave.
+    #[cfg(feature = "loom")]
+    lock: loom::sync::Mutex<()>,
+}
+
+const _: () = assert!(core::mem::align_of::<AtomicDouble<u8>>() == 16);
+
+/// A 128-bit unsigned integer type which can be safely shared between threads.
+///
+/// `fetch_add` and `fetch_sub` are wrapping integer addition and subtraction,
+/// and `fetch_max`/`fetch_min` compare the value as a `u128`.
+///
+/// ```
+/// use atomicdouble::AtomicU128;
+/// use atomicdouble::Ordering::SeqCst;
+///
+/// let a = AtomicU128::new(u128::MAX);
+/// assert_eq!(a.fetch_add(1, SeqCst), u128::MAX);
+/// assert_eq!(a.load(SeqCst), 0);
+/// ```
+pub type AtomicU128 = AtomicDouble<u128>;
+
+/// A 128-bit signed integer type which can be safely shared between threads.
+///
+/// `fetch_add` and `fetch_sub` are wrapping integer addition and subtraction,
+/// and `fetch_max_signed`/`fetch_min_signed` compare the value as an `i128`.
+///
+/// ```
+/// use atomicdouble::AtomicI128;
+/// use atomicdouble::Ordering::SeqCst;
+///
+/// let a = AtomicI128::new(i128::MIN);
+/// assert_eq!(a.fetch_sub(1, SeqCst), i128::MIN);
+/// assert_eq!(a.load(SeqCst), i128::MAX);
+/// assert_eq!(a.fetch_min_signed(-1, SeqCst), i128::MAX);
+/// assert_eq!(a.load(SeqCst), -1);
+/// ```
+pub type AtomicI128 = AtomicDouble<i128>;
+
+/// The error of `compare_exchange_detailed` and
+/// `compare_exchange_weak_detailed`, telling why the exchange failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CasError<T> {
+    /// The value found in the `AtomicDouble`, the one `Err` holds for
+    /// `compare_exchange`. It can be fed back in as the next `current`.
+    pub observed: T,
+    /// Whether `observed` is the same as `current`, compared bytewise as for
+    /// `compare_exchange`.
+    ///
+    /// A spurious failure did not find a different value: a weak exchange can
+    /// fail that way on LL/SC targets such as aarch64, when the exclusive
+    /// store is interrupted. Retrying with the same `current` may succeed.
+    /// When this is `false`, another value was stored, and retrying with the
+    /// same `current` can't succeed until it is stored back.
+    pub is_spurious: bool,
 }
 
+// Sharing an `AtomicDouble<T>` between threads only ever moves whole values of
+// `T` in and out of it, by copy, and never hands out a `&T`. That is the same as
+// sending the value to whichever thread loads it, so `T: Send` is required, but
+// `T: Sync` is not: no two threads can ever access the same `T` through it.
 unsafe impl<T: Copy + Send> Sync for AtomicDouble<T> {}
+// Moving an `AtomicDouble<T>` to another thread moves the `T` with it. This
+// would be derived from `UnsafeCell<T>: Send` anyway, but is spelled out so the
+// bound matches `Sync` and doesn't depend on the fields.
+unsafe impl<T: Copy + Send> Send for AtomicDouble<T> {}
 
 impl<T: Copy + RefUnwindSafe> RefUnwindSafe for AtomicDouble<T> {}
 
@@ -47,6 +361,29 @@ impl<T: Copy + Default> Default for AtomicDouble<T> {
     }
 }
 
+impl<T: Copy> From<T> for AtomicDouble<T> {
+    /// Converts a `T` into an `AtomicDouble<T>`.
+    #[inline]
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T: Copy> Clone for AtomicDouble<T> {
+    /// Creates a new, independent `AtomicDouble` holding a snapshot of the
+    /// current value, loaded with `SeqCst`.
+    ///
+    /// The clone does not share anything with the original: later operations
+    /// on one are never seen by the other. To share a single atomic between
+    /// owners, wrap it in an `Arc` instead.
+    #[inline]
+    fn clone(&self) -> Self {
+        Self::new(self.load(Ordering::SeqCst))
+    }
+}
+
+/// Formats a snapshot of the value taken with `load(SeqCst)`. Use
+/// `debug_address` to format the `AtomicDouble` without loading it.
 impl<T: Copy + fmt::Debug> fmt::Debug for AtomicDouble<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_tuple("AtomicDouble")
@@ -55,13 +392,195 @@ impl<T: Copy + fmt::Debug> fmt::Debug for AtomicDouble<T> {
     }
 }
 
+// Returned by `debug_address`.
+struct DebugAddress<T>(*const T);
+
+impl<T> fmt::Debug for DebugAddress<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AtomicDouble")
+            .field("type", &core::any::type_name::<T>())
+            .field("addr", &self.0)
+            .finish()
+    }
+}
+
+/// Formats a snapshot of the value taken with `load(SeqCst)`, like `Debug`.
+impl<T: Copy + fmt::Display> fmt::Display for AtomicDouble<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.load(Ordering::SeqCst), f)
+    }
+}
+
+/// Formats the address of the value, as `as_ptr` returns it, without loading
+/// it.
+impl<T> fmt::Pointer for AtomicDouble<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&self.as_ptr(), f)
+    }
+}
+
+/// Compares snapshots of both values taken with `load(SeqCst)`.
+///
+/// The two loads are separate operations, so this is not an atomic compound
+/// comparison. It is meant for single-threaded or quiescent contexts such as
+/// assertions in tests.
+impl<T: Copy + PartialEq> PartialEq for AtomicDouble<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.load(Ordering::SeqCst) == other.load(Ordering::SeqCst)
+    }
+}
+
+impl<T: Copy + Eq> Eq for AtomicDouble<T> {}
+
+/// Serializes a snapshot of the value taken with `load(SeqCst)`.
+#[cfg(feature = "serde")]
+impl<T: Copy + serde::Serialize> serde::Serialize for AtomicDouble<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.load(Ordering::SeqCst).serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T: Copy + serde::Deserialize<'de>> serde::Deserialize<'de> for AtomicDouble<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize(deserializer).map(Self::new)
+    }
+}
+
 impl<T> AtomicDouble<T> {
+    // Referenced from the constructors, so that it is evaluated for every `T`
+    // an `AtomicDouble` is built for and an oversized `T` fails to compile.
+    const SIZE_CHECK: () = assert!(
+        core::mem::size_of::<T>() <= 16,
+        "`AtomicDouble<T>` requires a type of at most 16 bytes"
+    );
+
     /// Creates a new `AtomicDouble`.
+    ///
+    /// `T` can be at most 16 bytes large; a larger `T` is rejected at compile
+    /// time. Like other errors raised while evaluating constants, this one is
+    /// only reported once the code is built, not by `cargo check`.
+    #[cfg(not(feature = "loom"))]
     #[inline]
     pub const fn new(v: T) -> AtomicDouble<T> {
+        let () = Self::SIZE_CHECK;
+        AtomicDouble {
+            v: UnsafeCell::new(v),
+        }
+    }
+
+    /// Creates a new `AtomicDouble`.
+    ///
+    /// `T` can be at most 16 bytes large; a larger `T` is rejected at compile
+    /// time.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn new(v: T) -> AtomicDouble<T> {
+        let () = Self::SIZE_CHECK;
         AtomicDouble {
             v: UnsafeCell::new(v),
+            lock: loom::sync::Mutex::new(()),
+        }
+    }
+
+    /// Get atomic access to a `&mut T`.
+    ///
+    /// The 128-bit atomic instructions need the value to be 16-byte aligned,
+    /// so `T` itself must have an alignment of at least 16, for example by
+    /// marking it `#[repr(align(16))]`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align_of::<T>()` is less than 16. A `T` larger than 16 bytes
+    /// is rejected at compile time, as for `new`.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub fn from_mut(v: &mut T) -> &mut AtomicDouble<T> {
+        let () = Self::SIZE_CHECK;
+        assert!(
+            core::mem::align_of::<T>() >= 16,
+            "`AtomicDouble::from_mut` requires a type aligned to at least 16 bytes"
+        );
+        debug_assert_eq!(v as *mut T as usize % 16, 0);
+        // `AtomicDouble<T>` is `repr(C)` around an `UnsafeCell<T>`, and
+        // with `T` aligned to 16 the extra alignment adds no padding.
+        unsafe { &mut *(v as *mut T as *mut AtomicDouble<T>) }
+    }
+
+    /// Get atomic access to the value behind a raw pointer, checking that it
+    /// is aligned to 16 bytes.
+    ///
+    /// This is the checked entry point for memory the crate didn't allocate,
+    /// such as a buffer handed over through FFI or a field of a packed struct.
+    /// It returns `None` if `ptr` is null or not 16-byte aligned, where the
+    /// 128-bit atomic instructions would fault or be undefined behavior.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// #[repr(C, align(16))]
+    /// struct Buffer([u64; 4]);
+    ///
+    /// let mut buffer = Buffer([1, 2, 3, 4]);
+    /// let ptr = buffer.0.as_mut_ptr() as *mut (u64, u64);
+    /// let a = unsafe { AtomicDouble::try_from_ptr(ptr) }.unwrap();
+    /// assert_eq!(a.load(SeqCst), (1, 2));
+    /// // Eight bytes in, the pointer is only aligned to 8.
+    /// assert!(unsafe { AtomicDouble::try_from_ptr(ptr.cast::<u64>().add(1).cast::<(u64, u64)>()) }.is_none());
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// If `ptr` is aligned, it must be valid for reads and writes of 16 bytes,
+    /// even when `T` is smaller, for the whole lifetime `'a`: the bytes past
+    /// the end of a smaller `T` are the atomic's padding. Operations read them
+    /// and may write them back, but only ever with what they held, so they are
+    /// left as they were. During that lifetime the memory must only be
+    /// accessed through atomic operations of this crate, as with `as_ptr`.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub unsafe fn try_from_ptr<'a>(ptr: *mut T) -> Option<&'a AtomicDouble<T>> {
+        let () = Self::SIZE_CHECK;
+        if ptr.is_null() || ptr as usize % 16 != 0 {
+            return None;
         }
+        // `AtomicDouble<T>` is `repr(C)` around an `UnsafeCell<T>`, and the
+        // caller guarantees the 16 bytes its alignment pads it to.
+        Some(&*(ptr as *const AtomicDouble<T>))
+    }
+
+    /// Get atomic access to a `&mut [T]` slice.
+    ///
+    /// As for `from_mut`, `T` must have an alignment of at least 16, which
+    /// makes every element of the slice 16-byte aligned. `T` must also be
+    /// exactly 16 bytes large, so that the elements of both slices are laid out
+    /// the same way.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `align_of::<T>()` is less than 16, or `size_of::<T>()` is not
+    /// 16.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub fn from_mut_slice(v: &mut [T]) -> &mut [AtomicDouble<T>] {
+        assert!(
+            core::mem::align_of::<T>() >= 16 && core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::from_mut_slice` requires a type of 16 bytes aligned to 16 bytes"
+        );
+        unsafe { core::slice::from_raw_parts_mut(v.as_mut_ptr() as *mut AtomicDouble<T>, v.len()) }
+    }
+
+    #[inline]
+    fn with<R>(&self, f: impl FnOnce(*mut T) -> R) -> R {
+        debug_assert!(
+            self.v.get() as usize % 16 == 0,
+            "`AtomicDouble` used at an address that is not aligned to 16 bytes"
+        );
+        #[cfg(feature = "loom")]
+        let _guard = self.lock.lock().unwrap();
+        f(self.v.get())
     }
 
     /// Checks if `AtomicDouble` objects of this type are lock-free.
@@ -73,9 +592,208 @@ impl<T> AtomicDouble<T> {
     pub fn is_lock_free() -> bool {
         ops::atomic_is_lock_free::<T>()
     }
+
+    /// Returns `true` if `AtomicDouble` objects of this type are lock-free on
+    /// every CPU the crate's target can run on.
+    ///
+    /// Unlike `is_lock_free`, this is a `const fn` that does no runtime
+    /// detection, and is therefore conservative: on x86_64 it is only `true`
+    /// when `cmpxchg16b` is enabled at compile time (for example with
+    /// `-C target-feature=+cmpxchg16b`), even though `is_lock_free` is `true`
+    /// on practically every x86_64 CPU. When this returns `true`, so does
+    /// `is_lock_free`.
+    #[inline]
+    pub const fn is_always_lock_free() -> bool {
+        ops::atomic_is_always_lock_free::<T>()
+    }
+
+    /// Asserts that `T` is exactly 16 bytes, so that it fills the whole 128-bit
+    /// word and no zero padding is added around it.
+    ///
+    /// Meant for a `const` item next to the definition of `T`, where a type
+    /// that stops being 16 bytes fails to compile:
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    ///
+    /// #[derive(Clone, Copy)]
+    /// #[repr(C)]
+    /// struct Pair(u64, u64);
+    ///
+    /// const _: () = AtomicDouble::<Pair>::assert_layout();
+    /// ```
+    ///
+    /// See the [crate documentation](crate#layout) for the rest of what the
+    /// crate assumes about `T`, which can't be checked at compile time.
+    #[inline]
+    pub const fn assert_layout() {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::assert_layout` requires a type of exactly 16 bytes"
+        );
+    }
+
+    /// An atomic fence, the same as the free function [`fence`].
+    ///
+    /// A fence lets several relaxed operations share one barrier, or puts the
+    /// barrier only on the path that needs it. A `Relaxed` load followed by an
+    /// `Acquire` fence synchronizes with a `Release` store it read from, like
+    /// an `Acquire` load does, so the reads after the fence see everything
+    /// written before that store:
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::{Acquire, Relaxed, Release};
+    /// use std::sync::atomic::AtomicUsize;
+    /// use std::sync::Arc;
+    /// use std::thread;
+    ///
+    /// let data = Arc::new(AtomicUsize::new(0));
+    /// let flag = Arc::new(AtomicDouble::new((0u64, 0u64)));
+    /// let (d, f) = (data.clone(), flag.clone());
+    /// thread::spawn(move || {
+    ///     d.store(42, Relaxed);
+    ///     f.store((1, 0), Release);
+    /// });
+    /// // Poll cheaply, and pay for the barrier once the flag is seen.
+    /// while flag.load(Relaxed).0 == 0 {}
+    /// AtomicDouble::<(u64, u64)>::fence(Acquire);
+    /// assert_eq!(data.load(Relaxed), 42);
+    /// ```
+    ///
+    /// Without the value having come from a `Release` store (or a store
+    /// followed by a `Release` fence on the other side), a fence orders
+    /// nothing. [`compiler_fence`] only stops the compiler from reordering and
+    /// emits no instruction, which is enough against a signal handler on the
+    /// same thread but not against other threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Relaxed`.
+    #[inline]
+    pub fn fence(order: Ordering) {
+        core::sync::atomic::fence(order)
+    }
+
+    /// Returns the number of locks used by the fallback implementation.
+    ///
+    /// Atomics that are not lock-free are guarded by one of these locks, chosen
+    /// by hashing their address, so unrelated atomics rarely contend.
+    #[cfg(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback"
+    ))]
+    #[inline]
+    pub const fn fallback_shard_count() -> usize {
+        fallback::SHARD_COUNT
+    }
+
+    /// Returns a raw pointer to the underlying data.
+    ///
+    /// The pointer is always aligned to 16 bytes, so it can be handed to code
+    /// issuing its own 128-bit atomic instructions. Any access through it that
+    /// can race with this `AtomicDouble` must itself be atomic and use the same
+    /// instructions as this crate (`lock cmpxchg16b` on x86_64, for example);
+    /// when the value is not lock-free, concurrent access through the pointer
+    /// is not synchronized with the fallback lock at all.
+    #[inline]
+    pub const fn as_ptr(&self) -> *const T {
+        self.v.get()
+    }
+
+    /// Returns a mutable raw pointer to the underlying data.
+    ///
+    /// See `as_ptr` for the alignment guarantee and the rules for concurrent
+    /// access through the pointer.
+    #[inline]
+    pub const fn as_mut_ptr(&self) -> *mut T {
+        self.v.get()
+    }
+
+    /// Returns a view of the value as two `AtomicU64`, the first and last
+    /// eight bytes in memory order.
+    ///
+    /// On a little-endian target the first one holds the low word. This is for
+    /// interop with code that works on the halves, and for cheap loads of one
+    /// half, such as a relaxed read of just a counter.
+    ///
+    /// The two halves are independent atomics: an operation on one of them is
+    /// not atomic with respect to the other half, and 64-bit operations that
+    /// race with 128-bit ones on the same `AtomicDouble` are not covered by
+    /// the Rust memory model. When the value is not lock-free, the halves are
+    /// not synchronized with the fallback lock either. Mixing both views is
+    /// only sound when the accesses can't race, for example when each phase of
+    /// an algorithm uses one view and the phases are ordered by other means.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::{Relaxed, SeqCst};
+    ///
+    /// let a = AtomicDouble::new([1u64, 2u64]);
+    /// let [first, last] = a.as_u64_pair();
+    /// assert_eq!((first.load(Relaxed), last.load(Relaxed)), (1, 2));
+    /// last.store(3, SeqCst);
+    /// assert_eq!(a.load(SeqCst), [1, 3]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of::<T>()` is not 16.
+    #[cfg(target_has_atomic = "64")]
+    #[inline]
+    pub fn as_u64_pair(&self) -> &[core::sync::atomic::AtomicU64; 2] {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::as_u64_pair` requires a type of exactly 16 bytes"
+        );
+        // The value is 16 bytes at a 16-byte aligned address, and `AtomicU64`
+        // has the size and alignment of a `u64`, so both halves are in bounds
+        // and aligned. The `UnsafeCell` allows the shared mutation.
+        unsafe { &*(self.v.get() as *const [core::sync::atomic::AtomicU64; 2]) }
+    }
+
+    /// Returns a `Debug` view of the `AtomicDouble` that prints only its type
+    /// and address, without loading the value.
+    ///
+    /// The `Debug` impl of `AtomicDouble` itself performs a `SeqCst` load,
+    /// which is a full barrier and, on x86_64, a locked write cycle. This is
+    /// for logging where that is unwanted, such as in a signal handler or a
+    /// tight loop. It works for any `T`, even one that isn't `Debug`.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    ///
+    /// let a = AtomicDouble::new(5u64);
+    /// let s = format!("{:?}", a.debug_address());
+    /// assert_eq!(s, format!("AtomicDouble {{ type: \"u64\", addr: {:p} }}", a.as_ptr()));
+    /// ```
+    #[inline]
+    pub fn debug_address(&self) -> impl fmt::Debug {
+        DebugAddress(self.as_ptr())
+    }
 }
 
 impl<T: Copy> AtomicDouble<T> {
+    /// Creates an array of `N` atomics, each holding `v`.
+    ///
+    /// `AtomicDouble` isn't `Copy`, so `[AtomicDouble::new(v); N]` doesn't
+    /// compile unless `v` is a constant. Every element of the array is 16-byte
+    /// aligned, as for any `AtomicDouble`.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// let slots: [AtomicDouble<(u64, u64)>; 8] = AtomicDouble::fill_array((0, 0));
+    /// slots[3].store((1, 2), SeqCst);
+    /// assert_eq!(slots[3].load(SeqCst), (1, 2));
+    /// ```
+    #[inline]
+    pub fn fill_array<const N: usize>(v: T) -> [AtomicDouble<T>; N] {
+        core::array::from_fn(|_| AtomicDouble::new(v))
+    }
+
     /// Returns a mutable reference to the underlying type.
     ///
     /// This is safe because the mutable reference guarantees that no other threads are
@@ -85,6 +803,74 @@ impl<T: Copy> AtomicDouble<T> {
         unsafe { &mut *self.v.get() }
     }
 
+    /// Runs `f` with plain, non-atomic access to the value.
+    ///
+    /// This is `f(self.get_mut())`: the `&mut self` borrow guarantees that no
+    /// other thread can access the value while `f` runs, so a burst of updates
+    /// can be made without any atomic operations, and becomes visible to other
+    /// threads through whatever later shares the `AtomicDouble` with them.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// let mut a = AtomicDouble::new((1u64, 2u64));
+    /// a.with_mut(|v| {
+    ///     v.0 += 10;
+    ///     v.1 += 20;
+    /// });
+    /// assert_eq!(a.load(SeqCst), (11, 22));
+    /// ```
+    #[inline]
+    pub fn with_mut<R>(&mut self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(self.get_mut())
+    }
+
+    /// Get non-atomic access to a `&mut [AtomicDouble<T>]` slice.
+    ///
+    /// This is safe because the mutable reference guarantees that no other
+    /// threads are concurrently accessing the atomic data. The elements of the
+    /// two slices have to be laid out the same way, so `T` must be exactly 16
+    /// bytes large; a smaller `T` is padded inside each `AtomicDouble`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of::<T>()` is not 16.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub fn get_mut_slice(this: &mut [AtomicDouble<T>]) -> &mut [T] {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::get_mut_slice` requires a type of exactly 16 bytes"
+        );
+        // With `T` 16 bytes large, `AtomicDouble<T>` has the same size, and
+        // only a larger alignment.
+        unsafe { core::slice::from_raw_parts_mut(this.as_mut_ptr() as *mut T, this.len()) }
+    }
+
+    /// Takes the value out, leaving `T::default()` in its place.
+    ///
+    /// This is `mem::take(self.get_mut())`, the non-atomic counterpart of
+    /// `take` for when the `&mut self` borrow already rules out other threads.
+    /// It is meant for handles that own something, such as a pointer from
+    /// `Box::into_raw`: once taken, the atomic holds the default handle and
+    /// the caller is the only owner, so the pointee is released exactly once.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    ///
+    /// let mut a = AtomicDouble::new((7u64, 1u64));
+    /// assert_eq!(a.take_inner(), (7, 1));
+    /// assert_eq!(a.into_inner(), (0, 0));
+    /// ```
+    #[inline]
+    pub fn take_inner(&mut self) -> T
+    where
+        T: Default,
+    {
+        core::mem::take(self.get_mut())
+    }
+
     /// Consumes the atomic and returns the contained value.
     ///
     /// This is safe because passing `self` by value guarantees that no other threads are
@@ -94,17 +880,133 @@ impl<T: Copy> AtomicDouble<T> {
         self.v.into_inner()
     }
 
+    /// Allocates a new `AtomicDouble` holding `value` on the heap.
+    ///
+    /// `Box` allocates with the alignment of the type it holds, so the boxed
+    /// atomic is 16-byte aligned like any other. This is meant for linked
+    /// structures whose nodes are allocated one by one.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// let head = AtomicDouble::into_box((0usize, 0usize));
+    /// assert_eq!(head.compare_exchange((0, 0), (1, 1), SeqCst, SeqCst), Ok((0, 0)));
+    /// assert_eq!(AtomicDouble::from_box(head), (1, 1));
+    /// ```
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn into_box(value: T) -> alloc::boxed::Box<AtomicDouble<T>> {
+        alloc::boxed::Box::new(AtomicDouble::new(value))
+    }
+
+    /// Consumes a boxed `AtomicDouble` and returns the contained value.
+    ///
+    /// Like `into_inner`, owning the box guarantees that no other threads are
+    /// concurrently accessing the atomic data.
+    // Taking the box is the point: it is the counterpart of `into_box`.
+    #[allow(clippy::boxed_local)]
+    #[cfg(feature = "alloc")]
+    #[inline]
+    pub fn from_box(this: alloc::boxed::Box<AtomicDouble<T>>) -> T {
+        this.into_inner()
+    }
+
     /// Loads a value from the `AtomicDouble`.
     ///
     /// `load` takes an `Ordering` argument which describes the memory ordering
     /// of this operation.
     ///
+    /// On x86_64 the load is performed with a locked `cmpxchg16b`, which always
+    /// issues a write cycle to the target even though the stored value is never
+    /// changed. An `AtomicDouble` must therefore not be placed in read-only memory.
+    /// The fallback implementation only reads the value.
+    ///
+    /// A `Relaxed` load on an Intel or AMD processor that supports AVX is
+    /// instead a plain aligned `movdqa`, with no lock prefix and no barrier, as
+    /// both vendors guarantee aligned 16-byte SSE loads to be atomic on those
+    /// processors. Other orderings, and other x86_64 processors, use
+    /// `cmpxchg16b`. On aarch64 every load is an exclusive pair loop or a
+    /// `casp`.
+    ///
     /// # Panics
     ///
     /// Panics if `order` is `Release` or `AcqRel`.
     #[inline]
     pub fn load(&self, order: Ordering) -> T {
-        unsafe { ops::atomic_load(self.v.get(), order) }
+        self.with(|v| unsafe { ops::atomic_load(v, order) })
+    }
+
+    /// Loads a value with consume ordering.
+    ///
+    /// A consume load only orders the reads that depend on the loaded value,
+    /// such as dereferencing a pointer it contains, which is all that chasing
+    /// pointers through a lock-free structure needs. Weakly-ordered hardware
+    /// provides that ordering for free, where an acquire load needs a barrier
+    /// or a special instruction.
+    ///
+    /// Rust has no consume ordering, and neither does any compiler implement
+    /// one reliably, so this is currently an `Acquire` load. That is always at
+    /// least as strong, and code written against `load_consume` can get a
+    /// cheaper load on targets such as aarch64 later without any change.
+    /// Don't rely on it ordering reads that don't depend on the value.
+    #[inline]
+    pub fn load_consume(&self) -> T {
+        self.load(Ordering::Acquire)
+    }
+
+    /// Reads the value with a single plain load, without any write-back or
+    /// synchronization.
+    ///
+    /// **This is not an atomic operation and must never be used for
+    /// synchronization.** It is a best-effort snapshot meant for diagnostics
+    /// and telemetry, where a slightly stale or even torn value is acceptable.
+    /// On x86_64 it is one aligned 16-byte `movdqa` load, which is only
+    /// guaranteed tear-free on some processors (see `load`), and elsewhere a
+    /// volatile read of `T`. It establishes no happens-before relationship
+    /// with any store.
+    ///
+    /// # Safety
+    ///
+    /// A store racing with this read may leave it with a mix of the bytes of
+    /// the old and the new value. The caller must make sure that either no
+    /// such store can happen, or that any such mix is still a valid `T` (as it
+    /// is for plain integers, but not for pointers that are dereferenced or
+    /// types with invalid bit patterns).
+    #[inline]
+    pub unsafe fn load_unsync(&self) -> T {
+        self.with(|v| ops::load_unsync(v))
+    }
+
+    /// Loads the value and returns its raw bits as a `u128`, whatever `T` is.
+    ///
+    /// The bytes of the value are read as a native-endian `u128`; a `T`
+    /// smaller than 16 bytes is zero-extended, so its bytes end up in the same
+    /// place as after `ptr::copy` into a zeroed `u128`. For an
+    /// `AtomicDouble<u128>` this is simply `load`.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// let a = AtomicDouble::new(*b"0123456789abcdef");
+    /// let bits = unsafe { a.to_u128(SeqCst) };
+    /// assert_eq!(bits, u128::from_ne_bytes(*b"0123456789abcdef"));
+    /// ```
+    ///
+    /// # Safety
+    ///
+    /// Every byte of `T` must be initialized, which rules out types with
+    /// padding, such as `(u8, u64)`, or with `MaybeUninit` fields. A `u128`,
+    /// an array of integers, or a `#[repr(C)]` struct of integers without gaps
+    /// is fine.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub unsafe fn to_u128(&self, order: Ordering) -> u128 {
+        ops::to_u128(&self.load(order))
     }
 
     /// Stores a value into the `AtomicDouble`.
@@ -117,9 +1019,85 @@ impl<T: Copy> AtomicDouble<T> {
     /// Panics if `order` is `Acquire` or `AcqRel`.
     #[inline]
     pub fn store(&self, val: T, order: Ordering) {
-        unsafe {
-            ops::atomic_store(self.v.get(), val, order);
-        }
+        self.with(|v| unsafe { ops::atomic_store(v, val, order) })
+    }
+
+    /// Stores a value into the `AtomicDouble`, giving up after `max_attempts`
+    /// failed exchanges instead of spinning until it succeeds.
+    ///
+    /// Where there is no plain 128-bit store, `store` is a compare-exchange
+    /// loop, which under pathological contention has no bound on how long it
+    /// spins. This makes at most `max_attempts` exchanges (and always at least
+    /// one), so that real-time code can apply its own deadline. On failure the
+    /// value observed by the last exchange is returned in `Err`, and nothing
+    /// was stored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn try_store_bounded(&self, val: T, order: Ordering, max_attempts: usize) -> Result<(), T> {
+        self.with(|v| unsafe { ops::atomic_store_bounded(v, val, order, max_attempts) })
+    }
+
+    /// Stores a value into the `AtomicDouble` with a single write, for
+    /// protocols where only one thread ever writes it.
+    ///
+    /// `store` retries its exchange until no other write got in between,
+    /// which a single writer never needs to. This writes once: for a 16-byte
+    /// `T`, with one aligned `movdqa` on the x86_64 processors where that is
+    /// atomic (as for a relaxed `load`) unless `order` is `SeqCst`, and
+    /// otherwise with one exchange against the value a load found, which
+    /// keeps the padding past a smaller `T` as it was. The fallbacks store
+    /// under their lock as `store` does. Readers see either the old value or
+    /// `val`, never a mix of the two.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may write to the `AtomicDouble` while this runs, with
+    /// this or any other method. A concurrent write can be lost, or overwrite
+    /// `val`; debug builds panic where they notice one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub unsafe fn store_single_writer(&self, val: T, order: Ordering) {
+        self.with(|v| ops::atomic_store_single_writer(v, val, order))
+    }
+
+    /// Stores a value into the `AtomicDouble`, returning the previous value.
+    ///
+    /// `swap` takes an `Ordering` argument which describes the memory ordering
+    /// of this operation.
+    #[inline]
+    pub fn swap(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_swap(v, val, order) })
+    }
+
+    /// Replaces the value in the `AtomicDouble` with `val`, returning the
+    /// previous value.
+    ///
+    /// This is the same operation as `swap`, named after `Cell::replace`. The
+    /// `Ordering` applies to the whole read-modify-write: the read of the old
+    /// value is `Acquire` for `Acquire`, `AcqRel` and `SeqCst`, and the write
+    /// of `val` is `Release` for `Release`, `AcqRel` and `SeqCst`.
+    #[inline]
+    pub fn replace(&self, val: T, order: Ordering) -> T {
+        self.swap(val, order)
+    }
+
+    /// Takes the value out of the `AtomicDouble`, leaving `T::default()` in its
+    /// place.
+    ///
+    /// This is `replace(T::default(), order)`, named after `Cell::take`, and
+    /// `order` has the same meaning as for `replace`.
+    #[inline]
+    pub fn take(&self, order: Ordering) -> T
+    where
+        T: Default,
+    {
+        self.replace(T::default(), order)
     }
 
     /// Stores a value into the `AtomicDouble` if the current value is the same as the
@@ -132,8 +1110,32 @@ impl<T: Copy> AtomicDouble<T> {
     /// `compare_exchange` takes two `Ordering` arguments to describe the memory
     /// ordering of this operation. The first describes the required ordering if
     /// the operation succeeds while the second describes the required ordering
-    /// when the operation fails. The failure ordering can't be `Acquire` or
+    /// when the operation fails. The failure ordering can't be `Release` or
     /// `AcqRel` and must be equivalent or weaker than the success ordering.
+    ///
+    /// A failed exchange is ordered at least as strongly as `failure` asks,
+    /// and may be ordered more strongly. The lock-free paths run a single
+    /// instruction with a single ordering: a lock-prefixed `cmpxchg16b` on
+    /// x86_64 is sequentially consistent whatever the orderings, and the
+    /// aarch64 and riscv64 instructions take `success`, strengthened to
+    /// acquire where `failure` is `Acquire`. So a failed `(SeqCst, Relaxed)`
+    /// exchange is as strong as a `SeqCst` one, and its read synchronizes
+    /// like an acquire load. The fallbacks take their locks with acquire
+    /// ordering either way. No fence can weaken an instruction's ordering,
+    /// so the failure ordering is honored by this strengthening rather than
+    /// exactly; code that is correct under the requested orderings stays
+    /// correct, but a weaker `failure` doesn't make a failed exchange any
+    /// cheaper.
+    ///
+    /// The exchange is performed even when `new` is the same as `current` and
+    /// `success` has no `Release` component. Like every read-modify-write, it
+    /// reads the latest value in the modification order, so `Ok` means that
+    /// value was `current`; a load could return an older one instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
     #[inline]
     pub fn compare_exchange(
         &self,
@@ -142,18 +1144,926 @@ impl<T: Copy> AtomicDouble<T> {
         success: Ordering,
         failure: Ordering,
     ) -> Result<T, T> {
-        unsafe { ops::atomic_compare_exchange(self.v.get(), current, new, success, failure) }
+        self.with(|v| unsafe { ops::atomic_compare_exchange(v, current, new, success, failure) })
+    }
+
+    /// Stores a value into the `AtomicDouble` if the current value matches
+    /// `current` on every bit outside of `mask`.
+    ///
+    /// `mask` covers the bits of `T` read as a native-endian `u128`, the same
+    /// interpretation `fetch_and` and the other bitwise operations use. Bits
+    /// set in `mask` are left out of the comparison: whatever the current value
+    /// holds there, the exchange succeeds as long as all other bits are equal
+    /// to those of `current`. On success `new` is written in full, masked bits
+    /// included, and the previous value is returned in `Ok`. On failure the
+    /// current value is returned in `Err`. A `mask` of zero makes this the same
+    /// as `compare_exchange`.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// // The low 4 bits are scratch space and don't take part in the comparison.
+    /// let a = AtomicDouble::new(0x1005u128);
+    /// assert_eq!(a.compare_exchange_masked(0x1000, 0x2000, 0xf, SeqCst, SeqCst), Ok(0x1005));
+    /// assert_eq!(a.load(SeqCst), 0x2000);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_masked(
+        &self,
+        current: T,
+        new: T,
+        mask: u128,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.with(|v| unsafe {
+            ops::atomic_compare_exchange_masked(v, current, new, mask, success, failure)
+        })
+    }
+
+    /// Stores a value into the `AtomicDouble` if the current value is the same as the
+    /// `current` value, returning whether the new value was written.
+    ///
+    /// This is `compare_exchange(current, new, success, failure).is_ok()`, for
+    /// when the previous value isn't needed.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::{Acquire, Relaxed};
+    ///
+    /// let slot = AtomicDouble::new((0u64, 0u64));
+    /// assert!(slot.compare_exchange_bool((0, 0), (1, 7), Acquire, Relaxed));
+    /// // The slot is already claimed, so a second attempt fails.
+    /// assert!(!slot.compare_exchange_bool((0, 0), (2, 9), Acquire, Relaxed));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[inline]
+    pub fn compare_exchange_bool(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> bool {
+        self.compare_exchange(current, new, success, failure)
+            .is_ok()
+    }
+
+    /// Stores a value into the `AtomicDouble` if the current value is the same as the
+    /// `current` value.
+    ///
+    /// Unlike `compare_exchange`, this function is allowed to spuriously fail
+    /// even when the comparison succeeds, which can result in more efficient
+    /// code on some platforms. Callers must therefore be prepared to handle an
+    /// `Err` whose value is equal to `current`, typically by retrying in a loop.
+    ///
+    /// `compare_exchange_weak` takes two `Ordering` arguments to describe the memory
+    /// ordering of this operation. The first describes the required ordering if
+    /// the operation succeeds while the second describes the required ordering
+    /// when the operation fails. The failure ordering can't be `Release` or
+    /// `AcqRel` and must be equivalent or weaker than the success ordering.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.with(|v| unsafe {
+            ops::atomic_compare_exchange_weak(v, current, new, success, failure)
+        })
+    }
+
+    /// Stores `new` if the current value is the same as `current`, like
+    /// `compare_exchange`, but fails with a `CasError` that tells what was
+    /// found.
+    ///
+    /// A strong exchange only fails when it finds a different value, so
+    /// `is_spurious` is always `false` here; it matters for
+    /// `compare_exchange_weak_detailed`.
+    ///
+    /// ```
+    /// use atomicdouble::{AtomicDouble, CasError};
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// let a = AtomicDouble::new(5u128);
+    /// let err = a.compare_exchange_detailed(4, 6, SeqCst, SeqCst).unwrap_err();
+    /// assert_eq!(err, CasError { observed: 5, is_spurious: false });
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_detailed(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, CasError<T>> {
+        self.compare_exchange(current, new, success, failure)
+            .map_err(|observed| CasError {
+                observed,
+                is_spurious: ops::same_value(&observed, &current),
+            })
+    }
+
+    /// Stores `new` if the current value is the same as `current`, like
+    /// `compare_exchange_weak`, but fails with a `CasError` that tells a
+    /// spurious failure from a different value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_weak_detailed(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, CasError<T>> {
+        self.compare_exchange_weak(current, new, success, failure)
+            .map_err(|observed| CasError {
+                observed,
+                is_spurious: ops::same_value(&observed, &current),
+            })
+    }
+
+    /// Stores `new` if the current value is the same as `current`, retrying
+    /// spurious failures of `compare_exchange_weak`.
+    ///
+    /// This builds on `compare_exchange_weak` and retries only while the value
+    /// it observed is still the same as `current`, compared bytewise as for
+    /// `compare_exchange`. It gives up and returns the observed value in `Err`
+    /// the first time that value differs from `current`: the loop never
+    /// retries with a new `current`, unlike `fetch_update`.
+    ///
+    /// The orderings have the same meaning as for `compare_exchange_weak`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_loop(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        loop {
+            match self.compare_exchange_weak(current, new, success, failure) {
+                Err(prev) if ops::same_value(&prev, &current) => {}
+                res => return res,
+            }
+        }
+    }
+
+    /// Makes a single attempt at storing `new` if the current value is the same
+    /// as `current`.
+    ///
+    /// This never retries on contention: on LL/SC targets such as aarch64 it
+    /// is one `ldxp`/`stxp` pair, and on x86_64 one `cmpxchg16b`. An `Err` may
+    /// therefore be spurious, holding a value equal to `current`, for instance
+    /// when the exclusive store was interrupted. This leaves the retry and
+    /// backoff strategy to the caller. On x86_64 and in the fallback
+    /// implementation the attempt never fails spuriously.
+    ///
+    /// The bytes past the end of a `T` smaller than 16 bytes are not part of
+    /// the value. When only they differ from the zeros `current` has there,
+    /// the exchange is made once more with the bytes it found, rather than
+    /// reported as a failure that retrying with the returned value could
+    /// never get past.
+    ///
+    /// The orderings have the same meaning as for `compare_exchange_weak`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn try_compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        // The weak exchange is already a single attempt on every target, apart
+        // from the retry that matches the padding it found.
+        self.compare_exchange_weak(current, new, success, failure)
+    }
+
+    /// Loads a value with `SeqCst` ordering.
+    ///
+    /// `SeqCst` is the safe default: it is never too weak, which makes it a
+    /// good choice while prototyping. It can be more expensive than needed,
+    /// so code that is past that stage should use `load` with the weakest
+    /// ordering that is correct.
+    #[inline]
+    pub fn load_seqcst(&self) -> T {
+        self.load(Ordering::SeqCst)
+    }
+
+    /// Stores a value with `SeqCst` ordering.
+    ///
+    /// Like `load_seqcst`, this is the safe default; see `store` for choosing
+    /// a cheaper ordering.
+    #[inline]
+    pub fn store_seqcst(&self, val: T) {
+        self.store(val, Ordering::SeqCst)
+    }
+
+    /// Stores a value with `SeqCst` ordering, returning the previous value.
+    ///
+    /// Like `load_seqcst`, this is the safe default; see `swap` for choosing
+    /// a cheaper ordering.
+    #[inline]
+    pub fn swap_seqcst(&self, val: T) -> T {
+        self.swap(val, Ordering::SeqCst)
+    }
+
+    /// Stores `new` if the current value is the same as `current`, with
+    /// `SeqCst` ordering on both success and failure.
+    ///
+    /// Like `load_seqcst`, this is the safe default; see `compare_exchange`
+    /// for choosing cheaper orderings.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn cas_seqcst(&self, current: T, new: T) -> Result<T, T> {
+        self.compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+    }
+
+    /// Fetches the value, and applies a function to it that returns an optional
+    /// new value. Returns a `Result` of `Ok(previous_value)` if the function returned `Some(_)`, else
+    /// `Err(previous_value)`.
+    ///
+    /// Note: This may call the function multiple times if the value has been changed from other threads in
+    /// the meantime, as long as the function returns `Some(_)`, but the function will have been applied
+    /// only once to the stored value.
+    ///
+    /// `fetch_update` takes two `Ordering` arguments to describe the memory ordering of this operation.
+    /// The first describes the required ordering for when the operation finally succeeds while the second
+    /// describes the required ordering for loads. These correspond to the success and failure orderings of
+    /// `compare_exchange` respectively.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fetch_order` is `Release`, `AcqRel` or stronger than
+    /// `set_order`, even if `f` never returns `Some(_)`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<T, T>
+    where
+        F: FnMut(T) -> Option<T>,
+    {
+        // Checked up front, so that a bad pair fails whatever `f` returns.
+        ops::check_compare_exchange_ordering(set_order, fetch_order);
+        let mut prev = self.load(fetch_order);
+        while let Some(next) = f(prev) {
+            match self.compare_exchange_weak(prev, next, set_order, fetch_order) {
+                x @ Ok(_) => return x,
+                Err(next_prev) => prev = next_prev,
+            }
+        }
+        Err(prev)
+    }
+
+    /// Stores `new` if `pred` returns `true` for the current value.
+    ///
+    /// The current value is loaded and passed to `pred`. If it returns `false`,
+    /// nothing is stored and that value is returned in `Err`. Otherwise `new`
+    /// is exchanged in, and the previous value is returned in `Ok`. If another
+    /// thread changes the value in between, `pred` is called again with the
+    /// new one, so it may run several times; a spurious failure of the exchange
+    /// is retried without calling it again.
+    ///
+    /// `order` is the ordering of the exchange, like for `swap`. The loads use
+    /// the strongest failure ordering it allows: `Acquire` for `Acquire` and
+    /// `AcqRel`, `SeqCst` for `SeqCst`, and `Relaxed` otherwise.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::AcqRel;
+    ///
+    /// // A state machine that only ever moves forward.
+    /// let state = AtomicDouble::new((2u64, 0u64));
+    /// assert_eq!(state.store_if((3, 0), |(s, _)| s < 3, AcqRel), Ok((2, 0)));
+    /// assert_eq!(state.store_if((1, 0), |(s, _)| s < 1, AcqRel), Err((3, 0)));
+    /// ```
+    #[must_use = "the result indicates whether the value was stored and must be checked"]
+    #[inline]
+    pub fn store_if<P>(&self, new: T, mut pred: P, order: Ordering) -> Result<T, T>
+    where
+        P: FnMut(T) -> bool,
+    {
+        let fetch_order = ops::strongest_failure_ordering(order);
+        let mut current = self.load(fetch_order);
+        loop {
+            if !pred(current) {
+                return Err(current);
+            }
+            loop {
+                match self.compare_exchange_weak(current, new, order, fetch_order) {
+                    Ok(prev) => return Ok(prev),
+                    Err(actual) if ops::same_value(&actual, &current) => {}
+                    Err(actual) => {
+                        current = actual;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Replaces the value with `f` applied to it, and returns the previous
+    /// and the new value.
+    ///
+    /// This is `fetch_update` for updates that always store: `f` can't
+    /// decline, so there is no `Result`, and the value it computed comes back
+    /// along with the one it replaced. As with `store_if`, `f` may run several
+    /// times if other threads change the value in between, and a spurious
+    /// failure of the exchange is retried without calling it again.
+    ///
+    /// `order` is the ordering of the exchange, like for `swap`, and the loads
+    /// use the strongest failure ordering it allows, as for `store_if`.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::AcqRel;
+    ///
+    /// let a = AtomicDouble::new(21u128);
+    /// assert_eq!(a.map(AcqRel, |v| v * 2), (21, 42));
+    /// ```
+    #[inline]
+    pub fn map<F>(&self, order: Ordering, mut f: F) -> (T, T)
+    where
+        F: FnMut(T) -> T,
+    {
+        let fetch_order = ops::strongest_failure_ordering(order);
+        let mut current = self.load(fetch_order);
+        loop {
+            let new = f(current);
+            loop {
+                match self.compare_exchange_weak(current, new, order, fetch_order) {
+                    Ok(prev) => return (prev, new),
+                    Err(actual) if ops::same_value(&actual, &current) => {}
+                    Err(actual) => {
+                        current = actual;
+                        break;
+                    }
+                }
+            }
+        }
     }
 
     /// Add to the current value, returning the previous value.
+    ///
+    /// The value is added as one 128-bit unsigned integer, with wrapping. Its
+    /// bytes are read in native byte order, so which end of `T` a carry runs
+    /// towards depends on the target's endianness.
     #[inline]
     pub fn fetch_add(&self, val: T, order: Ordering) -> T {
-        unsafe { ops::atomic_add(self.v.get(), val, order) }
+        self.with(|v| unsafe { ops::atomic_add(v, val, order) })
+    }
+
+    /// Add to the current value, returning the previous value, with separate
+    /// orderings for observing the value and for updating it.
+    ///
+    /// `fetch_add` is a compare-exchange loop with a single ordering. Here the
+    /// value is first loaded with `load_order`, which also applies to every
+    /// failed exchange of the loop, while the exchange that stores the sum
+    /// uses `rmw_order`. The constraints are those of the failure and success
+    /// orderings of `compare_exchange`: `load_order` can't be `Release` or
+    /// `AcqRel`, and can't be stronger than `rmw_order`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `load_order` is `Release`, `AcqRel` or stronger than
+    /// `rmw_order`.
+    #[inline]
+    pub fn fetch_add_explicit(&self, val: T, load_order: Ordering, rmw_order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_add_explicit(v, val, load_order, rmw_order) })
+    }
+
+    /// Add to the current value, giving up after `max_attempts` failed
+    /// exchanges.
+    ///
+    /// This is `fetch_add` with the bound of `try_store_bounded`: on success
+    /// the previous value is returned in `Ok`, and once `max_attempts`
+    /// exchanges have failed, the value observed by the last one is returned
+    /// in `Err` without anything being added.
+    #[inline]
+    pub fn try_fetch_add_bounded(
+        &self,
+        val: T,
+        order: Ordering,
+        max_attempts: usize,
+    ) -> Result<T, T> {
+        self.with(|v| unsafe { ops::atomic_add_bounded(v, val, order, max_attempts) })
+    }
+
+    /// Add to the current value, returning the new value.
+    ///
+    /// This performs the same operation as `fetch_add`, but returns the result
+    /// of the addition rather than the value that was replaced.
+    #[inline]
+    pub fn add_fetch(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_add_fetch(v, val, order) })
+    }
+
+    /// Subtract from the current value, returning the new value.
+    ///
+    /// This performs the same operation as `fetch_sub`, but returns the result
+    /// of the subtraction rather than the value that was replaced.
+    #[inline]
+    pub fn sub_fetch(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_sub_fetch(v, val, order) })
+    }
+
+    /// Add to the current value, returning the previous value, or `None` if
+    /// the addition overflows.
+    ///
+    /// Like `fetch_add`, the value is added as one unsigned integer, of the
+    /// width of `T`: for a `T` smaller than 16 bytes, a carry out of its bytes
+    /// is an overflow. On overflow the stored value is left unchanged.
+    #[inline]
+    pub fn checked_fetch_add(&self, val: T, order: Ordering) -> Option<T> {
+        self.with(|v| unsafe { ops::atomic_checked_add(v, val, order) })
+    }
+
+    /// Subtract from the current value, returning the previous value, or `None`
+    /// if the subtraction overflows.
+    ///
+    /// Like `fetch_sub`, the value is subtracted as one unsigned integer, of
+    /// the width of `T`. On overflow the stored value is left unchanged.
+    #[inline]
+    pub fn checked_fetch_sub(&self, val: T, order: Ordering) -> Option<T> {
+        self.with(|v| unsafe { ops::atomic_checked_sub(v, val, order) })
+    }
+
+    /// Add to the current value, saturating at the maximum instead of wrapping,
+    /// and return the previous value.
+    ///
+    /// Saturation applies to the whole unsigned integer interpretation of the
+    /// value, of the width of `T`, not to its fields: an overflow sets every
+    /// bit of the value.
+    #[inline]
+    pub fn saturating_fetch_add(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_saturating_add(v, val, order) })
+    }
+
+    /// Subtract from the current value, saturating at zero instead of wrapping,
+    /// and return the previous value.
+    ///
+    /// Saturation applies to the whole unsigned integer interpretation of the
+    /// value, of the width of `T`, not to its fields: an overflow clears every
+    /// bit of the value.
+    #[inline]
+    pub fn saturating_fetch_sub(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_saturating_sub(v, val, order) })
+    }
+
+    /// Add to the current value lane-wise, returning the previous value.
+    ///
+    /// Unlike `fetch_add`, the 128 bits are split into two `u64` lanes which
+    /// are added independently with wrapping, so an overflow of one lane does
+    /// not carry into the other. This suits two independent 64-bit counters
+    /// packed into one `T`.
+    #[inline]
+    pub fn fetch_add_u64x2(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_add_u64x2(v, val, order) })
+    }
+
+    /// Add to the current value as two `f64` lanes, returning the previous
+    /// lanes.
+    ///
+    /// The 16 bytes of `T` are read as a `[f64; 2]`, `val` is added to them
+    /// lane-wise, and the resulting bit pattern is stored back with a
+    /// compare-exchange loop. Float addition is not associative, so when
+    /// several threads accumulate into the same value the result depends on
+    /// the order in which their additions land, which is nondeterministic.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of::<T>()` is not 16.
+    #[inline]
+    pub fn fetch_add_f64x2(&self, val: [f64; 2], order: Ordering) -> [f64; 2] {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::fetch_add_f64x2` requires a type of exactly 16 bytes"
+        );
+        self.with(|v| unsafe { ops::atomic_add_f64x2(v, val, order) })
+    }
+
+    /// Subtract from the current value as two `f64` lanes, returning the
+    /// previous lanes.
+    ///
+    /// See `fetch_add_f64x2` for how the value is interpreted.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of::<T>()` is not 16.
+    #[inline]
+    pub fn fetch_sub_f64x2(&self, val: [f64; 2], order: Ordering) -> [f64; 2] {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::fetch_sub_f64x2` requires a type of exactly 16 bytes"
+        );
+        self.with(|v| unsafe { ops::atomic_sub_f64x2(v, val, order) })
     }
 
     /// Subtract from the current value, returning the previous value.
     #[inline]
     pub fn fetch_sub(&self, val: T, order: Ordering) -> T {
-        unsafe { ops::atomic_sub(self.v.get(), val, order) }
+        self.with(|v| unsafe { ops::atomic_sub(v, val, order) })
+    }
+
+    /// Bitwise "and" with the current value, returning the previous value.
+    ///
+    /// The operation is applied over the whole 128-bit representation of `T`.
+    #[inline]
+    pub fn fetch_and(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_and(v, val, order) })
+    }
+
+    /// Bitwise "or" with the current value, returning the previous value.
+    ///
+    /// The operation is applied over the whole 128-bit representation of `T`.
+    #[inline]
+    pub fn fetch_or(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_or(v, val, order) })
+    }
+
+    /// Sets bit `bit` of the value, returning whether it was already set.
+    ///
+    /// This is a `fetch_or` with `1 << bit`, so bits are numbered over the
+    /// 128-bit integer interpretation of `T`, as for the other bitwise
+    /// operations. For a `T` smaller than 16 bytes the bits past its size are
+    /// padding and are never set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is 128 or more.
+    #[inline]
+    pub fn bit_set(&self, bit: u32, order: Ordering) -> bool {
+        assert!(bit < 128, "bit index out of range: {} >= 128", bit);
+        self.with(|v| unsafe { ops::atomic_bit_set(v, bit, order) })
     }
+
+    /// Clears bit `bit` of the value, returning whether it was set.
+    ///
+    /// This is a `fetch_and` with `!(1 << bit)`, with bits numbered as for
+    /// `bit_set`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `bit` is 128 or more.
+    #[inline]
+    pub fn bit_clear(&self, bit: u32, order: Ordering) -> bool {
+        assert!(bit < 128, "bit index out of range: {} >= 128", bit);
+        self.with(|v| unsafe { ops::atomic_bit_clear(v, bit, order) })
+    }
+
+    /// Rotates the value left by `n` bits, returning the previous value.
+    ///
+    /// The value is rotated as one 128-bit integer, with the bits shifted out
+    /// at the top coming back in at the bottom, like `u128::rotate_left`. `n`
+    /// is taken modulo 128, so any `n` is accepted and a multiple of 128 leaves
+    /// the value unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of::<T>()` is not 16, as the padding of a smaller `T`
+    /// would be rotated into the value.
+    #[inline]
+    pub fn fetch_rotate_left(&self, n: u32, order: Ordering) -> T {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::fetch_rotate_left` requires a type of exactly 16 bytes"
+        );
+        self.with(|v| unsafe { ops::atomic_rotate_left(v, n, order) })
+    }
+
+    /// Rotates the value right by `n` bits, returning the previous value.
+    ///
+    /// This is the inverse of `fetch_rotate_left`, like `u128::rotate_right`,
+    /// and `n` is likewise taken modulo 128.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size_of::<T>()` is not 16.
+    #[inline]
+    pub fn fetch_rotate_right(&self, n: u32, order: Ordering) -> T {
+        assert!(
+            core::mem::size_of::<T>() == 16,
+            "`AtomicDouble::fetch_rotate_right` requires a type of exactly 16 bytes"
+        );
+        self.with(|v| unsafe { ops::atomic_rotate_right(v, n, order) })
+    }
+
+    /// Bitwise "xor" with the current value, returning the previous value.
+    ///
+    /// The operation is applied over the whole 128-bit representation of `T`.
+    #[inline]
+    pub fn fetch_xor(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_xor(v, val, order) })
+    }
+
+    /// Bitwise "nand" with the current value, returning the previous value.
+    ///
+    /// The operation computes `!(old & val)` bit-for-bit across every byte of
+    /// `T`, including any padding bytes inside it. For structs with several
+    /// fields the result is therefore not a field-wise nand. The bytes past
+    /// the end of a `T` smaller than 16 bytes are left as they are.
+    #[inline]
+    pub fn fetch_nand(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_nand(v, val, order) })
+    }
+
+    /// Maximum with the current value, returning the previous value.
+    ///
+    /// The whole 16 bytes are compared as a single `u128`, so a two-field
+    /// struct is compared lexicographically with the low-order field (the
+    /// first field on little-endian targets) being least significant.
+    #[inline]
+    pub fn fetch_max(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_umax(v, val, order) })
+    }
+
+    /// Minimum with the current value, returning the previous value.
+    ///
+    /// The whole 16 bytes are compared as a single `u128`, so a two-field
+    /// struct is compared lexicographically with the low-order field (the
+    /// first field on little-endian targets) being least significant.
+    #[inline]
+    pub fn fetch_min(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_umin(v, val, order) })
+    }
+
+    /// Maximum with the current value, returning the previous value and
+    /// whether the stored value changed.
+    ///
+    /// Compares like `fetch_max`. The flag is `false` when the current value
+    /// was already at least `val`, so for a high-water mark only the caller
+    /// that set a new maximum sees `true`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use atomicdouble::{AtomicDouble, Ordering};
+    ///
+    /// let high = AtomicDouble::new(5u128);
+    /// assert_eq!(high.fetch_max_changed(9, Ordering::AcqRel), (5, true));
+    /// assert_eq!(high.fetch_max_changed(7, Ordering::AcqRel), (9, false));
+    /// ```
+    #[inline]
+    pub fn fetch_max_changed(&self, val: T, order: Ordering) -> (T, bool) {
+        self.with(|v| unsafe { ops::atomic_umax_changed(v, val, order) })
+    }
+
+    /// Minimum with the current value, returning the previous value and
+    /// whether the stored value changed.
+    ///
+    /// Compares like `fetch_min`. The flag is `false` when the current value
+    /// was already at most `val`.
+    #[inline]
+    pub fn fetch_min_changed(&self, val: T, order: Ordering) -> (T, bool) {
+        self.with(|v| unsafe { ops::atomic_umin_changed(v, val, order) })
+    }
+
+    /// Maximum with the current value, returning the previous value.
+    ///
+    /// Same as `fetch_max`, but the 16 bytes are compared as a single `i128`.
+    /// Types smaller than 16 bytes are zero-extended, so they never compare
+    /// as negative.
+    #[inline]
+    pub fn fetch_max_signed(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_max(v, val, order) })
+    }
+
+    /// Minimum with the current value, returning the previous value.
+    ///
+    /// Same as `fetch_min`, but the 16 bytes are compared as a single `i128`.
+    /// Types smaller than 16 bytes are zero-extended, so they never compare
+    /// as negative.
+    #[inline]
+    pub fn fetch_min_signed(&self, val: T, order: Ordering) -> T {
+        self.with(|v| unsafe { ops::atomic_min(v, val, order) })
+    }
+}
+
+impl AtomicDouble<u128> {
+    /// Creates a new `AtomicDouble<u128>` holding the raw 128-bit word `v`.
+    ///
+    /// This is `new`, spelled out for code that passes 128-bit words around,
+    /// and the counterpart of `to_u128`, which is safe on an
+    /// `AtomicDouble<u128>`.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicU128;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// static WORD: AtomicU128 = AtomicU128::from_u128(1 << 100);
+    /// assert_eq!(unsafe { WORD.to_u128(SeqCst) }, 1 << 100);
+    /// ```
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub const fn from_u128(v: u128) -> AtomicDouble<u128> {
+        AtomicDouble::new(v)
+    }
+
+    /// Creates a new `AtomicDouble<u128>` holding the raw 128-bit word `v`.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn from_u128(v: u128) -> AtomicDouble<u128> {
+        AtomicDouble::new(v)
+    }
+
+    /// Adds a signed delta to the current value, returning the previous value.
+    ///
+    /// This is `u128::wrapping_add_signed` as one atomic operation: a negative
+    /// `delta` subtracts its magnitude, and the result wraps around at both
+    /// ends instead of saturating, so subtracting past zero continues down
+    /// from `u128::MAX`, and adding past `u128::MAX` continues up from zero.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicU128;
+    /// use atomicdouble::Ordering::SeqCst;
+    ///
+    /// let a = AtomicU128::new(10);
+    /// assert_eq!(a.fetch_add_signed(-3, SeqCst), 10);
+    /// assert_eq!(a.fetch_add_signed(-8, SeqCst), 7);
+    /// assert_eq!(a.load(SeqCst), u128::MAX);
+    /// ```
+    #[inline]
+    pub fn fetch_add_signed(&self, delta: i128, order: Ordering) -> u128 {
+        // In two's complement, adding the delta's bits with wrapping is the
+        // same as `wrapping_add_signed`.
+        self.fetch_add(delta as u128, order)
+    }
+}
+
+/// A 16-byte array, such as a UUID or a hash key, is stored exactly as is:
+/// `load_bytes` returns the bytes in the order `store_bytes` was given them,
+/// on every target, whatever its endianness. Only the arithmetic and bitwise
+/// operations read the array as a number, as a native-endian `u128`, and they
+/// write the result back the same way, so an operation that leaves the number
+/// unchanged leaves the bytes unchanged too.
+impl AtomicDouble<[u8; 16]> {
+    /// Loads the bytes.
+    ///
+    /// This is `load`, spelled out for byte arrays.
+    ///
+    /// ```
+    /// use atomicdouble::AtomicDouble;
+    /// use atomicdouble::Ordering::{Acquire, Release};
+    ///
+    /// let key = AtomicDouble::new([0u8; 16]);
+    /// key.store_bytes(*b"0123456789abcdef", Release);
+    /// assert_eq!(&key.load_bytes(Acquire), b"0123456789abcdef");
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load_bytes(&self, order: Ordering) -> [u8; 16] {
+        self.load(order)
+    }
+
+    /// Stores the bytes.
+    ///
+    /// This is `store`, spelled out for byte arrays.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store_bytes(&self, bytes: [u8; 16], order: Ordering) {
+        self.store(bytes, order)
+    }
+}
+
+/// Loads a value from `ptr`, like `AtomicDouble::load`, without an
+/// `AtomicDouble` wrapped around the memory.
+///
+/// This is for memory the crate doesn't own, such as a buffer shared with
+/// other code, where `AtomicDouble::try_from_ptr` would need a lifetime. Any
+/// `load_at` and `store_at` on the same address, and any `AtomicDouble` placed
+/// there, are atomic with respect to each other.
+///
+/// # Safety
+///
+/// - `ptr` must be 16-byte aligned, and valid for reads and writes of 16
+///   bytes, even when `T` is smaller. Despite the `*const`, the memory must be
+///   writable: on x86_64 a load may be a `cmpxchg16b`, which writes the value
+///   back.
+/// - While any thread may be accessing the memory with these functions, it
+///   must not be accessed any other way than through atomic operations of this
+///   crate.
+/// - The bytes past the end of a `T` smaller than 16 bytes are read and
+///   written back unchanged, as for `try_from_ptr`.
+///
+/// # Panics
+///
+/// Panics if `order` is `Release` or `AcqRel`.
+#[cfg(not(feature = "loom"))]
+#[inline]
+pub unsafe fn load_at<T: Copy>(ptr: *const T, order: Ordering) -> T {
+    let () = AtomicDouble::<T>::SIZE_CHECK;
+    debug_assert!(
+        ptr as usize % 16 == 0,
+        "`load_at` requires a 16-byte aligned pointer"
+    );
+    ops::atomic_load(ptr as *mut T, order)
+}
+
+/// Stores a value to `ptr`, like `AtomicDouble::store`, without an
+/// `AtomicDouble` wrapped around the memory.
+///
+/// ```
+/// use atomicdouble::Ordering::{Acquire, Release};
+/// use atomicdouble::{load_at, store_at};
+///
+/// #[repr(C, align(16))]
+/// struct Event(u64, u64);
+///
+/// let slot = Box::into_raw(Box::new(Event(0, 0)));
+/// unsafe {
+///     store_at(slot as *mut [u64; 2], [1, 2], Release);
+///     assert_eq!(load_at(slot as *const [u64; 2], Acquire), [1, 2]);
+///     drop(Box::from_raw(slot));
+/// }
+/// ```
+///
+/// # Safety
+///
+/// The same as for `load_at`.
+///
+/// # Panics
+///
+/// Panics if `order` is `Acquire` or `AcqRel`.
+#[cfg(not(feature = "loom"))]
+#[inline]
+pub unsafe fn store_at<T: Copy>(ptr: *mut T, val: T, order: Ordering) {
+    let () = AtomicDouble::<T>::SIZE_CHECK;
+    debug_assert!(
+        ptr as usize % 16 == 0,
+        "`store_at` requires a 16-byte aligned pointer"
+    );
+    ops::atomic_store(ptr, val, order)
+}
+
+/// Returns how many times, in total, a CAS loop of this crate has retried
+/// since the program started.
+///
+/// Only available with the `telemetry` feature. Every failed exchange that a
+/// loop follows with another attempt counts once, across all `AtomicDouble`s
+/// and threads: in `store`, `swap`, the arithmetic and bitwise operations,
+/// and the other operations built on a CAS loop. A single `compare_exchange`
+/// never retries, so it never counts. Loops that start from a guessed value,
+/// such as `swap`, count a retry when the guess is wrong, even without
+/// contention. The fallback and the `portable-atomic` backend have no CAS
+/// loops to count.
+///
+/// The counter is one global `AtomicU64`, incremented with a relaxed
+/// `fetch_add` on every retry. That adds contention of its own, so leave the
+/// feature off outside of diagnostics; without it there is no counter at all.
+///
+/// ```
+/// let before = atomicdouble::retry_count();
+/// // ... run a workload ...
+/// let retries = atomicdouble::retry_count() - before;
+/// # let _ = retries;
+/// ```
+#[cfg(feature = "telemetry")]
+#[inline]
+pub fn retry_count() -> u64 {
+    ops::retry_count()
 }
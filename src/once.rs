@@ -0,0 +1,205 @@
+use core::fmt;
+use core::hint;
+use core::mem::MaybeUninit;
+use core::sync::atomic::AtomicU8;
+
+use crate::AtomicDouble;
+use crate::Ordering::{Acquire, Relaxed, Release};
+
+// The states of the cell. Every `T` of up to 16 bytes is a valid value, so no
+// bit pattern of the value itself can stand for "uninitialized"; the state is
+// held next to it instead.
+const UNINIT: u8 = 0;
+const WRITING: u8 = 1;
+const READY: u8 = 2;
+
+/// A cell that can be set once, holding a value of up to 16 bytes in an
+/// `AtomicDouble<T>`.
+///
+/// `set` elects one winner with a `compare_exchange` on the cell's state,
+/// from uninitialized to writing. The winner stores the value and then marks
+/// the cell ready with release ordering, so a `get` that finds it ready with
+/// acquire ordering also sees everything the winner wrote before `set`. As
+/// the winner is the only thread that ever writes the value, it stores it
+/// with `store_single_writer`, and as the value never changes after that,
+/// `get` loads it relaxed. For a 16-byte `T`, both are a single `movdqa`
+/// where that is atomic.
+///
+/// A `set` that loses waits for the winner to finish storing before it
+/// returns, so once any `set` has returned, `get` returns the value. That wait
+/// is a spin, as for `std::sync::OnceLock`, which makes `set` blocking while
+/// another thread is in the middle of one.
+///
+/// ```
+/// use atomicdouble::OnceDouble;
+///
+/// let config = OnceDouble::new();
+/// assert_eq!(config.get(), None);
+/// assert_eq!(config.set((1u64, 2u64)), Ok(()));
+/// assert_eq!(config.set((3, 4)), Err((3, 4)));
+/// assert_eq!(config.get(), Some((1, 2)));
+/// ```
+pub struct OnceDouble<T: Copy> {
+    value: AtomicDouble<MaybeUninit<T>>,
+    state: AtomicU8,
+}
+
+impl<T: Copy> OnceDouble<T> {
+    /// Creates a new, uninitialized `OnceDouble`.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub const fn new() -> OnceDouble<T> {
+        OnceDouble {
+            value: AtomicDouble::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(UNINIT),
+        }
+    }
+
+    /// Creates a new, uninitialized `OnceDouble`.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn new() -> OnceDouble<T> {
+        OnceDouble {
+            value: AtomicDouble::new(MaybeUninit::uninit()),
+            state: AtomicU8::new(UNINIT),
+        }
+    }
+
+    /// Returns the value, or `None` if no `set` has finished yet.
+    #[inline]
+    pub fn get(&self) -> Option<T> {
+        if self.state.load(Acquire) == READY {
+            // The state only becomes `READY` after the value was stored.
+            Some(unsafe { self.value.load(Relaxed).assume_init() })
+        } else {
+            None
+        }
+    }
+
+    /// Sets the value if it hasn't been set yet.
+    ///
+    /// Returns `Err` with `value` if the cell already held a value or another
+    /// `set` won the race, after waiting for that `set` to store its value.
+    #[inline]
+    pub fn set(&self, value: T) -> Result<(), T> {
+        match self
+            .state
+            .compare_exchange(UNINIT, WRITING, Acquire, Acquire)
+        {
+            Ok(_) => {
+                // Only the thread that moved the state from `UNINIT` writes.
+                unsafe {
+                    self.value
+                        .store_single_writer(MaybeUninit::new(value), Relaxed)
+                };
+                self.state.store(READY, Release);
+                Ok(())
+            }
+            Err(_) => {
+                while self.state.load(Acquire) != READY {
+                    hint::spin_loop();
+                }
+                Err(value)
+            }
+        }
+    }
+
+    /// Consumes the cell and returns the value, if it was set.
+    #[inline]
+    pub fn into_inner(self) -> Option<T> {
+        if self.state.into_inner() == READY {
+            Some(unsafe { self.value.into_inner().assume_init() })
+        } else {
+            None
+        }
+    }
+}
+
+impl<T: Copy> Default for OnceDouble<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for OnceDouble<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get() {
+            Some(value) => f.debug_tuple("OnceDouble").field(&value).finish(),
+            None => f.write_str("OnceDouble(<uninit>)"),
+        }
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::OnceDouble;
+    use std::sync::{Arc, Barrier};
+    use std::thread;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    struct Config {
+        id: u64,
+        limit: u64,
+    }
+
+    #[test]
+    fn set_once() {
+        let cell = OnceDouble::new();
+        assert_eq!(cell.get(), None);
+        assert_eq!(format!("{:?}", cell), "OnceDouble(<uninit>)");
+        assert_eq!(cell.set(Config { id: 1, limit: 2 }), Ok(()));
+        assert_eq!(cell.get(), Some(Config { id: 1, limit: 2 }));
+        assert_eq!(
+            cell.set(Config { id: 3, limit: 4 }),
+            Err(Config { id: 3, limit: 4 })
+        );
+        assert_eq!(
+            format!("{:?}", cell),
+            "OnceDouble(Config { id: 1, limit: 2 })"
+        );
+        assert_eq!(cell.into_inner(), Some(Config { id: 1, limit: 2 }));
+        assert_eq!(OnceDouble::<u32>::default().into_inner(), None);
+
+        // A value that is all zeros is still a value.
+        let zero = OnceDouble::new();
+        assert_eq!(zero.set(0u128), Ok(()));
+        assert_eq!(zero.get(), Some(0));
+    }
+
+    #[test]
+    fn concurrent_set_has_one_winner() {
+        let threads = 8;
+        let rounds = if cfg!(miri) { 2 } else { 200 };
+        for _ in 0..rounds {
+            let cell = Arc::new(OnceDouble::new());
+            let barrier = Arc::new(Barrier::new(threads));
+            let handles: Vec<_> = (0..threads as u64)
+                .map(|i| {
+                    let cell = cell.clone();
+                    let barrier = barrier.clone();
+                    thread::spawn(move || {
+                        let config = Config { id: i, limit: !i };
+                        barrier.wait();
+                        let won = match cell.set(config) {
+                            Ok(()) => true,
+                            Err(rejected) => {
+                                assert_eq!(rejected, config);
+                                false
+                            }
+                        };
+                        // Whoever won, its value is published by now.
+                        (won, cell.get().unwrap())
+                    })
+                })
+                .collect();
+            let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            let winners: Vec<_> = results.iter().filter(|(won, _)| *won).collect();
+            assert_eq!(winners.len(), 1);
+            let published = cell.get().unwrap();
+            assert_eq!(published.limit, !published.id);
+            assert!(results.iter().all(|&(_, seen)| seen == published));
+            assert_eq!(winners[0].1, published);
+        }
+    }
+}
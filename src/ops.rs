@@ -1,200 +1,4192 @@
-use core::arch::x86_64::cmpxchg16b;
+#[cfg(all(
+    any(
+        target_arch = "x86_64",
+        target_arch = "aarch64",
+        all(target_arch = "riscv64", target_feature = "zacas")
+    ),
+    not(miri)
+))]
+use core::arch::asm;
+use core::hint;
 use core::mem;
+use core::ptr;
+#[cfg(any(
+    all(target_arch = "x86", target_has_atomic = "64", not(miri)),
+    feature = "telemetry"
+))]
+use core::sync::atomic::AtomicU64;
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+use core::sync::atomic::AtomicU8;
 use core::sync::atomic::Ordering;
 
-#[cfg(feature = "fallback")]
+#[cfg(any(feature = "fallback", miri))]
 use crate::fallback;
+// Miri keeps the spin-lock, as the optimistic seqlock reads race with writers.
+#[cfg(all(feature = "mutex-fallback", not(miri)))]
+use crate::fallback::mutex as fallback;
+#[cfg(all(feature = "seqlock-fallback", not(miri)))]
+use crate::fallback::seqlock as fallback;
 
-#[inline(never)]
-#[target_feature(enable = "cmpxchg16b")]
+// The results of the x86_64 feature detection: 0 until it has run, then
+// `DETECTED_NO` or `DETECTED_YES`.
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+static CMPXCHG16B: AtomicU8 = AtomicU8::new(0);
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+static ATOMIC_MOVDQA: AtomicU8 = AtomicU8::new(0);
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+const DETECTED_NO: u8 = 1;
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+const DETECTED_YES: u8 = 2;
+
+// Tests can force the fallback on the calling thread, so that it is exercised
+// on hardware that has the native instructions too. Memory touched while it is
+// forced must not be shared with threads that don't force it, as the two paths
+// don't exclude each other. Outside of tests this is always `false`.
+#[cfg(all(
+    test,
+    any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback"
+    )
+))]
+std::thread_local! {
+    static FORCE_FALLBACK: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+}
+
+// Tests can also have the `probe` feature check a broken exchange on the
+// calling thread, which then keeps its own detection result, so that the
+// other tests still see the real one.
+#[cfg(all(test, target_arch = "x86_64", not(miri), feature = "probe"))]
+type ProbeCas = fn(*mut u128, u128, u128) -> u128;
+#[cfg(all(test, target_arch = "x86_64", not(miri), feature = "probe"))]
+std::thread_local! {
+    static TEST_PROBE: core::cell::Cell<Option<ProbeCas>> = const { core::cell::Cell::new(None) };
+    static TEST_CMPXCHG16B: AtomicU8 = const { AtomicU8::new(0) };
+}
+
+// Runs `f` with the detection redone on the current thread, probing `cas`
+// instead of the instruction, and returns what it detected along with `f`'s
+// result. Only the test of a failed probe uses it.
+#[cfg(all(
+    test,
+    target_arch = "x86_64",
+    not(miri),
+    not(target_feature = "cmpxchg16b"),
+    not(feature = "tsan"),
+    feature = "probe",
+    feature = "fallback"
+))]
+pub fn with_probe<R>(cas: ProbeCas, f: impl FnOnce() -> R) -> (u8, R) {
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            TEST_PROBE.with(|probe| probe.set(None));
+            TEST_CMPXCHG16B.with(|state| state.store(0, Ordering::Relaxed));
+        }
+    }
+    TEST_CMPXCHG16B.with(|state| state.store(0, Ordering::Relaxed));
+    TEST_PROBE.with(|probe| probe.set(Some(cas)));
+    let _reset = Reset;
+    let res = f();
+    (
+        TEST_CMPXCHG16B.with(|state| state.load(Ordering::Relaxed)),
+        res,
+    )
+}
+
+// ThreadSanitizer can't see into inline assembly, so with the `tsan` feature
+// it is always forced: every operation goes through the spin-locks, whose
+// atomics and critical sections it does understand.
+//
+// Unused on targets that always go through the fallback.
+#[allow(dead_code)]
+#[inline]
+fn fallback_forced() -> bool {
+    if cfg!(feature = "tsan") {
+        return true;
+    }
+    #[cfg(all(
+        test,
+        any(
+            feature = "fallback",
+            feature = "seqlock-fallback",
+            feature = "mutex-fallback"
+        )
+    ))]
+    {
+        FORCE_FALLBACK.with(|forced| forced.get())
+    }
+    #[cfg(not(all(
+        test,
+        any(
+            feature = "fallback",
+            feature = "seqlock-fallback",
+            feature = "mutex-fallback"
+        )
+    )))]
+    {
+        false
+    }
+}
+
+// Runs `f` with the fallback forced on the current thread. Only the tests that
+// aren't model-checked under loom use it.
+#[cfg(all(
+    test,
+    not(feature = "loom"),
+    any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback"
+    )
+))]
+pub fn with_fallback_forced<R>(f: impl FnOnce() -> R) -> R {
+    struct Reset;
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            FORCE_FALLBACK.with(|forced| forced.set(false));
+        }
+    }
+    FORCE_FALLBACK.with(|forced| forced.set(true));
+    let _reset = Reset;
+    f()
+}
+
+// With the `portable-atomic` feature, every operation on a value that fits in
+// 128 bits goes through `portable_atomic::AtomicU128`, which brings its own
+// native instructions and fallback, instead of the paths below. It never mixes
+// with them, as it is used for all operations on a given `T` or none.
+#[cfg(all(feature = "portable-atomic", not(miri)))]
+#[inline]
+fn use_portable<T>() -> bool {
+    !fallback_forced() && fits_in_u128::<T>()
+}
+
+#[cfg(all(feature = "portable-atomic", not(miri)))]
+#[inline]
+unsafe fn as_portable<'a>(dst: *mut u128) -> &'a portable_atomic::AtomicU128 {
+    // `AtomicU128` has the size and alignment of a `u128`, and every
+    // `AtomicDouble` is 16-byte aligned.
+    &*(dst as *const portable_atomic::AtomicU128)
+}
+
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[inline]
+fn has_cmpxchg16b() -> bool {
+    if cfg!(target_feature = "cmpxchg16b") {
+        return true;
+    }
+    #[cfg(all(test, feature = "probe"))]
+    {
+        if let Some(cas) = TEST_PROBE.with(|probe| probe.get()) {
+            return TEST_CMPXCHG16B.with(|state| cmpxchg16b_detected(state, cas));
+        }
+    }
+    cmpxchg16b_detected(&CMPXCHG16B, |dst, current, new| unsafe {
+        x86_64_cmpxchg16b(dst, current, new, Ordering::SeqCst, Ordering::SeqCst)
+    })
+}
+
+// `cas` is the exchange the `probe` feature checks, and `state` where the
+// result is kept: the real instruction and `CMPXCHG16B`, except in the tests
+// that make the probe fail.
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[inline]
+fn cmpxchg16b_detected(state: &AtomicU8, cas: impl Fn(*mut u128, u128, u128) -> u128) -> bool {
+    match state.load(Ordering::Relaxed) {
+        0 => detect_cmpxchg16b(state, cas),
+        detected => detected == DETECTED_YES,
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[cfg_attr(not(feature = "probe"), allow(unused_variables))]
+#[cold]
+fn detect_cmpxchg16b(state: &AtomicU8, cas: impl Fn(*mut u128, u128, u128) -> u128) -> bool {
+    #[cfg(feature = "std")]
+    let detected = std::is_x86_feature_detected!("cmpxchg16b");
+    // Without std there is no feature detection macro, so ask CPUID directly:
+    // cmpxchg16b support is reported in bit 13 of ecx for leaf 1.
+    #[cfg(not(feature = "std"))]
+    let detected = {
+        #[allow(unused_unsafe)]
+        let cpuid = unsafe { core::arch::x86_64::__cpuid(1) };
+        cpuid.ecx & (1 << 13) != 0
+    };
+    #[cfg(feature = "probe")]
+    let detected = detected && probe_cmpxchg16b(cas);
+    let result = if detected { DETECTED_YES } else { DETECTED_NO };
+    state.store(result, Ordering::Relaxed);
+    detected
+}
+
+// Runs a failing and then a succeeding exchange on a local value, and checks
+// that both behave as cmpxchg16b should. The values differ only in their high
+// halves, which an instruction that compares or stores 64 bits gets wrong. An
+// instruction that faults isn't caught: the process gets `SIGILL` right here.
+#[cfg(all(target_arch = "x86_64", not(miri), feature = "probe"))]
+#[cold]
+fn probe_cmpxchg16b(cas: impl Fn(*mut u128, u128, u128) -> u128) -> bool {
+    #[repr(align(16))]
+    struct Word(u128);
+
+    const OLD: u128 = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210;
+    const WRONG: u128 = OLD ^ 1 << 64;
+    const NEW: u128 = OLD ^ 1 << 127;
+    let mut word = Word(OLD);
+    let dst = &mut word.0 as *mut u128;
+    let failed = cas(dst, WRONG, NEW) == OLD && unsafe { ptr::read(dst) } == OLD;
+    failed && cas(dst, OLD, NEW) == OLD && unsafe { ptr::read(dst) } == NEW
+}
+
+// Whether an aligned 16-byte `movdqa` load is single-copy atomic. Intel (SDM
+// vol. 3A, 9.1.1) and AMD (APM vol. 2, 7.3.2) both guarantee this for their
+// processors that enumerate AVX support. Other vendors, and older Intel and AMD
+// processors, make no such promise, so those keep using `cmpxchg16b`.
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[inline]
+fn has_atomic_movdqa() -> bool {
+    match ATOMIC_MOVDQA.load(Ordering::Relaxed) {
+        0 => detect_atomic_movdqa(),
+        detected => detected == DETECTED_YES,
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[cold]
+fn detect_atomic_movdqa() -> bool {
+    // This is about what the CPU guarantees, not whether the OS has enabled the
+    // AVX registers, so CPUID is asked directly even with std.
+    #[allow(unused_unsafe)]
+    let (vendor, features) = unsafe {
+        (
+            core::arch::x86_64::__cpuid(0),
+            core::arch::x86_64::__cpuid(1),
+        )
+    };
+    // The vendor string is spread over ebx, edx and ecx of leaf 0.
+    let vendor = (vendor.ebx, vendor.edx, vendor.ecx);
+    let intel = vendor == (0x756e_6547, 0x4965_6e69, 0x6c65_746e);
+    let amd = vendor == (0x6874_7541, 0x6974_6e65, 0x444d_4163);
+    // AVX support is reported in bit 28 of ecx for leaf 1.
+    let detected = (intel || amd) && features.ecx & (1 << 28) != 0;
+    let state = if detected { DETECTED_YES } else { DETECTED_NO };
+    ATOMIC_MOVDQA.store(state, Ordering::Relaxed);
+    detected
+}
+
+// Runtime detection of FEAT_LSE needs std; without it `casp` is only used when
+// the target is compiled with the `lse` feature enabled.
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+#[inline]
+fn has_lse() -> bool {
+    #[cfg(feature = "std")]
+    {
+        std::arch::is_aarch64_feature_detected!("lse")
+    }
+    #[cfg(not(feature = "std"))]
+    {
+        cfg!(target_feature = "lse")
+    }
+}
+
+// Inlined into the caller by default, so that a CAS loop compiles to one loop
+// around the instruction. The assembler accepts it without the target
+// feature, and `has_cmpxchg16b` is checked before it is ever executed. The
+// `outline-asm` feature keeps it out of line instead, for smaller code.
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[cfg_attr(not(feature = "outline-asm"), inline(always))]
+#[cfg_attr(feature = "outline-asm", inline(never))]
 unsafe fn x86_64_cmpxchg16b(
+    dst: *mut u128,
+    current: u128,
+    new: u128,
+    _success: Ordering,
+    _failure: Ordering,
+) -> u128 {
+    // A lock-prefixed cmpxchg16b is sequentially consistent, so the orderings
+    // don't change the emitted code. rbx is reserved by LLVM and can't be used
+    // as an operand, so the low half of `new` is swapped into it around the
+    // instruction. The other operands are pinned to explicit registers so that
+    // none of them can be allocated to rbx while it is swapped out.
+    let prev_lo: u64;
+    let prev_hi: u64;
+    asm!(
+        "xchg rsi, rbx",
+        "lock cmpxchg16b xmmword ptr [rdi]",
+        "mov rbx, rsi",
+        in("rdi") dst,
+        inout("rsi") new as u64 => _,
+        in("rcx") (new >> 64) as u64,
+        inout("rax") current as u64 => prev_lo,
+        inout("rdx") (current >> 64) as u64 => prev_hi,
+        options(nostack),
+    );
+    (prev_hi as u128) << 64 | prev_lo as u128
+}
+
+// A plain aligned 16-byte load, which only executes a read and no barrier.
+// Only atomic where `has_atomic_movdqa` says so.
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[inline]
+unsafe fn x86_64_movdqa(src: *mut u128) -> u128 {
+    let out: core::arch::x86_64::__m128i;
+    asm!(
+        "movdqa {out}, xmmword ptr [{src}]",
+        src = in(reg) src,
+        out = out(xmm_reg) out,
+        options(nostack, preserves_flags, readonly),
+    );
+    mem::transmute(out)
+}
+
+// A plain aligned 16-byte store, the counterpart of `x86_64_movdqa`, with the
+// same guarantee. Like any x86 store it has release ordering, and the `asm!`
+// block keeps the compiler from moving memory accesses across it.
+#[cfg(all(target_arch = "x86_64", not(miri)))]
+#[inline]
+unsafe fn x86_64_movdqa_store(dst: *mut u128, val: u128) {
+    let val: core::arch::x86_64::__m128i = mem::transmute(val);
+    asm!(
+        "movdqa xmmword ptr [{dst}], {val}",
+        dst = in(reg) dst,
+        val = in(xmm_reg) val,
+        options(nostack, preserves_flags),
+    );
+}
+
+// The pair instructions move the doubleword at the lower address through the
+// first register of a pair. That is the low half of a `u128` on little-endian
+// targets, and the high half on big-endian ones such as `aarch64_be`.
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+#[inline(always)]
+fn to_pair(val: u128) -> (u64, u64) {
+    if cfg!(target_endian = "big") {
+        ((val >> 64) as u64, val as u64)
+    } else {
+        (val as u64, (val >> 64) as u64)
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+#[inline(always)]
+fn from_pair(first: u64, second: u64) -> u128 {
+    if cfg!(target_endian = "big") {
+        (first as u128) << 64 | second as u128
+    } else {
+        (second as u128) << 64 | first as u128
+    }
+}
+
+// Exclusive pair loop. A `ldxp` on its own is not guaranteed to be a single-copy
+// atomic read of all 128 bits, so when the comparison fails the observed value
+// is stored back with `stxp` and the load is retried if that store fails. When
+// `weak` is set, a failed store of `new` is reported instead of retried.
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+macro_rules! aarch64_ldxp_stxp {
+    ($ld:literal, $st:literal, $dst:ident, $current:ident, $new:ident, $weak:ident) => {{
+        let (cur0, cur1) = to_pair($current);
+        let (new0, new1) = to_pair($new);
+        let prev0: u64;
+        let prev1: u64;
+        let r: u32;
+        asm!(
+            "2:",
+            concat!($ld, " {prev0}, {prev1}, [{dst}]"),
+            "cmp {prev0}, {cur0}",
+            "ccmp {prev1}, {cur1}, #0, eq",
+            "b.ne 3f",
+            concat!($st, " {r:w}, {new0}, {new1}, [{dst}]"),
+            "cbz {r:w}, 4f",
+            "cbz {weak}, 2b",
+            "b 4f",
+            "3:",
+            concat!($st, " {r:w}, {prev0}, {prev1}, [{dst}]"),
+            "cbnz {r:w}, 2b",
+            "4:",
+            dst = in(reg) $dst,
+            cur0 = in(reg) cur0,
+            cur1 = in(reg) cur1,
+            new0 = in(reg) new0,
+            new1 = in(reg) new1,
+            weak = in(reg) $weak as u64,
+            prev0 = out(reg) prev0,
+            prev1 = out(reg) prev1,
+            r = out(reg) r,
+            options(nostack),
+        );
+        (from_pair(prev0, prev1), r == 0)
+    }};
+}
+
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+#[inline]
+unsafe fn aarch64_ldxp_stxp(
+    dst: *mut u128,
+    current: u128,
+    new: u128,
+    order: Ordering,
+    weak: bool,
+) -> Result<u128, u128> {
+    let (prev, stored) = match order {
+        Ordering::Relaxed => aarch64_ldxp_stxp!("ldxp", "stxp", dst, current, new, weak),
+        Ordering::Acquire => aarch64_ldxp_stxp!("ldaxp", "stxp", dst, current, new, weak),
+        Ordering::Release => aarch64_ldxp_stxp!("ldxp", "stlxp", dst, current, new, weak),
+        _ => aarch64_ldxp_stxp!("ldaxp", "stlxp", dst, current, new, weak),
+    };
+    if prev == current && stored {
+        Ok(prev)
+    } else {
+        Err(prev)
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+macro_rules! aarch64_casp {
+    ($op:literal, $dst:ident, $current:ident, $new:ident) => {{
+        let (cur0, cur1) = to_pair($current);
+        let (new0, new1) = to_pair($new);
+        let prev0: u64;
+        let prev1: u64;
+        asm!(
+            concat!($op, " x0, x1, x2, x3, [{dst}]"),
+            dst = in(reg) $dst,
+            inout("x0") cur0 => prev0,
+            inout("x1") cur1 => prev1,
+            in("x2") new0,
+            in("x3") new1,
+            options(nostack, preserves_flags),
+        );
+        from_pair(prev0, prev1)
+    }};
+}
+
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+#[inline(never)]
+#[target_feature(enable = "lse")]
+unsafe fn aarch64_casp(dst: *mut u128, current: u128, new: u128, order: Ordering) -> u128 {
+    match order {
+        Ordering::Relaxed => aarch64_casp!("casp", dst, current, new),
+        Ordering::Acquire => aarch64_casp!("caspa", dst, current, new),
+        Ordering::Release => aarch64_casp!("caspl", dst, current, new),
+        _ => aarch64_casp!("caspal", dst, current, new),
+    }
+}
+
+// The exclusive pair, `casp` and `amocas.q` instructions take a single
+// ordering, so the failure ordering (which can never be stronger than
+// `success`) is folded into the success ordering.
+#[cfg(all(
+    any(
+        target_arch = "aarch64",
+        all(target_arch = "riscv64", target_feature = "zacas")
+    ),
+    not(miri)
+))]
+#[inline]
+fn fold_orderings(success: Ordering, failure: Ordering) -> Ordering {
+    match (success, failure) {
+        (_, Ordering::SeqCst) => Ordering::SeqCst,
+        (Ordering::Relaxed, Ordering::Acquire) => Ordering::Acquire,
+        (Ordering::Release, Ordering::Acquire) => Ordering::AcqRel,
+        (success, _) => success,
+    }
+}
+
+#[cfg(all(target_arch = "aarch64", not(miri)))]
+#[inline]
+unsafe fn aarch64_compare_exchange(
     dst: *mut u128,
     current: u128,
     new: u128,
     success: Ordering,
     failure: Ordering,
-) -> u128 {
-    cmpxchg16b(dst, current, new, success, failure)
+    weak: bool,
+) -> Result<u128, u128> {
+    let order = fold_orderings(success, failure);
+    if has_lse() {
+        let res = aarch64_casp(dst, current, new, order);
+        if res == current {
+            Ok(res)
+        } else {
+            Err(res)
+        }
+    } else {
+        aarch64_ldxp_stxp(dst, current, new, order, weak)
+    }
+}
+
+// RV64 has no 128-bit LR/SC: `lr.d`/`sc.d` only reserve a single doubleword,
+// so a pair of them can't update both halves atomically. The only lock-free
+// 128-bit CAS is `amocas.q` from the Zacas extension, which is used when the
+// target is compiled with it. There is no stable runtime detection for it.
+#[cfg(all(target_arch = "riscv64", target_feature = "zacas", not(miri)))]
+macro_rules! riscv64_amocas_q {
+    ($op:literal, $dst:ident, $current:ident, $new:ident) => {{
+        let prev_lo: u64;
+        let prev_hi: u64;
+        // Both register pairs have to start at an even register.
+        asm!(
+            concat!($op, " a0, a2, ({dst})"),
+            dst = in(reg) $dst,
+            inout("a0") $current as u64 => prev_lo,
+            inout("a1") ($current >> 64) as u64 => prev_hi,
+            in("a2") $new as u64,
+            in("a3") ($new >> 64) as u64,
+            options(nostack, preserves_flags),
+        );
+        (prev_hi as u128) << 64 | prev_lo as u128
+    }};
+}
+
+#[cfg(all(target_arch = "riscv64", target_feature = "zacas", not(miri)))]
+#[inline]
+unsafe fn riscv64_compare_exchange(
+    dst: *mut u128,
+    current: u128,
+    new: u128,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<u128, u128> {
+    let res = match fold_orderings(success, failure) {
+        Ordering::Relaxed => riscv64_amocas_q!("amocas.q", dst, current, new),
+        Ordering::Acquire => riscv64_amocas_q!("amocas.q.aq", dst, current, new),
+        Ordering::Release => riscv64_amocas_q!("amocas.q.rl", dst, current, new),
+        _ => riscv64_amocas_q!("amocas.q.aqrl", dst, current, new),
+    };
+    if res == current {
+        Ok(res)
+    } else {
+        Err(res)
+    }
+}
+
+// 32-bit x86 has no 128-bit CAS, but `cmpxchg8b` is double width for its 32-bit
+// pointers. It is what `AtomicU64` compiles to there, so types of up to 8 bytes
+// go through it, using only the low half of the atomic.
+#[cfg(all(target_arch = "x86", target_has_atomic = "64", not(miri)))]
+#[inline]
+unsafe fn x86_cmpxchg8b(
+    dst: *mut u128,
+    current: u128,
+    new: u128,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<u128, u128> {
+    let dst = &*(dst as *const AtomicU64);
+    dst.compare_exchange(current as u64, new as u64, success, failure)
+        .map(u128::from)
+        .map_err(u128::from)
+}
+
+// The orderings are unused on targets without a native 128-bit CAS.
+#[allow(unused_variables)]
+#[inline]
+unsafe fn compare_exchange_intrinsic<T>(
+    dst: *mut u128,
+    current: u128,
+    new: u128,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<u128, u128> {
+    #[cfg(all(feature = "portable-atomic", not(miri)))]
+    {
+        if use_portable::<T>() {
+            return as_portable(dst).compare_exchange(current, new, success, failure);
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    {
+        if !fallback_forced() && has_cmpxchg16b() && fits_in_u128::<T>() {
+            let res = x86_64_cmpxchg16b(dst, current, new, success, failure);
+            if res == current {
+                return Ok(res);
+            } else {
+                return Err(res);
+            }
+        }
+    }
+
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            return aarch64_compare_exchange(dst, current, new, success, failure, false);
+        }
+    }
+
+    #[cfg(all(target_arch = "riscv64", target_feature = "zacas", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            return riscv64_compare_exchange(dst, current, new, success, failure);
+        }
+    }
+
+    #[cfg(all(target_arch = "x86", target_has_atomic = "64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u64::<T>() {
+            return x86_cmpxchg8b(dst, current, new, success, failure);
+        }
+    }
+
+    // Miri can't run inline assembly, so it always uses the fallback.
+    #[cfg(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback",
+        miri
+    ))]
+    {
+        // Lock and access only the bytes of `T`, never the padding of the atomic.
+        if fits_in_u128::<T>() {
+            let res = fallback::atomic_compare_exchange::<T>(
+                dst as *mut T,
+                mem::transmute_copy(&current),
+                mem::transmute_copy(&new),
+            );
+            return match res {
+                Ok(x) => Ok(to_u128(&x)),
+                Err(x) => Err(to_u128(&x)),
+            };
+        }
+        fallback::atomic_compare_exchange(dst, current, new)
+    }
+    #[cfg(not(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback",
+        miri
+    )))]
+    panic!("Atomic operations for type `{}` are not available as none of the `fallback`, `seqlock-fallback` and `mutex-fallback` features of the `atomicdouble` crate is enabled.", core::any::type_name::<T>());
+}
+
+#[cfg(not(miri))]
+#[inline]
+unsafe fn compare_exchange_weak_intrinsic<T>(
+    dst: *mut u128,
+    current: u128,
+    new: u128,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<u128, u128> {
+    #[cfg(all(feature = "portable-atomic", not(miri)))]
+    {
+        if use_portable::<T>() {
+            return as_portable(dst).compare_exchange_weak(current, new, success, failure);
+        }
+    }
+
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            return aarch64_compare_exchange(dst, current, new, success, failure, true);
+        }
+    }
+
+    // cmpxchg16b has no weak form, so everything else uses the strong exchange.
+    compare_exchange_intrinsic::<T>(dst, current, new, success, failure)
+}
+
+#[cfg(not(miri))]
+// The ordering is unused on targets without a native 128-bit CAS.
+#[allow(unused_variables)]
+#[inline]
+unsafe fn load_intrinsic<T>(dst: *mut u128, order: Ordering) -> u128 {
+    #[cfg(all(feature = "portable-atomic", not(miri)))]
+    {
+        if use_portable::<T>() {
+            return as_portable(dst).load(order);
+        }
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    {
+        if !fallback_forced() && has_cmpxchg16b() && fits_in_u128::<T>() {
+            // A relaxed load needs no barrier, so on CPUs where an aligned SSE
+            // load is atomic it can skip the locked instruction.
+            if order == Ordering::Relaxed && has_atomic_movdqa() {
+                return x86_64_movdqa(dst);
+            }
+            // Otherwise there is no plain 128-bit atomic load, so this has to be
+            // a locked cmpxchg16b. It always performs a write cycle, but the
+            // value written back is always the value that was observed: either
+            // the comparison fails and the old value is written back by the
+            // instruction, or the value is zero and zero is stored over it.
+            // Either way the bits are only compared and copied, never read as
+            // a `T`, so it makes no difference what an all-zero `T` means,
+            // such as `None` in an `Option<NonNull<_>>` field.
+            let fail_order = strongest_failure_ordering(order);
+            return x86_64_cmpxchg16b(dst, 0, 0, order, fail_order);
+        }
+    }
+
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            // Same as above: the exclusive pair loop and `casp` both write the
+            // observed value back.
+            let fail_order = strongest_failure_ordering(order);
+            return match aarch64_compare_exchange(dst, 0, 0, order, fail_order, false) {
+                Ok(load_val) | Err(load_val) => load_val,
+            };
+        }
+    }
+
+    #[cfg(all(target_arch = "riscv64", target_feature = "zacas", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            // `amocas.q` needs write access even when the comparison fails,
+            // like `cmpxchg16b`.
+            let fail_order = strongest_failure_ordering(order);
+            return match riscv64_compare_exchange(dst, 0, 0, order, fail_order) {
+                Ok(load_val) | Err(load_val) => load_val,
+            };
+        }
+    }
+
+    #[cfg(all(target_arch = "x86", target_has_atomic = "64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u64::<T>() {
+            // Unlike the 128-bit paths, a 64-bit load doesn't need a write.
+            return u128::from((*(dst as *const AtomicU64)).load(order));
+        }
+    }
+
+    #[cfg(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback",
+        miri
+    ))]
+    {
+        if fits_in_u128::<T>() {
+            return to_u128(&fallback::atomic_load(dst as *mut T));
+        }
+        fallback::atomic_load(dst)
+    }
+    #[cfg(not(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback",
+        miri
+    )))]
+    panic!("Atomic operations for type `{}` are not available as none of the `fallback`, `seqlock-fallback` and `mutex-fallback` features of the `atomicdouble` crate is enabled.", core::any::type_name::<T>());
+}
+
+// Types smaller than 16 bytes are zero-padded up to a `u128`. The padding bytes
+// of the atomic itself are never initialized by `AtomicDouble::new`, so only the
+// bits covered by `value_mask` are meaningful when comparing loaded values.
+#[inline]
+fn fits_in_u128<T>() -> bool {
+    mem::size_of::<T>() <= 16 && mem::align_of::<T>() <= 16
+}
+
+// The double-width atomics of 32-bit x86 only cover the low 8 bytes.
+#[cfg(all(target_arch = "x86", target_has_atomic = "64", not(miri)))]
+#[inline]
+fn fits_in_u64<T>() -> bool {
+    mem::size_of::<T>() <= 8
+}
+
+#[inline]
+fn value_mask<T>() -> u128 {
+    let bits = 8 * mem::size_of::<T>() as u32;
+    if bits >= 128 {
+        return !0;
+    }
+    let mask = (1u128 << bits) - 1;
+    if cfg!(target_endian = "big") {
+        mask << (128 - bits)
+    } else {
+        mask
+    }
+}
+
+// `new` in the bits of `T`, and the padding past a smaller `T` as it is in
+// `current`. Arithmetic on the whole `u128` carries and borrows into that
+// padding, which may be memory the caller lent through `try_from_ptr`.
+#[inline]
+fn keep_padding<T>(new: u128, current: u128) -> u128 {
+    let mask = value_mask::<T>();
+    new & mask | current & !mask
+}
+
+#[inline]
+pub unsafe fn to_u128<T>(val: &T) -> u128 {
+    let mut bits: u128 = 0;
+    ptr::copy_nonoverlapping(
+        val as *const T as *const u8,
+        &mut bits as *mut u128 as *mut u8,
+        mem::size_of::<T>().min(16),
+    );
+    bits
+}
+
+// Compares two values the way `compare_exchange` does: bytewise, ignoring the
+// zero padding of types smaller than 16 bytes.
+#[inline]
+pub fn same_value<T>(a: &T, b: &T) -> bool {
+    let mask = value_mask::<T>();
+    unsafe { to_u128(a) & mask == to_u128(b) & mask }
+}
+
+#[inline]
+unsafe fn map_result<T, U>(r: Result<T, T>) -> Result<U, U> {
+    match r {
+        Ok(x) => Ok(mem::transmute_copy(&x)),
+        Err(x) => Err(mem::transmute_copy(&x)),
+    }
+}
+
+#[inline]
+pub fn strongest_failure_ordering(order: Ordering) -> Ordering {
+    match order {
+        Ordering::Release => Ordering::Relaxed,
+        Ordering::Relaxed => Ordering::Relaxed,
+        Ordering::SeqCst => Ordering::SeqCst,
+        Ordering::Acquire => Ordering::Acquire,
+        Ordering::AcqRel => Ordering::Acquire,
+        _ => Ordering::SeqCst,
+    }
+}
+
+// Exponential backoff for the CAS retry loops. Every loop starts by issuing its
+// CAS with a guessed current value, so the first failure is expected and costs
+// nothing. After that the number of spins doubles on each failure up to
+// 2^(SPIN_LIMIT - 1), and once past the limit the thread yields if std is
+// available.
+const SPIN_LIMIT: u32 = 6;
+
+// With the `telemetry` feature, every retry of a CAS loop is counted here. All
+// loops retry through `Backoff::spin`, so this is the one place to count them.
+#[cfg(feature = "telemetry")]
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "telemetry")]
+#[inline]
+pub fn retry_count() -> u64 {
+    RETRIES.load(Ordering::Relaxed)
 }
 
-#[inline]
-unsafe fn compare_exchange_intrinsic<T>(
-    dst: *mut u128,
-    current: u128,
-    new: u128,
-    success: Ordering,
-    failure: Ordering,
-) -> Result<u128, u128> {
-    #[cfg(target_arch = "x86_64")]
-    {
-        if is_x86_feature_detected!("cmpxchg16b") && mem::size_of::<T>() == 16 {
-            let res = x86_64_cmpxchg16b(dst, current, new, success, failure);
-            if res == current {
-                return Ok(res);
-            } else {
-                return Err(res);
+struct Backoff {
+    step: u32,
+}
+
+impl Backoff {
+    #[inline]
+    fn new() -> Backoff {
+        Backoff { step: 0 }
+    }
+
+    #[inline]
+    fn spin(&mut self) {
+        #[cfg(feature = "telemetry")]
+        RETRIES.fetch_add(1, Ordering::Relaxed);
+        if self.step <= SPIN_LIMIT {
+            for _ in 0..(1u32 << self.step) >> 1 {
+                hint::spin_loop();
+            }
+            self.step += 1;
+        } else {
+            #[cfg(feature = "std")]
+            std::thread::yield_now();
+            #[cfg(not(feature = "std"))]
+            for _ in 0..1u32 << (SPIN_LIMIT - 1) {
+                hint::spin_loop();
+            }
+        }
+    }
+}
+
+#[inline]
+pub fn check_load_ordering(order: Ordering) {
+    match order {
+        Ordering::Release => panic!("there is no such thing as a release load"),
+        Ordering::AcqRel => panic!("there is no such thing as an acquire/release load"),
+        _ => {}
+    }
+}
+
+#[inline]
+pub fn check_store_ordering(order: Ordering) {
+    match order {
+        Ordering::Acquire => panic!("there is no such thing as an acquire store"),
+        Ordering::AcqRel => panic!("there is no such thing as an acquire/release store"),
+        _ => {}
+    }
+}
+
+#[inline]
+pub fn check_compare_exchange_ordering(success: Ordering, failure: Ordering) {
+    match failure {
+        Ordering::Release => panic!("there is no such thing as a release failure ordering"),
+        Ordering::AcqRel => panic!("there is no such thing as an acquire/release failure ordering"),
+        _ => {}
+    }
+    let failure_is_stronger = match (success, failure) {
+        (_, Ordering::Relaxed) => false,
+        (Ordering::Relaxed | Ordering::Release, _) => true,
+        (Ordering::Acquire | Ordering::AcqRel, Ordering::SeqCst) => true,
+        _ => false,
+    };
+    if failure_is_stronger {
+        panic!("a failure ordering can't be stronger than a success ordering");
+    }
+}
+
+// Whether `atomic_is_lock_free` holds without any runtime detection, which on
+// x86_64 means cmpxchg16b has to be enabled at compile time.
+#[inline]
+pub const fn atomic_is_always_lock_free<T>() -> bool {
+    let size = mem::size_of::<T>();
+    #[cfg(all(feature = "portable-atomic", not(miri)))]
+    {
+        !cfg!(feature = "tsan") && size <= 16 && portable_atomic::AtomicU128::is_always_lock_free()
+    }
+    #[cfg(not(all(feature = "portable-atomic", not(miri))))]
+    {
+        if cfg!(any(miri, feature = "tsan")) {
+            false
+        } else if cfg!(any(
+            target_arch = "aarch64",
+            all(target_arch = "x86_64", target_feature = "cmpxchg16b"),
+            all(target_arch = "riscv64", target_feature = "zacas")
+        )) {
+            size <= 16
+        } else if cfg!(all(target_arch = "x86", target_has_atomic = "64")) {
+            size <= 8
+        } else {
+            false
+        }
+    }
+}
+
+// `T` is unused on targets where nothing is lock-free, such as wasm32, which
+// has no 128-bit atomic instruction and always goes through the fallback.
+#[allow(clippy::extra_unused_type_parameters)]
+#[inline]
+pub fn atomic_is_lock_free<T>() -> bool {
+    #[cfg(all(feature = "portable-atomic", not(miri)))]
+    {
+        if use_portable::<T>() {
+            return portable_atomic::AtomicU128::is_lock_free();
+        }
+    }
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    {
+        if !fallback_forced() && has_cmpxchg16b() && fits_in_u128::<T>() {
+            return true;
+        }
+    }
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            return true;
+        }
+    }
+    #[cfg(all(target_arch = "riscv64", target_feature = "zacas", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u128::<T>() {
+            return true;
+        }
+    }
+    #[cfg(all(target_arch = "x86", target_has_atomic = "64", not(miri)))]
+    {
+        if !fallback_forced() && fits_in_u64::<T>() {
+            return true;
+        }
+    }
+    false
+}
+// Deliberately not atomic: one aligned 16-byte load where there is a vector
+// load for it, and a volatile read of `T` elsewhere.
+#[inline]
+pub unsafe fn load_unsync<T>(dst: *mut T) -> T {
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    {
+        mem::transmute_copy(&x86_64_movdqa(dst as *mut u128))
+    }
+    #[cfg(not(all(target_arch = "x86_64", not(miri))))]
+    {
+        ptr::read_volatile(dst)
+    }
+}
+#[cfg(not(miri))]
+#[inline]
+pub unsafe fn atomic_load<T>(dst: *mut T, order: Ordering) -> T {
+    check_load_ordering(order);
+    mem::transmute_copy(&load_intrinsic::<T>(dst as *mut u128, order))
+}
+#[cfg(not(miri))]
+#[inline]
+pub unsafe fn atomic_store<T>(dst: *mut T, val: T, order: Ordering) {
+    check_store_ordering(order);
+    #[cfg(all(feature = "portable-atomic", not(miri)))]
+    {
+        // A smaller `T` goes through the exchange below instead, which keeps
+        // the padding.
+        if use_portable::<T>() && mem::size_of::<T>() == 16 {
+            return as_portable(dst as *mut u128).store(to_u128(&val), order);
+        }
+    }
+    // The seqlock and the mutex take their lock once instead of going through
+    // a CAS loop.
+    #[cfg(any(feature = "seqlock-fallback", feature = "mutex-fallback"))]
+    {
+        if !atomic_is_lock_free::<T>() {
+            return fallback::atomic_store(dst, val);
+        }
+    }
+    store_loop::<T>(dst as *mut u128, to_u128(&val), order);
+}
+
+// With no other writers the value can't change between the load and the
+// exchange, so one exchange is enough.
+#[cfg(not(miri))]
+#[inline]
+pub unsafe fn atomic_store_single_writer<T>(dst: *mut T, val: T, order: Ordering) {
+    check_store_ordering(order);
+    #[cfg(all(target_arch = "x86_64", not(miri)))]
+    {
+        // A `SeqCst` store also needs a full barrier, which the locked
+        // instruction below provides. The store writes all 16 bytes, so a
+        // smaller `T` also goes through the exchange, which keeps the padding.
+        if order != Ordering::SeqCst
+            && !fallback_forced()
+            && has_cmpxchg16b()
+            && mem::size_of::<T>() == 16
+            && has_atomic_movdqa()
+        {
+            return x86_64_movdqa_store(dst as *mut u128, to_u128(&val));
+        }
+    }
+    #[cfg(all(target_arch = "x86", target_has_atomic = "64", not(miri)))]
+    {
+        // As above, but with a store of 8 bytes.
+        if !fallback_forced() && mem::size_of::<T>() == 8 {
+            return (*(dst as *const AtomicU64)).store(to_u128(&val) as u64, order);
+        }
+    }
+    // The fallbacks write under their lock, which other writers can't get in
+    // the way of either.
+    if !atomic_is_lock_free::<T>() {
+        return atomic_store(dst, val, order);
+    }
+    let dst = dst as *mut u128;
+    let current = load_intrinsic::<T>(dst, Ordering::Relaxed);
+    let res = compare_exchange_intrinsic::<T>(
+        dst,
+        current,
+        keep_padding::<T>(to_u128(&val), current),
+        order,
+        strongest_failure_ordering(order),
+    );
+    debug_assert!(
+        res.is_ok(),
+        "`store_single_writer` raced with another write to the same `AtomicDouble`"
+    );
+}
+
+// Returns the number of exchanges the store took, which is one unless another
+// thread wrote in between.
+#[cfg(not(miri))]
+#[inline]
+unsafe fn store_loop<T>(dst: *mut u128, new: u128, order: Ordering) -> usize {
+    // Seeding `current` with the value that is there, rather than a guess, lets
+    // an uncontended store succeed on its first exchange. The exchange provides
+    // the ordering, so the load can be relaxed.
+    let mut current = load_intrinsic::<T>(dst, Ordering::Relaxed);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    let mut attempts = 1;
+    while let Err(load_val) = compare_exchange_intrinsic::<T>(
+        dst,
+        current,
+        keep_padding::<T>(new, current),
+        order,
+        fail_order,
+    ) {
+        current = load_val;
+        attempts += 1;
+        backoff.spin();
+    }
+    attempts
+}
+
+#[cfg(not(miri))]
+#[inline]
+pub unsafe fn atomic_compare_exchange<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    check_compare_exchange_ordering(success, failure);
+    let mask = value_mask::<T>();
+    let mut current = to_u128(&current);
+    let mut new = to_u128(&new);
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, success, failure) {
+            // Only the padding differed, so retry with the padding that is there.
+            Err(prev) if prev & mask == current & mask => {
+                current = prev;
+                new = new & mask | prev & !mask;
+            }
+            res => return map_result(res),
+        }
+    }
+}
+#[cfg(not(miri))]
+#[inline]
+pub unsafe fn atomic_compare_exchange_weak<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    check_compare_exchange_ordering(success, failure);
+    let mask = value_mask::<T>();
+    let mut current = to_u128(&current);
+    let mut new = to_u128(&new);
+    loop {
+        match compare_exchange_weak_intrinsic::<T>(dst as *mut u128, current, new, success, failure)
+        {
+            // Only the padding differed, which a caller retrying with the value
+            // it got back could never fix, so retry with the padding that is
+            // there. A spurious failure sees the word it expected and is
+            // reported as one.
+            Err(prev) if prev != current && prev & mask == current & mask => {
+                current = prev;
+                new = keep_padding::<T>(new, prev);
+            }
+            res => return map_result(res),
+        }
+    }
+}
+#[cfg(not(miri))]
+#[inline]
+pub unsafe fn atomic_swap<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let val_bits: u128 = to_u128(&val);
+    let mut new = val_bits;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = keep_padding::<T>(val_bits, load_val);
+            }
+        };
+    }
+    val
+}
+
+// Going through `u128` strips the provenance of any pointers inside `T`, which
+// Miri reports as soon as such a pointer is used. Under Miri the operations that
+// move whole values around therefore stay typed as `T`.
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_load<T>(dst: *mut T, order: Ordering) -> T {
+    check_load_ordering(order);
+    fallback::atomic_load(dst)
+}
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_store<T>(dst: *mut T, val: T, order: Ordering) {
+    check_store_ordering(order);
+    fallback::atomic_swap(dst, val);
+}
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_store_single_writer<T>(dst: *mut T, val: T, order: Ordering) {
+    atomic_store(dst, val, order)
+}
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_compare_exchange<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    check_compare_exchange_ordering(success, failure);
+    fallback::atomic_compare_exchange(dst, current, new)
+}
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_compare_exchange_weak<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    atomic_compare_exchange(dst, current, new, success, failure)
+}
+#[cfg(miri)]
+#[inline]
+pub unsafe fn atomic_swap<T: Copy>(dst: *mut T, val: T, _order: Ordering) -> T {
+    fallback::atomic_swap(dst, val)
+}
+
+// The caller's `current` is the first guess for the masked-out bits; after
+// that each failed exchange tells what they actually are.
+#[inline]
+pub unsafe fn atomic_compare_exchange_masked<T>(
+    dst: *mut T,
+    current: T,
+    new: T,
+    mask: u128,
+    success: Ordering,
+    failure: Ordering,
+) -> Result<T, T> {
+    check_compare_exchange_ordering(success, failure);
+    let keep = !mask & value_mask::<T>();
+    let mut expected = to_u128(&current);
+    let new = to_u128(&new);
+    let mut backoff = Backoff::new();
+    loop {
+        let new = keep_padding::<T>(new, expected);
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, expected, new, success, failure) {
+            Err(prev) if prev & keep == expected & keep => {
+                expected = prev;
+                backoff.spin();
+            }
+            res => return map_result(res),
+        }
+    }
+}
+// A look at the current value, to seed loops that shouldn't start from a guess.
+#[inline]
+unsafe fn load_seed<T>(dst: *mut T, order: Ordering) -> u128 {
+    #[cfg(not(miri))]
+    {
+        load_intrinsic::<T>(dst as *mut u128, order)
+    }
+    #[cfg(miri)]
+    {
+        let _ = order;
+        to_u128(&fallback::atomic_load(dst))
+    }
+}
+// Makes at most `max_attempts` exchanges, and at least one, each storing
+// `update` applied to the value it expects. Gives up with the value seen by the
+// last failed exchange.
+#[inline]
+unsafe fn bounded_loop<T>(
+    dst: *mut T,
+    order: Ordering,
+    max_attempts: usize,
+    update: impl Fn(u128) -> u128,
+) -> Result<u128, u128> {
+    // Not wasting the first attempt on a guess.
+    let mut current = load_seed(dst, Ordering::Relaxed);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    let mut attempts = 1;
+    loop {
+        let new = update(current);
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(prev) => return Ok(prev),
+            Err(prev) if attempts >= max_attempts => return Err(prev),
+            Err(prev) => {
+                current = prev;
+                attempts += 1;
+                backoff.spin();
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_store_bounded<T: Copy>(
+    dst: *mut T,
+    val: T,
+    order: Ordering,
+    max_attempts: usize,
+) -> Result<(), T> {
+    check_store_ordering(order);
+    let new = to_u128(&val);
+    match bounded_loop(dst, order, max_attempts, |current| {
+        keep_padding::<T>(new, current)
+    }) {
+        Ok(_) => Ok(()),
+        Err(prev) => Err(mem::transmute_copy(&prev)),
+    }
+}
+#[inline]
+pub unsafe fn atomic_add_bounded<T: Copy>(
+    dst: *mut T,
+    val: T,
+    order: Ordering,
+    max_attempts: usize,
+) -> Result<T, T> {
+    let val = to_u128(&val);
+    map_result(bounded_loop(dst, order, max_attempts, |current| {
+        keep_padding::<T>(current.wrapping_add(val), current)
+    }))
+}
+#[inline]
+pub unsafe fn atomic_add<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    add_loop(
+        dst,
+        0,
+        to_u128(&val),
+        order,
+        strongest_failure_ordering(order),
+    )
+}
+#[inline]
+pub unsafe fn atomic_add_explicit<T: Copy>(
+    dst: *mut T,
+    val: T,
+    load_order: Ordering,
+    rmw_order: Ordering,
+) -> T {
+    // A failed exchange is another observation of the value, so it takes the
+    // load ordering too.
+    check_compare_exchange_ordering(rmw_order, load_order);
+    add_loop(
+        dst,
+        load_seed(dst, load_order),
+        to_u128(&val),
+        rmw_order,
+        load_order,
+    )
+}
+// Starts from `current`, which may be a guess. A wrong guess costs nothing
+// extra: the failed exchange is the load, and it returns the value to retry
+// with.
+#[inline]
+unsafe fn add_loop<T: Copy>(
+    dst: *mut T,
+    mut current: u128,
+    val: u128,
+    success: Ordering,
+    failure: Ordering,
+) -> T {
+    let mut backoff = Backoff::new();
+    loop {
+        let new = keep_padding::<T>(current.wrapping_add(val), current);
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, success, failure) {
+            Ok(load_val) => return mem::transmute_copy(&load_val),
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+            }
+        }
+    }
+}
+// Subtracting is adding the two's complement, bit for bit modulo 2^128, the
+// zero-extended padding of a smaller `T` included.
+#[inline]
+pub unsafe fn atomic_sub<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    add_loop(
+        dst,
+        0,
+        to_u128(&val).wrapping_neg(),
+        order,
+        strongest_failure_ordering(order),
+    )
+}
+#[inline]
+pub unsafe fn atomic_add_fetch<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let prev = atomic_add(dst, val, order);
+    mem::transmute_copy(&to_u128(&prev).wrapping_add(to_u128(&val)))
+}
+#[inline]
+pub unsafe fn atomic_sub_fetch<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let prev = atomic_sub(dst, val, order);
+    mem::transmute_copy(&to_u128(&prev).wrapping_sub(to_u128(&val)))
+}
+// Adds in the width of `T`, whose bits are those of `value_mask`, or returns
+// `None` if the sum doesn't fit in them. `val` has no bits outside of them.
+#[inline]
+fn checked_add_in<T>(current: u128, val: u128) -> Option<u128> {
+    let mask = value_mask::<T>();
+    (current & mask)
+        .checked_add(val)
+        .filter(|sum| sum & !mask == 0)
+}
+#[inline]
+pub unsafe fn atomic_checked_add<T: Copy>(dst: *mut T, val: T, order: Ordering) -> Option<T> {
+    let val = to_u128(&val);
+    let mut current: u128 = 0;
+    let mut new: u128 = val;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => return Some(mem::transmute_copy(&load_val)),
+            Err(load_val) => {
+                current = load_val;
+                // Overflow gives up without storing anything. For a smaller
+                // `T` the carry out of its bits lands in the padding rather
+                // than overflowing the `u128`.
+                new = keep_padding::<T>(checked_add_in::<T>(load_val, val)?, load_val);
+                backoff.spin();
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_checked_sub<T: Copy>(dst: *mut T, val: T, order: Ordering) -> Option<T> {
+    let val = to_u128(&val);
+    let mut current: u128 = val;
+    let mut new: u128 = 0;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => return Some(mem::transmute_copy(&load_val)),
+            Err(load_val) => {
+                current = load_val;
+                new = keep_padding::<T>((load_val & value_mask::<T>()).checked_sub(val)?, load_val);
+                backoff.spin();
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_saturating_add<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let val = to_u128(&val);
+    let mut current: u128 = 0;
+    let mut new: u128 = val;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => return mem::transmute_copy(&load_val),
+            Err(load_val) => {
+                current = load_val;
+                // The maximum of `T` is all of its bits set.
+                new = keep_padding::<T>(
+                    checked_add_in::<T>(load_val, val).unwrap_or(value_mask::<T>()),
+                    load_val,
+                );
+                backoff.spin();
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_saturating_sub<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let val = to_u128(&val);
+    let mut current: u128 = val;
+    let mut new: u128 = 0;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => return mem::transmute_copy(&load_val),
+            Err(load_val) => {
+                current = load_val;
+                new =
+                    keep_padding::<T>((load_val & value_mask::<T>()).saturating_sub(val), load_val);
+                backoff.spin();
+            }
+        }
+    }
+}
+
+#[inline]
+pub unsafe fn atomic_and<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = 0;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = keep_padding::<T>(load_val & to_u128(&val), load_val);
+            }
+        };
+    }
+    val
+}
+#[inline]
+pub unsafe fn atomic_or<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = to_u128(&val);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = load_val | to_u128(&val);
+            }
+        };
+    }
+    val
+}
+#[inline]
+pub unsafe fn atomic_xor<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = to_u128(&val);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = load_val ^ to_u128(&val);
+            }
+        };
+    }
+    val
+}
+
+#[inline]
+pub unsafe fn atomic_bit_set<T: Copy>(dst: *mut T, bit: u32, order: Ordering) -> bool {
+    let mask = 1u128 << bit;
+    to_u128(&atomic_or(dst, mem::transmute_copy(&mask), order)) & mask != 0
+}
+#[inline]
+pub unsafe fn atomic_bit_clear<T: Copy>(dst: *mut T, bit: u32, order: Ordering) -> bool {
+    let mask = 1u128 << bit;
+    to_u128(&atomic_and(dst, mem::transmute_copy(&!mask), order)) & mask != 0
+}
+#[inline]
+unsafe fn rotate_loop<T: Copy>(
+    dst: *mut T,
+    n: u32,
+    order: Ordering,
+    rotate: fn(u128, u32) -> u128,
+) -> T {
+    let mut current: u128 = 0;
+    let mut new: u128 = 0;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => return mem::transmute_copy(&load_val),
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = rotate(load_val, n);
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_rotate_left<T: Copy>(dst: *mut T, n: u32, order: Ordering) -> T {
+    rotate_loop(dst, n, order, u128::rotate_left)
+}
+#[inline]
+pub unsafe fn atomic_rotate_right<T: Copy>(dst: *mut T, n: u32, order: Ordering) -> T {
+    rotate_loop(dst, n, order, u128::rotate_right)
+}
+
+#[inline]
+pub unsafe fn atomic_nand<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = value_mask::<T>();
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = keep_padding::<T>(!(load_val & to_u128(&val)), load_val);
+            }
+        };
+    }
+    val
+}
+
+#[inline]
+pub unsafe fn atomic_umax<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = to_u128(&val);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new =
+                    keep_padding::<T>((load_val & value_mask::<T>()).max(to_u128(&val)), load_val);
+            }
+        };
+    }
+    val
+}
+#[inline]
+pub unsafe fn atomic_umin<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = 0;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new =
+                    keep_padding::<T>((load_val & value_mask::<T>()).min(to_u128(&val)), load_val);
+            }
+        };
+    }
+    val
+}
+// Like `atomic_umax` and `atomic_umin`, but also reports whether the stored
+// value was replaced by a different one.
+#[inline]
+unsafe fn changed_loop<T: Copy>(
+    dst: *mut T,
+    val: T,
+    order: Ordering,
+    pick: fn(u128, u128) -> u128,
+) -> (T, bool) {
+    let val = to_u128(&val);
+    let mask = value_mask::<T>();
+    let fail_order = strongest_failure_ordering(order);
+    let mut current: u128 = 0;
+    let mut new = pick(current, val);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => {
+                return (mem::transmute_copy(&load_val), new & mask != current & mask);
+            }
+            Err(load_val) => {
+                // The exchange has to expect the padding that is there.
+                current = load_val;
+                backoff.spin();
+                new = keep_padding::<T>(pick(load_val & mask, val), load_val);
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_umax_changed<T: Copy>(dst: *mut T, val: T, order: Ordering) -> (T, bool) {
+    changed_loop(dst, val, order, u128::max)
+}
+#[inline]
+pub unsafe fn atomic_umin_changed<T: Copy>(dst: *mut T, val: T, order: Ordering) -> (T, bool) {
+    changed_loop(dst, val, order, u128::min)
+}
+#[inline]
+pub unsafe fn atomic_max<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = 0i128.max(to_u128(&val) as i128) as u128;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                let max = ((load_val & value_mask::<T>()) as i128).max(to_u128(&val) as i128);
+                new = keep_padding::<T>(max as u128, load_val);
+            }
+        };
+    }
+    val
+}
+#[inline]
+pub unsafe fn atomic_min<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = 0i128.min(to_u128(&val) as i128) as u128;
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                let min = ((load_val & value_mask::<T>()) as i128).min(to_u128(&val) as i128);
+                new = keep_padding::<T>(min as u128, load_val);
+            }
+        };
+    }
+    val
+}
+
+#[inline]
+fn add_u64x2(a: u128, b: u128) -> u128 {
+    let lo = (a as u64).wrapping_add(b as u64);
+    let hi = ((a >> 64) as u64).wrapping_add((b >> 64) as u64);
+    (hi as u128) << 64 | lo as u128
+}
+#[inline]
+pub unsafe fn atomic_add_u64x2<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
+    let mut res = Err(0);
+    let mut current: u128 = 0;
+    let mut new: u128 = to_u128(&val);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    while res.is_err() {
+        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
+        match res {
+            Ok(load_val) => {
+                return mem::transmute_copy(&load_val);
+            }
+            Err(load_val) => {
+                current = load_val;
+                backoff.spin();
+                new = keep_padding::<T>(add_u64x2(load_val, to_u128(&val)), load_val);
+            }
+        };
+    }
+    val
+}
+
+// The lanes are in memory order, like the fields of a `[f64; 2]`.
+#[inline]
+fn apply_f64x2(a: u128, b: [f64; 2], op: fn(f64, f64) -> f64) -> u128 {
+    let a = unsafe { mem::transmute::<u128, [f64; 2]>(a) };
+    unsafe { mem::transmute::<[f64; 2], u128>([op(a[0], b[0]), op(a[1], b[1])]) }
+}
+#[inline]
+unsafe fn f64x2_loop<T: Copy>(
+    dst: *mut T,
+    val: [f64; 2],
+    order: Ordering,
+    op: fn(f64, f64) -> f64,
+) -> [f64; 2] {
+    let mut current: u128 = 0;
+    let mut new: u128 = apply_f64x2(current, val, op);
+    let fail_order = strongest_failure_ordering(order);
+    let mut backoff = Backoff::new();
+    loop {
+        match compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order) {
+            Ok(load_val) => return mem::transmute::<u128, [f64; 2]>(load_val),
+            Err(load_val) => {
+                // The exchange compares bit patterns, so a NaN lane still
+                // matches itself.
+                current = load_val;
+                backoff.spin();
+                new = apply_f64x2(load_val, val, op);
+            }
+        }
+    }
+}
+#[inline]
+pub unsafe fn atomic_add_f64x2<T: Copy>(dst: *mut T, val: [f64; 2], order: Ordering) -> [f64; 2] {
+    f64x2_loop(dst, val, order, |a, b| a + b)
+}
+#[inline]
+pub unsafe fn atomic_sub_f64x2<T: Copy>(dst: *mut T, val: [f64; 2], order: Ordering) -> [f64; 2] {
+    f64x2_loop(dst, val, order, |a, b| a - b)
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use crate::Ordering::{AcqRel, Acquire, Relaxed, Release, SeqCst};
+    use crate::{AtomicDouble, CasError};
+    use std::boxed::Box;
+    use std::ptr::NonNull;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    struct Bar(u64, u64);
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct SizeBar(u32, u32);
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    struct Node {
+        head_ptr: Option<NonNull<i32>>,
+        head_count: usize,
+    }
+
+    #[test]
+    fn atomic_bar() {
+        let a: AtomicDouble<Bar> = AtomicDouble::default();
+        assert_eq!(
+            AtomicDouble::<Bar>::is_lock_free(),
+            !cfg!(any(miri, feature = "tsan"))
+        );
+        a.load(SeqCst);
+        assert_eq!(format!("{:?}", a), "AtomicDouble(Bar(0, 0))");
+        assert_eq!(
+            format!("{:?}", a.debug_address()),
+            format!(
+                "AtomicDouble {{ type: \"{}\", addr: {:p} }}",
+                std::any::type_name::<Bar>(),
+                a.as_ptr()
+            )
+        );
+        assert_eq!(a.load(SeqCst), Bar(0, 0));
+        a.store(Bar(1, 1), SeqCst);
+        assert_eq!(
+            a.compare_exchange(Bar(5, 5), Bar(45, 45), SeqCst, SeqCst),
+            Err(Bar(1, 1))
+        );
+        assert_eq!(
+            a.compare_exchange(Bar(1, 1), Bar(3, 3), SeqCst, SeqCst),
+            Ok(Bar(1, 1))
+        );
+        assert_eq!(a.load(SeqCst), Bar(3, 3));
+    }
+
+    #[test]
+    fn atomic_swap() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.swap(Bar(3, 4), SeqCst), Bar(1, 2));
+        assert_eq!(a.swap(Bar(5, 6), SeqCst), Bar(3, 4));
+        assert_eq!(a.load(SeqCst), Bar(5, 6));
+    }
+
+    #[test]
+    fn atomic_compare_exchange_weak() {
+        let a = AtomicDouble::new(Bar(1, 1));
+        let mut current = a.load(SeqCst);
+        loop {
+            match a.compare_exchange_weak(current, Bar(2, 2), SeqCst, SeqCst) {
+                Ok(prev) => {
+                    assert_eq!(prev, Bar(1, 1));
+                    break;
+                }
+                Err(prev) => current = prev,
+            }
+        }
+        assert_eq!(
+            a.compare_exchange_weak(Bar(1, 1), Bar(3, 3), SeqCst, SeqCst),
+            Err(Bar(2, 2))
+        );
+        assert_eq!(a.load(SeqCst), Bar(2, 2));
+
+        // Padding that isn't zero differs from the zero padding of `current`
+        // in a way no retry could fix, so it must not fail the exchange.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            let mut attempts = 0;
+            while let Err(prev) = b.compare_exchange_weak(1, 2, SeqCst, SeqCst) {
+                // Only spurious failures are left, and they are rare.
+                assert_eq!(prev, 1);
+                attempts += 1;
+                assert!(attempts < 1000);
+            }
+            assert_eq!(b.compare_exchange_weak(1, 3, SeqCst, SeqCst), Err(2));
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[test]
+    fn atomic_compare_exchange_loop() {
+        let a = AtomicDouble::new(Bar(1, 1));
+        assert_eq!(
+            a.compare_exchange_loop(Bar(1, 1), Bar(2, 2), SeqCst, SeqCst),
+            Ok(Bar(1, 1))
+        );
+        assert_eq!(
+            a.compare_exchange_loop(Bar(1, 1), Bar(3, 3), SeqCst, SeqCst),
+            Err(Bar(2, 2))
+        );
+
+        // Racing threads all start from the same `current`: one wins, and the
+        // others give up with the winner's value instead of retrying.
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let handles: Vec<_> = (1..=4)
+            .map(|t| {
+                let a = a.clone();
+                thread::spawn(move || a.compare_exchange_loop(Bar(0, 0), Bar(t, t), SeqCst, SeqCst))
+            })
+            .collect();
+        let results: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        let winner = a.load(SeqCst);
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        for result in results {
+            assert!(result == Ok(Bar(0, 0)) || result == Err(winner));
+        }
+
+        // Padding that isn't zero is neither a different value nor a reason to
+        // spin forever.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(b.compare_exchange_loop(1, 2, SeqCst, SeqCst), Ok(1));
+            assert_eq!(b.compare_exchange_loop(1, 3, SeqCst, SeqCst), Err(2));
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[test]
+    fn atomic_is_always_lock_free() {
+        const ALWAYS: bool = AtomicDouble::<Bar>::is_always_lock_free();
+        let expected = cfg!(all(
+            not(any(miri, feature = "tsan")),
+            any(
+                target_arch = "aarch64",
+                all(target_arch = "x86_64", target_feature = "cmpxchg16b")
+            )
+        ));
+        assert_eq!(ALWAYS, expected);
+        assert_eq!(
+            AtomicDouble::<Bar>::is_lock_free(),
+            !cfg!(any(miri, feature = "tsan"))
+        );
+        // Always lock-free implies lock-free on this CPU.
+        assert!(!ALWAYS || AtomicDouble::<Bar>::is_lock_free());
+        assert!(
+            !AtomicDouble::<SizeBar>::is_always_lock_free()
+                || AtomicDouble::<SizeBar>::is_lock_free()
+        );
+    }
+
+    #[cfg(not(miri))]
+    #[test]
+    fn atomic_fetch_add_explicit() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.fetch_add_explicit(Bar(1, 1), Acquire, AcqRel), Bar(1, 2));
+        assert_eq!(a.fetch_add_explicit(Bar(1, 1), Relaxed, Release), Bar(2, 3));
+        assert_eq!(a.fetch_add_explicit(Bar(1, 1), SeqCst, SeqCst), Bar(3, 4));
+        assert_eq!(a.load(SeqCst), Bar(4, 5));
+
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        a.fetch_add_explicit(Bar(1, 2), Acquire, AcqRel);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), Bar(4 * n, 8 * n));
+    }
+
+    #[test]
+    #[should_panic]
+    fn atomic_fetch_add_explicit_release_load() {
+        AtomicDouble::new(Bar(0, 0)).fetch_add_explicit(Bar(1, 1), Release, SeqCst);
+    }
+
+    #[test]
+    fn atomic_bounded() {
+        // Uncontended, the first attempt succeeds.
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.try_store_bounded(Bar(3, 4), SeqCst, 1), Ok(()));
+        assert_eq!(a.try_fetch_add_bounded(Bar(1, 1), SeqCst, 1), Ok(Bar(3, 4)));
+        assert_eq!(a.load(SeqCst), Bar(4, 5));
+        // Zero attempts still makes one.
+        assert_eq!(a.try_store_bounded(Bar(0, 0), Relaxed, 0), Ok(()));
+
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let contender = {
+            let a = a.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Relaxed) {
+                    a.fetch_add(Bar(1, 0), Relaxed);
+                }
+            })
+        };
+        // With a single attempt each, the calls eventually lose a race.
+        while a.try_fetch_add_bounded(Bar(0, 1), SeqCst, 1).is_ok() {}
+        while a.try_store_bounded(Bar(5, 5), SeqCst, 1).is_ok() {}
+        done.store(true, Relaxed);
+        contender.join().unwrap();
+    }
+
+    #[test]
+    fn atomic_fetch_sub_first_attempt() {
+        // The first exchange guesses zero, so cover a value that matches the
+        // guess, one that matches `val`, and others, against plain wrapping
+        // arithmetic.
+        let values = [0u128, 1, 7, 1 << 64, u128::MAX];
+        for &x in &values {
+            for &y in &values {
+                for &order in &[Relaxed, Release, AcqRel, SeqCst] {
+                    let a = AtomicDouble::new(x);
+                    assert_eq!(a.fetch_sub(y, order), x);
+                    assert_eq!(a.load(SeqCst), x.wrapping_sub(y));
+                    assert_eq!(a.fetch_add(y, order), x.wrapping_sub(y));
+                    assert_eq!(a.load(SeqCst), x);
+                    assert_eq!(a.sub_fetch(y, order), x.wrapping_sub(y));
+                }
+            }
+        }
+        // The borrow out of a smaller type doesn't reach the padding, which is
+        // kept as it was. It is checked in a buffer of our own, as the padding
+        // of `AtomicDouble::new` is uninitialized.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let mut buffer = Buffer([0, 0]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr() as *mut SizeBar).unwrap();
+            assert_eq!(b.fetch_sub(SizeBar(1, 0), SeqCst), SizeBar(0, 0));
+            assert_eq!(b.load(SeqCst), SizeBar(u32::MAX, u32::MAX));
+        }
+        assert_eq!(buffer.0, [u64::MAX, 0]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr() as *mut SizeBar).unwrap();
+            assert_eq!(
+                b.fetch_sub(SizeBar(u32::MAX, u32::MAX), SeqCst),
+                SizeBar(u32::MAX, u32::MAX)
+            );
+            assert_eq!(b.load(SeqCst), SizeBar(0, 0));
+        }
+        assert_eq!(buffer.0, [0, 0]);
+    }
+
+    #[test]
+    fn atomic_store_single_writer() {
+        let a = AtomicDouble::new(Bar(0, 0));
+        for &order in &[Relaxed, Release, SeqCst] {
+            unsafe { a.store_single_writer(Bar(1, 2), order) };
+            assert_eq!(a.load(SeqCst), Bar(1, 2));
+            unsafe { a.store_single_writer(Bar(3, 4), order) };
+            assert_eq!(a.load(Relaxed), Bar(3, 4));
+        }
+        let b = AtomicDouble::new(SizeBar(1, 2));
+        unsafe { b.store_single_writer(SizeBar(3, 4), Release) };
+        assert_eq!(b.load(Acquire), SizeBar(3, 4));
+        #[cfg(feature = "fallback")]
+        super::with_fallback_forced(|| {
+            let c = AtomicDouble::new(Bar(0, 0));
+            unsafe { c.store_single_writer(Bar(5, 6), SeqCst) };
+            assert_eq!(c.load(SeqCst), Bar(5, 6));
+        });
+
+        // One writer and several readers, which must never see a value that
+        // wasn't stored.
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let n = if cfg!(miri) { 20 } else { 100_000 };
+        let readers: Vec<_> = (0..3)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    while last < n {
+                        let v = a.load(Acquire);
+                        assert_eq!(v.0, v.1, "torn value {:?}", v);
+                        assert!(v.0 >= last, "went back from {} to {:?}", last, v);
+                        last = v.0;
+                    }
+                })
+            })
+            .collect();
+        for i in 1..=n {
+            let order = [Relaxed, Release, SeqCst][i as usize % 3];
+            unsafe { a.store_single_writer(Bar(i, i), order) };
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+    }
+
+    // `store_loop` isn't used under Miri, where stores stay typed.
+    #[cfg(not(miri))]
+    #[test]
+    fn atomic_store_uncontended() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let new = unsafe { super::to_u128(&Bar(3, 4)) };
+        for &order in &[Relaxed, Release, SeqCst] {
+            let attempts =
+                unsafe { super::store_loop::<Bar>(a.as_mut_ptr() as *mut u128, new, order) };
+            assert_eq!(attempts, 1);
+        }
+        assert_eq!(a.load(SeqCst), Bar(3, 4));
+    }
+
+    #[cfg(feature = "alloc")]
+    #[test]
+    fn atomic_into_box() {
+        let a = AtomicDouble::into_box(Bar(1, 2));
+        assert_eq!(a.as_ptr() as usize % 16, 0);
+        assert_eq!(
+            a.compare_exchange(Bar(1, 2), Bar(3, 4), SeqCst, SeqCst),
+            Ok(Bar(1, 2))
+        );
+        let a: Arc<AtomicDouble<Bar>> = Arc::from(a);
+        let b = a.clone();
+        thread::spawn(move || b.fetch_add(Bar(1, 1), SeqCst))
+            .join()
+            .unwrap();
+        let a = Arc::try_unwrap(a).unwrap();
+        assert_eq!(AtomicDouble::from_box(Box::new(a)), Bar(4, 5));
+
+        // Small types are boxed with the same alignment.
+        let c = AtomicDouble::into_box(1u8);
+        assert_eq!(c.as_ptr() as usize % 16, 0);
+        assert_eq!(AtomicDouble::from_box(c), 1);
+    }
+
+    #[test]
+    fn atomic_with_mut() {
+        let mut a = AtomicDouble::new(Bar(1, 2));
+        let sum = a.with_mut(|v| {
+            for _ in 0..3 {
+                v.0 += 1;
+                v.1 += 2;
+            }
+            v.0 + v.1
+        });
+        assert_eq!(sum, 12);
+        assert_eq!(a.load(SeqCst), Bar(4, 8));
+    }
+
+    #[test]
+    fn atomic_take_inner_releases_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        struct Counted(Arc<AtomicUsize>);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                self.0.fetch_add(1, SeqCst);
+            }
+        }
+
+        // A `Copy` handle owning a boxed `Counted`, freed by whoever gets it
+        // out of the atomic.
+        type Handle = (Option<NonNull<Counted>>, usize);
+        let drops = Arc::new(AtomicUsize::new(0));
+        let new = |tag| -> Handle {
+            let counted = Box::new(Counted(drops.clone()));
+            (NonNull::new(Box::into_raw(counted)), tag)
+        };
+        let release = |handle: Handle| {
+            if let Some(ptr) = handle.0 {
+                drop(unsafe { Box::from_raw(ptr.as_ptr()) });
+            }
+        };
+
+        let mut a = AtomicDouble::new(new(1));
+        release(a.swap(new(2), SeqCst));
+        assert_eq!(drops.load(SeqCst), 1);
+        release(a.take(SeqCst));
+        assert_eq!(drops.load(SeqCst), 2);
+        a.store(new(3), SeqCst);
+        release(a.take_inner());
+        assert_eq!(drops.load(SeqCst), 3);
+        // Only the default handle is left, which owns nothing.
+        assert_eq!(a.take_inner(), (None, 0));
+        release(a.into_inner());
+        assert_eq!(drops.load(SeqCst), 3);
+    }
+
+    #[test]
+    fn atomic_clone() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let b = a.clone();
+        assert_eq!(b.load(SeqCst), Bar(1, 2));
+        a.store(Bar(3, 4), SeqCst);
+        b.fetch_add(Bar(1, 0), SeqCst);
+        assert_eq!(a.load(SeqCst), Bar(3, 4));
+        assert_eq!(b.load(SeqCst), Bar(2, 2));
+    }
+
+    #[test]
+    fn atomic_send_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+        assert_send::<AtomicDouble<Bar>>();
+        assert_sync::<AtomicDouble<Bar>>();
+        // `Send` but not `Sync`, which is all `AtomicDouble` needs.
+        #[derive(Clone, Copy)]
+        struct NotSync(std::marker::PhantomData<std::cell::Cell<u64>>);
+        assert_send::<AtomicDouble<NotSync>>();
+        assert_sync::<AtomicDouble<NotSync>>();
+    }
+
+    #[test]
+    fn atomic_load_consume() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.load_consume(), Bar(1, 2));
+
+        // The pointer read from the atomic is safe to follow.
+        #[derive(Copy, Clone)]
+        struct Published(*mut i32, usize);
+        unsafe impl Send for Published {}
+
+        let a = Arc::new(AtomicDouble::new(Published(std::ptr::null_mut(), 0)));
+        let b = a.clone();
+        let writer = thread::spawn(move || {
+            let value = Box::into_raw(Box::new(42));
+            b.store(Published(value, 1), Release);
+        });
+        let published = loop {
+            let published = a.load_consume();
+            if !published.0.is_null() {
+                break published;
+            }
+        };
+        writer.join().unwrap();
+        let value = unsafe { Box::from_raw(published.0) };
+        assert_eq!(*value, 42);
+        assert_eq!(published.1, 1);
+    }
+
+    // Meant to be run under `-Zsanitizer=thread`, where a race report on the
+    // plain reads and writes of `data` would fail it.
+    #[cfg(feature = "tsan")]
+    #[test]
+    fn atomic_tsan_smoke() {
+        assert!(!AtomicDouble::<Bar>::is_lock_free());
+        assert!(!AtomicDouble::<Bar>::is_always_lock_free());
+
+        struct Shared {
+            ready: AtomicDouble<Bar>,
+            data: std::cell::UnsafeCell<u64>,
+        }
+        unsafe impl Sync for Shared {}
+
+        let shared = Arc::new(Shared {
+            ready: AtomicDouble::new(Bar(0, 0)),
+            data: std::cell::UnsafeCell::new(0),
+        });
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    if i == 0 {
+                        unsafe { *shared.data.get() = 42 };
+                        shared.ready.fetch_add(Bar(1, 0), Release);
+                    } else {
+                        while shared.ready.load(Acquire).0 == 0 {}
+                        assert_eq!(unsafe { *shared.data.get() }, 42);
+                        let mut current = shared.ready.load(Relaxed);
+                        while let Err(actual) = shared.ready.compare_exchange_weak(
+                            current,
+                            Bar(current.0, current.1 + 1),
+                            AcqRel,
+                            Relaxed,
+                        ) {
+                            current = actual;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(shared.ready.load(SeqCst), Bar(1, 3));
+    }
+
+    #[test]
+    fn atomic_as_u64_pair() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let [first, last] = a.as_u64_pair();
+        assert_eq!(first.load(Relaxed), 1);
+        assert_eq!(last.load(Relaxed), 2);
+
+        // Once the writers are done, both views agree.
+        let a = Arc::new(a);
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        a.fetch_add_u64x2(Bar(1, 2), AcqRel);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        let [first, last] = a.as_u64_pair();
+        assert_eq!(first.load(Acquire), 201);
+        assert_eq!(last.load(Acquire), 402);
+        assert_eq!(a.load(SeqCst), Bar(201, 402));
+
+        last.store(7, Release);
+        assert_eq!(a.load(SeqCst), Bar(201, 7));
+    }
+
+    #[test]
+    #[should_panic(expected = "exactly 16 bytes")]
+    fn atomic_as_u64_pair_small() {
+        let a = AtomicDouble::new(SizeBar(1, 2));
+        let _ = a.as_u64_pair();
+    }
+
+    #[test]
+    fn atomic_fetch_add_signed() {
+        let a = AtomicDouble::new(5u128);
+        assert_eq!(a.fetch_add_signed(-2, SeqCst), 5);
+        assert_eq!(a.load(SeqCst), 3);
+        // Wraps below zero, and back up past the top.
+        assert_eq!(a.fetch_add_signed(-4, SeqCst), 3);
+        assert_eq!(a.load(SeqCst), u128::MAX);
+        assert_eq!(a.fetch_add_signed(2, SeqCst), u128::MAX);
+        assert_eq!(a.load(SeqCst), 1);
+        assert_eq!(a.fetch_add_signed(i128::MIN, SeqCst), 1);
+        assert_eq!(a.load(SeqCst), 1u128.wrapping_add_signed(i128::MIN));
+    }
+
+    #[cfg(all(
+        not(any(miri, feature = "tsan")),
+        any(
+            target_arch = "aarch64",
+            all(target_arch = "x86_64", target_feature = "cmpxchg16b")
+        )
+    ))]
+    crate::assert_lock_free!(const Bar);
+
+    #[test]
+    fn atomic_assert_lock_free() {
+        let res = std::panic::catch_unwind(|| crate::assert_lock_free!(Bar));
+        assert_eq!(res.is_ok(), AtomicDouble::<Bar>::is_lock_free());
+    }
+
+    #[cfg(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback"
+    ))]
+    #[test]
+    #[should_panic(expected = "`AtomicDouble<Bar>` is not lock-free on this CPU")]
+    fn atomic_assert_lock_free_fallback() {
+        super::with_fallback_forced(|| crate::assert_lock_free!(Bar));
+    }
+
+    #[test]
+    fn atomic_store_if() {
+        // Only forward transitions of the state in the first field.
+        let forward = |to: u64| move |current: Bar| current.0 < to;
+        let a = AtomicDouble::new(Bar(1, 0));
+        assert_eq!(a.store_if(Bar(2, 7), forward(2), AcqRel), Ok(Bar(1, 0)));
+        assert_eq!(a.store_if(Bar(1, 8), forward(1), AcqRel), Err(Bar(2, 7)));
+        assert_eq!(a.store_if(Bar(2, 9), forward(2), AcqRel), Err(Bar(2, 7)));
+        assert_eq!(a.load(SeqCst), Bar(2, 7));
+
+        // Racing threads each try to advance to their own state; the value
+        // only ever moves forward, and ends at the largest.
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let handles: Vec<_> = (1..=8)
+            .map(|to| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    let mut calls = 0;
+                    let res = a.store_if(
+                        Bar(to, 0),
+                        |current| {
+                            calls += 1;
+                            current.0 < to
+                        },
+                        Release,
+                    );
+                    assert!(calls >= 1);
+                    match res {
+                        Ok(prev) => assert!(prev.0 < to),
+                        Err(current) => assert!(current.0 >= to),
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), Bar(8, 0));
+
+        // `pred` is given the value without its padding, so padding that isn't
+        // zero must not keep the exchange failing.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(b.store_if(2, |v| v == 1, AcqRel), Ok(1));
+            assert_eq!(b.store_if(3, |v| v == 1, AcqRel), Err(2));
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[test]
+    fn atomic_compare_exchange_detailed() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let mismatch = CasError {
+            observed: Bar(1, 2),
+            is_spurious: false,
+        };
+        assert_eq!(
+            a.compare_exchange_detailed(Bar(0, 0), Bar(3, 4), SeqCst, SeqCst),
+            Err(mismatch)
+        );
+        assert_eq!(
+            a.compare_exchange_weak_detailed(Bar(0, 0), Bar(3, 4), SeqCst, SeqCst),
+            Err(mismatch)
+        );
+        assert_eq!(
+            a.compare_exchange_detailed(Bar(1, 2), Bar(3, 4), SeqCst, SeqCst),
+            Ok(Bar(1, 2))
+        );
+
+        // Any failure with the current value in hand is spurious, and then
+        // still holds that value. Where weak exchanges never fail spuriously,
+        // such as on x86_64, the loop succeeds at once.
+        let mut spurious = 0;
+        loop {
+            match a.compare_exchange_weak_detailed(Bar(3, 4), Bar(5, 6), SeqCst, Relaxed) {
+                Ok(prev) => {
+                    assert_eq!(prev, Bar(3, 4));
+                    break;
+                }
+                Err(err) => {
+                    assert_eq!(err.observed, Bar(3, 4));
+                    assert!(err.is_spurious);
+                    spurious += 1;
+                }
+            }
+        }
+        if cfg!(target_arch = "x86_64") {
+            assert_eq!(spurious, 0);
+        }
+        assert_eq!(a.load(SeqCst), Bar(5, 6));
+    }
+
+    #[test]
+    fn atomic_bytes_round_trip() {
+        let pattern: [u8; 16] = [
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xaa, 0xbb, 0xcc, 0xdd,
+            0xee, 0xff,
+        ];
+        let mut reversed = pattern;
+        reversed.reverse();
+
+        let a = AtomicDouble::new([0u8; 16]);
+        a.store_bytes(pattern, Release);
+        assert_eq!(a.load_bytes(Acquire), pattern);
+        assert_eq!(a.swap(reversed, SeqCst), pattern);
+        assert_eq!(
+            a.compare_exchange(reversed, pattern, SeqCst, SeqCst),
+            Ok(reversed)
+        );
+        // Operations that leave the number unchanged leave the bytes too.
+        assert_eq!(a.fetch_add([0; 16], SeqCst), pattern);
+        assert_eq!(a.fetch_or([0; 16], SeqCst), pattern);
+        assert_eq!(a.fetch_max([0; 16], SeqCst), pattern);
+        assert_eq!(a.load_bytes(SeqCst), pattern);
+        assert_eq!(a.into_inner(), pattern);
+    }
+
+    #[test]
+    fn atomic_u128_round_trip() {
+        let mut values = vec![0, 1, u64::MAX as u128, 1 << 64, u128::MAX - 1, u128::MAX];
+        let mut x = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128;
+        for _ in 0..64 {
+            // An LCG is enough to reach bits all over the word.
+            x = x
+                .wrapping_mul(0x2360_ed05_1fc6_5da4_4385_df64_9fcc_f645)
+                .wrapping_add(0x5851_f42d_4c95_7f2d_1405_7b7e_f767_814f);
+            values.push(x);
+        }
+        for v in values {
+            let a = AtomicDouble::from_u128(v);
+            assert_eq!(unsafe { a.to_u128(SeqCst) }, v);
+            assert_eq!(a.load(Relaxed), v);
+            a.store(!v, Release);
+            assert_eq!(unsafe { a.to_u128(Acquire) }, !v);
+            // The same bits seen through another 16-byte type.
+            let b = AtomicDouble::new(v.to_ne_bytes());
+            assert_eq!(unsafe { b.to_u128(SeqCst) }, v);
+        }
+        // Smaller types are zero-extended.
+        let c = AtomicDouble::new(0x0102_0304_0506_0708u64);
+        let mut bytes = [0; 16];
+        bytes[..8].copy_from_slice(&0x0102_0304_0506_0708u64.to_ne_bytes());
+        assert_eq!(unsafe { c.to_u128(SeqCst) }, u128::from_ne_bytes(bytes));
+    }
+
+    #[test]
+    fn atomic_fill_array() {
+        let slots: [AtomicDouble<Bar>; 16] = AtomicDouble::fill_array(Bar(1, 2));
+        for (i, slot) in slots.iter().enumerate() {
+            assert_eq!(slot.as_ptr() as usize % 16, 0);
+            assert_eq!(slot.load(SeqCst), Bar(1, 2));
+            slot.store(Bar(i as u64, 0), SeqCst);
+        }
+        // Each slot is its own atomic.
+        for (i, slot) in slots.iter().enumerate() {
+            assert_eq!(slot.load(SeqCst), Bar(i as u64, 0));
+        }
+        let empty: [AtomicDouble<u64>; 0] = AtomicDouble::fill_array(0);
+        assert!(empty.is_empty());
+    }
+
+    #[cfg(all(target_arch = "x86_64", not(miri), feature = "probe"))]
+    #[test]
+    fn atomic_probe_cmpxchg16b() {
+        use super::{has_cmpxchg16b, probe_cmpxchg16b, x86_64_cmpxchg16b};
+        use core::ptr;
+
+        if has_cmpxchg16b() {
+            assert!(probe_cmpxchg16b(|dst, current, new| unsafe {
+                x86_64_cmpxchg16b(dst, current, new, SeqCst, SeqCst)
+            }));
+        }
+        // An exchange that only compares and stores the low half, or that
+        // never stores, is caught.
+        assert!(!probe_cmpxchg16b(|dst, current, new| unsafe {
+            let prev = ptr::read(dst);
+            if prev as u64 == current as u64 {
+                ptr::write(dst, prev & !(u64::MAX as u128) | new & u64::MAX as u128);
+            }
+            prev
+        }));
+        assert!(!probe_cmpxchg16b(|dst, _, _| unsafe { ptr::read(dst) }));
+    }
+
+    // A probe that gets wrong results makes detection report no cmpxchg16b, so
+    // that every operation goes through the fallback.
+    #[cfg(all(
+        target_arch = "x86_64",
+        not(miri),
+        not(target_feature = "cmpxchg16b"),
+        not(feature = "tsan"),
+        feature = "probe",
+        feature = "fallback"
+    ))]
+    #[test]
+    fn atomic_probe_failed_uses_fallback() {
+        use super::{with_probe, x86_64_cmpxchg16b, DETECTED_NO, DETECTED_YES};
+        use core::ptr;
+
+        // Only compares and stores the low half.
+        fn low_half_cas(dst: *mut u128, current: u128, new: u128) -> u128 {
+            unsafe {
+                let prev = ptr::read(dst);
+                if prev as u64 == current as u64 {
+                    ptr::write(dst, prev & !(u64::MAX as u128) | new & u64::MAX as u128);
+                }
+                prev
+            }
+        }
+        fn real_cas(dst: *mut u128, current: u128, new: u128) -> u128 {
+            unsafe { x86_64_cmpxchg16b(dst, current, new, SeqCst, SeqCst) }
+        }
+
+        let (detected, ()) = with_probe(low_half_cas, || {
+            assert!(!AtomicDouble::<Bar>::is_lock_free());
+            let a = AtomicDouble::new(Bar(1, 2));
+            assert_eq!(
+                a.compare_exchange(Bar(1, 2), Bar(3, 4), SeqCst, SeqCst),
+                Ok(Bar(1, 2))
+            );
+            assert_eq!(a.fetch_add(Bar(1, 1), SeqCst), Bar(3, 4));
+            assert_eq!(a.load(Relaxed), Bar(4, 5));
+        });
+        assert_eq!(detected, DETECTED_NO);
+
+        // The same detection with the real instruction, where CPUID has it.
+        if super::has_cmpxchg16b() {
+            let (detected, lock_free) = with_probe(real_cas, AtomicDouble::<Bar>::is_lock_free);
+            assert_eq!(detected, DETECTED_YES);
+            assert!(lock_free);
+        }
+        // Outside of `with_probe` the real detection is back in effect.
+        assert_eq!(AtomicDouble::<Bar>::is_lock_free(), super::has_cmpxchg16b());
+    }
+
+    #[test]
+    fn atomic_load_store_at() {
+        use crate::{load_at, store_at};
+
+        // An event ring in memory the crate doesn't own.
+        #[repr(C, align(16))]
+        struct Ring([Bar; 8]);
+
+        struct SendPtr(*mut Bar);
+        unsafe impl Send for SendPtr {}
+        unsafe impl Sync for SendPtr {}
+
+        let ring = Box::into_raw(Box::new(Ring([Bar(0, 0); 8])));
+        let slots = Arc::new(SendPtr(ring as *mut Bar));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4u64)
+            .map(|t| {
+                let slots = slots.clone();
+                thread::spawn(move || {
+                    for i in 0..n {
+                        let slot = unsafe { slots.0.add((i % 8) as usize) };
+                        unsafe { store_at(slot, Bar(t * n + i, t * n + i), Release) };
+                        let Bar(x, y) = unsafe { load_at(slot as *const Bar, Acquire) };
+                        // Never a mix of two events.
+                        assert_eq!(x, y);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // The same memory, seen through an `AtomicDouble`.
+        let first = unsafe { AtomicDouble::try_from_ptr(slots.0) }.unwrap();
+        first.store(Bar(7, 7), SeqCst);
+        assert_eq!(unsafe { load_at(slots.0 as *const Bar, SeqCst) }, Bar(7, 7));
+        drop(unsafe { Box::from_raw(ring) });
+    }
+
+    // The native paths are the ones with CAS loops; the fallback, and
+    // `portable-atomic`, have none to count.
+    #[cfg(all(
+        feature = "telemetry",
+        not(any(miri, feature = "tsan", feature = "portable-atomic"))
+    ))]
+    #[test]
+    fn atomic_retry_count() {
+        use crate::retry_count;
+
+        if !AtomicDouble::<Bar>::is_lock_free() {
+            return;
+        }
+        // `swap` starts from a guess of zero, so swapping a nonzero value
+        // out always retries at least once.
+        let a = AtomicDouble::new(Bar(1, 1));
+        let before = retry_count();
+        a.swap(Bar(2, 2), SeqCst);
+        assert!(retry_count() > before);
+
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let before = retry_count();
+        let n = 10_000;
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        a.fetch_add(Bar(1, 1), SeqCst);
+                        a.swap(Bar(1, 1), SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // Every swap finds a nonzero value, so each retries at least once.
+        assert!(retry_count() - before >= n);
+    }
+
+    #[test]
+    fn atomic_map() {
+        let a = AtomicDouble::new(3u128 << 100);
+        assert_eq!(a.map(SeqCst, |v| v * 2), (3 << 100, 3 << 101));
+        assert_eq!(a.load(SeqCst), 3 << 101);
+        assert_eq!(a.map(Release, |v| v.wrapping_mul(2)), (3 << 101, 3 << 102));
+
+        // Each update is applied exactly once.
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        let (old, new) = a.map(AcqRel, |Bar(x, y)| Bar(x + 1, y + 2));
+                        assert_eq!(new, Bar(old.0 + 1, old.1 + 2));
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), Bar(4 * n, 8 * n));
+
+        // `f` is given the value without its padding, so padding that isn't
+        // zero must not keep the exchange failing.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(b.map(AcqRel, |v| v + 1), (1, 2));
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[test]
+    fn atomic_load_preserves_niche() {
+        fn check_loads(a: &AtomicDouble<Node>, expected: Node) {
+            for &order in &[Relaxed, Acquire, SeqCst] {
+                assert_eq!(a.load(order), expected);
+                // The write-back of the load left the bits as they were.
+                assert_eq!(unsafe { a.to_u128(Relaxed) }, unsafe {
+                    super::to_u128(&expected)
+                });
+            }
+        }
+
+        let x = Box::into_raw(Box::new(5));
+        let empty = Node {
+            head_ptr: None,
+            head_count: 0,
+        };
+        let full = Node {
+            head_ptr: NonNull::new(x),
+            head_count: 7,
+        };
+        // `None` is all zeros, which is the value the load compares against.
+        let a = AtomicDouble::new(empty);
+        check_loads(&a, empty);
+        a.store(
+            Node {
+                head_ptr: None,
+                head_count: 3,
+            },
+            SeqCst,
+        );
+        check_loads(
+            &a,
+            Node {
+                head_ptr: None,
+                head_count: 3,
+            },
+        );
+        a.store(full, SeqCst);
+        check_loads(&a, full);
+        #[cfg(feature = "fallback")]
+        super::with_fallback_forced(|| {
+            let b = AtomicDouble::new(empty);
+            check_loads(&b, empty);
+        });
+
+        // A writer alternates between an empty node with an even count and a
+        // full one with an odd count, which loads must never mix up.
+        struct Shared {
+            node: AtomicDouble<Node>,
+            x: NonNull<i32>,
+        }
+        unsafe impl Sync for Shared {}
+        let shared = Shared {
+            node: AtomicDouble::new(empty),
+            x: NonNull::new(x).unwrap(),
+        };
+        let n = if cfg!(miri) { 10 } else { 10_000 };
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=n {
+                    let head_ptr = if i % 2 == 0 { None } else { Some(shared.x) };
+                    shared.node.store(
+                        Node {
+                            head_ptr,
+                            head_count: i,
+                        },
+                        Release,
+                    );
+                }
+            });
+            for _ in 0..3 {
+                s.spawn(|| {
+                    for _ in 0..n {
+                        let node = shared.node.load(Acquire);
+                        assert_eq!(node.head_ptr.is_some(), node.head_count % 2 == 1);
+                    }
+                });
+            }
+        });
+        drop(unsafe { Box::from_raw(x) });
+    }
+
+    #[test]
+    fn atomic_try_from_ptr() {
+        #[repr(C, align(16))]
+        struct Buffer([u64; 6]);
+
+        let mut buffer = Buffer([1, 2, 3, 4, 5, 6]);
+        let base = buffer.0.as_mut_ptr();
+        let at = |i: usize| unsafe { base.add(i) as *mut Bar };
+        unsafe {
+            assert!(AtomicDouble::try_from_ptr(std::ptr::null_mut::<Bar>()).is_none());
+            assert!(AtomicDouble::try_from_ptr(at(1)).is_none());
+            assert!(AtomicDouble::try_from_ptr(at(3)).is_none());
+
+            let a = AtomicDouble::try_from_ptr(at(0)).unwrap();
+            let b = AtomicDouble::try_from_ptr(at(2)).unwrap();
+            assert_eq!(a.load(SeqCst), Bar(1, 2));
+            assert_eq!(b.fetch_add(Bar(1, 1), SeqCst), Bar(3, 4));
+            assert_eq!(
+                a.compare_exchange(Bar(1, 2), Bar(7, 8), SeqCst, SeqCst),
+                Ok(Bar(1, 2))
+            );
+
+            // A smaller `T` still gets the 16 bytes behind it, and the bytes
+            // past its end are padding.
+            let c = AtomicDouble::try_from_ptr(at(4) as *mut u64).unwrap();
+            assert_eq!(c.swap(9, SeqCst), 5);
+        }
+        assert_eq!(buffer.0, [7, 8, 4, 5, 9, 6]);
+    }
+
+    #[test]
+    fn atomic_writes_keep_padding() {
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+
+        // Stores and swaps of a `u64` write its eight bytes, and the lent
+        // second word stays as it was.
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        let ptr = buffer.0.as_mut_ptr();
+        // The low byte of the `u64`, whichever end of the `u128` it is at.
+        let low_byte = unsafe { super::to_u128(&0xffu64) };
+        unsafe {
+            let a = AtomicDouble::try_from_ptr(ptr).unwrap();
+            for &order in &[Relaxed, Release, SeqCst] {
+                a.store(2, order);
+                a.store_single_writer(3, order);
+            }
+            assert_eq!(a.swap(4, SeqCst), 3);
+            assert_eq!(a.replace(5, AcqRel), 4);
+            assert_eq!(a.take(SeqCst), 5);
+            assert_eq!(a.try_store_bounded(6, SeqCst, 1), Ok(()));
+            assert_eq!(
+                a.compare_exchange_masked(0, 7, low_byte, SeqCst, SeqCst),
+                Ok(6)
+            );
+            crate::store_at(ptr, 8, Release);
+            assert_eq!(crate::load_at(ptr, Acquire), 8);
+        }
+        assert_eq!(buffer.0, [8, neighbour]);
+
+        // The fallback writes only the bytes of `T` in the first place.
+        #[cfg(feature = "fallback")]
+        super::with_fallback_forced(|| unsafe {
+            let a = AtomicDouble::try_from_ptr(ptr).unwrap();
+            a.store(9, Release);
+            assert_eq!(a.swap(10, SeqCst), 9);
+        });
+        #[cfg(feature = "fallback")]
+        assert_eq!(buffer.0, [10, neighbour]);
+    }
+
+    #[test]
+    fn atomic_rmw_keeps_padding() {
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+
+        // The second word is someone else's, lent as padding. Operations that
+        // carry or borrow past the `u64` must leave it alone.
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([0, neighbour]);
+        let ptr = buffer.0.as_mut_ptr();
+        unsafe {
+            let a = AtomicDouble::try_from_ptr(ptr).unwrap();
+            assert_eq!(a.fetch_sub(1, SeqCst), 0);
+            assert_eq!(a.fetch_add(1, SeqCst), u64::MAX);
+            assert_eq!(a.sub_fetch(1, SeqCst), u64::MAX);
+            assert_eq!(a.add_fetch(1, SeqCst), 0);
+            assert_eq!(a.fetch_add_explicit(u64::MAX, Relaxed, SeqCst), 0);
+            assert_eq!(a.try_fetch_add_bounded(1, SeqCst, 2), Ok(u64::MAX));
+            assert_eq!(a.checked_fetch_add(u64::MAX, SeqCst), Some(0));
+            assert_eq!(a.saturating_fetch_add(1, SeqCst), u64::MAX);
+            assert_eq!(a.checked_fetch_sub(u64::MAX, SeqCst), Some(u64::MAX));
+            assert_eq!(a.saturating_fetch_sub(1, SeqCst), 0);
+            assert_eq!(a.fetch_nand(0, SeqCst), 0);
+            assert_eq!(a.fetch_add_u64x2(1, SeqCst), u64::MAX);
+            assert_eq!(a.load(SeqCst), 0);
+        }
+        assert_eq!(buffer.0, [0, neighbour]);
+    }
+
+    #[test]
+    fn atomic_seqcst_wrappers() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let b = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.load_seqcst(), b.load(SeqCst));
+        a.store_seqcst(Bar(3, 4));
+        b.store(Bar(3, 4), SeqCst);
+        assert_eq!(a.load_seqcst(), b.load(SeqCst));
+        assert_eq!(a.swap_seqcst(Bar(5, 6)), b.swap(Bar(5, 6), SeqCst));
+        for (current, new) in [(Bar(0, 0), Bar(7, 8)), (Bar(5, 6), Bar(7, 8))] {
+            assert_eq!(
+                a.cas_seqcst(current, new),
+                b.compare_exchange(current, new, SeqCst, SeqCst)
+            );
+            assert_eq!(a.load_seqcst(), b.load(SeqCst));
+        }
+        assert_eq!(a.load_seqcst(), Bar(7, 8));
+    }
+
+    #[test]
+    fn atomic_load_unsync() {
+        let a = AtomicDouble::new(Bar(1, u64::MAX));
+        assert_eq!(unsafe { a.load_unsync() }, Bar(1, u64::MAX));
+        a.store(Bar(2, 3), SeqCst);
+        assert_eq!(unsafe { a.load_unsync() }, Bar(2, 3));
+
+        let b = AtomicDouble::new(SizeBar(4, 5));
+        assert_eq!(unsafe { b.load_unsync() }, SizeBar(4, 5));
+    }
+
+    #[test]
+    fn atomic_replace_take() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.replace(Bar(3, 4), SeqCst), Bar(1, 2));
+        assert_eq!(a.load(SeqCst), Bar(3, 4));
+        assert_eq!(a.take(AcqRel), Bar(3, 4));
+        assert_eq!(a.load(SeqCst), Bar::default());
+    }
+
+    #[test]
+    fn atomic_relaxed_load_not_torn() {
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let n = if cfg!(miri) { 10 } else { 10_000 };
+        let writer = {
+            let a = a.clone();
+            thread::spawn(move || {
+                for i in 1..=n {
+                    a.store(Bar(i, !i), Release);
+                }
+            })
+        };
+        // Both halves must always come from the same store.
+        loop {
+            let Bar(lo, hi) = a.load(Relaxed);
+            assert!(
+                lo == 0 && hi == 0 || hi == !lo,
+                "torn load {:?}",
+                Bar(lo, hi)
+            );
+            if lo == n {
+                break;
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    fn maybe_forced<R>(forced: bool, f: impl FnOnce() -> R) -> R {
+        #[cfg(any(
+            feature = "fallback",
+            feature = "seqlock-fallback",
+            feature = "mutex-fallback"
+        ))]
+        {
+            if forced {
+                return super::with_fallback_forced(f);
+            }
+        }
+        assert!(!forced, "there is no fallback to force");
+        f()
+    }
+
+    // Run once as is and once with the fallback forced, so that the fallback
+    // is covered on hardware with the native instructions too.
+    fn cas_fetch_add_suite(forced: bool) {
+        maybe_forced(forced, || {
+            if forced {
+                assert!(!AtomicDouble::<Bar>::is_lock_free());
+            }
+            let a = AtomicDouble::new(Bar(1, 2));
+            assert_eq!(
+                a.compare_exchange(Bar(1, 2), Bar(3, 4), SeqCst, SeqCst),
+                Ok(Bar(1, 2))
+            );
+            assert_eq!(
+                a.compare_exchange(Bar(1, 2), Bar(5, 6), SeqCst, Relaxed),
+                Err(Bar(3, 4))
+            );
+            assert_eq!(a.fetch_add(Bar(u64::MAX, 0), SeqCst), Bar(3, 4));
+            assert_eq!(a.load(SeqCst), Bar(2, 5));
+
+            let b = AtomicDouble::new(SizeBar(1, 2));
+            assert_eq!(
+                b.compare_exchange(SizeBar(1, 2), SizeBar(3, 4), AcqRel, Acquire),
+                Ok(SizeBar(1, 2))
+            );
+            assert_eq!(b.fetch_add(SizeBar(1, 1), Release), SizeBar(3, 4));
+            assert_eq!(b.load(Acquire), SizeBar(4, 5));
+        });
+
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                // Every thread touching `a` has to take the same path.
+                thread::spawn(move || {
+                    maybe_forced(forced, || {
+                        for _ in 0..n {
+                            a.fetch_add(Bar(1, 0), SeqCst);
+                        }
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(maybe_forced(forced, || a.load(SeqCst)), Bar(4 * n, 0));
+    }
+
+    #[test]
+    fn atomic_suite_native() {
+        cas_fetch_add_suite(false);
+    }
+
+    #[cfg(any(
+        feature = "fallback",
+        feature = "seqlock-fallback",
+        feature = "mutex-fallback"
+    ))]
+    #[test]
+    fn atomic_suite_forced_fallback() {
+        cas_fetch_add_suite(true);
+    }
+
+    #[cfg(not(feature = "portable-atomic"))]
+    #[test]
+    fn compare_exchange_relaxed_failure_is_strengthened() {
+        use std::sync::atomic::AtomicU64;
+
+        // The data is only ever written before the flag is set, so a failed
+        // exchange that sees the flag has to see the data too. That is only
+        // guaranteed because the failure is ordered like the `SeqCst`
+        // success, not just as the requested `Relaxed`.
+        struct Shared {
+            flag: AtomicDouble<Bar>,
+            data: AtomicU64,
+        }
+        let shared = Arc::new(Shared {
+            flag: AtomicDouble::new(Bar(0, 0)),
+            data: AtomicU64::new(0),
+        });
+        let n = if cfg!(miri) { 5 } else { 100 };
+        for round in 1..=n {
+            let writer = {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    shared.data.store(round, Relaxed);
+                    shared.flag.store(Bar(round, 0), Release);
+                })
+            };
+            loop {
+                match shared
+                    .flag
+                    .compare_exchange(Bar(0, 0), Bar(0, 0), SeqCst, Relaxed)
+                {
+                    Err(Bar(r, 0)) if r == round => break,
+                    _ => std::hint::spin_loop(),
+                }
+            }
+            assert_eq!(shared.data.load(Relaxed), round);
+            writer.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn atomic_compare_exchange_same_value() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        for &(success, failure) in &[(Relaxed, Relaxed), (Acquire, Acquire), (SeqCst, Relaxed)] {
+            assert_eq!(
+                a.compare_exchange(Bar(1, 2), Bar(1, 2), success, failure),
+                Ok(Bar(1, 2))
+            );
+            assert_eq!(
+                a.compare_exchange(Bar(3, 4), Bar(3, 4), success, failure),
+                Err(Bar(1, 2))
+            );
+        }
+        assert_eq!(a.load(SeqCst), Bar(1, 2));
+
+        // The zero padding of a smaller type takes no part.
+        let b = AtomicDouble::new(SizeBar(1, 2));
+        assert_eq!(
+            b.compare_exchange(SizeBar(1, 2), SizeBar(1, 2), Relaxed, Relaxed),
+            Ok(SizeBar(1, 2))
+        );
+
+        // An exchange of equal values still sees every store of another thread.
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let b = a.clone();
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let writer = thread::spawn(move || {
+            for i in 1..=n {
+                b.store(Bar(i, i), Release);
+            }
+        });
+        let mut seen = 0;
+        while seen < n {
+            match a.compare_exchange(
+                Bar(seen + 1, seen + 1),
+                Bar(seen + 1, seen + 1),
+                Acquire,
+                Acquire,
+            ) {
+                Ok(_) => seen += 1,
+                Err(Bar(x, y)) => {
+                    assert_eq!(x, y);
+                    seen = seen.max(x);
+                }
+            }
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn atomic_compare_exchange_masked() {
+        let a = AtomicDouble::new(0xabcd_0005u128);
+        let mask = 0xf | 0xff << 120;
+        // Only the masked bits differ from `current`.
+        assert_eq!(
+            a.compare_exchange_masked(0xabcd_0000, 0x1234_000f, mask, SeqCst, SeqCst),
+            Ok(0xabcd_0005)
+        );
+        // `new` is written in full, masked bits included.
+        assert_eq!(a.load(SeqCst), 0x1234_000f);
+        assert_eq!(
+            a.compare_exchange_masked(0x1234_0000 | 0x42 << 120, 7, mask, SeqCst, SeqCst),
+            Ok(0x1234_000f)
+        );
+        assert_eq!(a.load(SeqCst), 7);
+        // A difference outside the mask fails and reports the current value.
+        assert_eq!(
+            a.compare_exchange_masked(0x17, 9, mask, SeqCst, Relaxed),
+            Err(7)
+        );
+        assert_eq!(a.compare_exchange_masked(8, 9, 0, SeqCst, Relaxed), Err(7));
+        assert_eq!(a.load(SeqCst), 7);
+
+        // The zero padding of a smaller type is never compared.
+        let b = AtomicDouble::new(SizeBar(1, 2));
+        assert_eq!(
+            b.compare_exchange_masked(SizeBar(1, 2), SizeBar(3, 4), !0 << 64, SeqCst, SeqCst),
+            Ok(SizeBar(1, 2))
+        );
+        assert_eq!(b.load(SeqCst), SizeBar(3, 4));
+    }
+
+    #[test]
+    fn atomic_try_compare_exchange() {
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..100 {
+                        // A caller-side retry loop with its own backoff.
+                        let mut current = a.load(SeqCst);
+                        let mut spins = 1;
+                        while let Err(prev) =
+                            a.try_compare_exchange(current, Bar(current.0 + 1, 0), SeqCst, SeqCst)
+                        {
+                            current = prev;
+                            for _ in 0..spins {
+                                std::hint::spin_loop();
+                            }
+                            spins = (spins * 2).min(64);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), Bar(400, 0));
+        assert_eq!(
+            a.try_compare_exchange(Bar(0, 0), Bar(1, 1), SeqCst, SeqCst),
+            Err(Bar(400, 0))
+        );
+
+        // Padding that isn't zero is no failure for a caller to retry.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            let mut attempts = 0;
+            while let Err(prev) = b.try_compare_exchange(1, 2, SeqCst, SeqCst) {
+                // Only spurious failures are left, and they are rare.
+                assert_eq!(prev, 1);
+                attempts += 1;
+                assert!(attempts < 1000);
+            }
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct Flags(u64, u64);
+
+    #[test]
+    fn atomic_bitwise() {
+        let a = AtomicDouble::new(Flags(0b1100, 0b1010));
+        assert_eq!(
+            a.fetch_or(Flags(0b0011, 0b0101), SeqCst),
+            Flags(0b1100, 0b1010)
+        );
+        assert_eq!(a.load(SeqCst), Flags(0b1111, 0b1111));
+        assert_eq!(
+            a.fetch_and(Flags(0b0110, 0b1001), SeqCst),
+            Flags(0b1111, 0b1111)
+        );
+        assert_eq!(a.load(SeqCst), Flags(0b0110, 0b1001));
+        assert_eq!(
+            a.fetch_xor(Flags(0b1111, 0b0001), SeqCst),
+            Flags(0b0110, 0b1001)
+        );
+        assert_eq!(a.load(SeqCst), Flags(0b1001, 0b1000));
+
+        let b = AtomicDouble::new(Flags(0, 0));
+        b.fetch_or(Flags(u64::MAX, 1), SeqCst);
+        assert_eq!(b.load(SeqCst), Flags(u64::MAX, 1));
+        b.fetch_and(Flags(0, 0), SeqCst);
+        assert_eq!(b.load(SeqCst), Flags(0, 0));
+
+        // The zero padding of `val` doesn't clear the padding in memory.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([0b1100, neighbour]);
+        unsafe {
+            let c = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(c.fetch_and(0b0110, SeqCst), 0b1100);
+            assert_eq!(c.fetch_or(0b0001, SeqCst), 0b0100);
+            assert_eq!(c.fetch_xor(0b0011, SeqCst), 0b0101);
+            assert!(c.bit_clear(1, SeqCst));
+        }
+        assert_eq!(buffer.0, [0b0100, neighbour]);
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct Wide(u128);
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    #[repr(transparent)]
+    struct W(u128);
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    #[repr(C)]
+    struct Pair(u64, u64);
+
+    const _: () = AtomicDouble::<W>::assert_layout();
+    const _: () = AtomicDouble::<Pair>::assert_layout();
+
+    // Runs the same operations on a `T` and on a plain `u128` and checks that
+    // both see the same bits.
+    fn check_layout<T: Copy + PartialEq + std::fmt::Debug>() {
+        let t = |v: u128| unsafe { std::mem::transmute_copy::<u128, T>(&v) };
+        let x = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128;
+        let a = AtomicDouble::new(t(x));
+        let b = AtomicDouble::new(x);
+        assert_eq!(
+            AtomicDouble::<T>::is_lock_free(),
+            AtomicDouble::<u128>::is_lock_free()
+        );
+        assert_eq!(a.load(SeqCst), t(b.load(SeqCst)));
+        assert_eq!(a.swap(t(!x), SeqCst), t(b.swap(!x, SeqCst)));
+        assert_eq!(
+            a.compare_exchange(t(!x), t(x), SeqCst, SeqCst),
+            b.compare_exchange(!x, x, SeqCst, SeqCst).map(t).map_err(t)
+        );
+        assert_eq!(
+            a.compare_exchange(t(!x), t(x), SeqCst, SeqCst),
+            b.compare_exchange(!x, x, SeqCst, SeqCst).map(t).map_err(t)
+        );
+        assert_eq!(
+            a.fetch_add(t(u64::MAX as u128), SeqCst),
+            t(b.fetch_add(u64::MAX as u128, SeqCst))
+        );
+        assert_eq!(
+            a.fetch_sub(t(1 << 100), SeqCst),
+            t(b.fetch_sub(1 << 100, SeqCst))
+        );
+        assert_eq!(a.fetch_xor(t(x), SeqCst), t(b.fetch_xor(x, SeqCst)));
+        assert_eq!(a.fetch_max(t(x), SeqCst), t(b.fetch_max(x, SeqCst)));
+        assert_eq!(a.fetch_min(t(1), SeqCst), t(b.fetch_min(1, SeqCst)));
+        assert_eq!(a.load(SeqCst), t(b.load(SeqCst)));
+    }
+
+    #[test]
+    fn atomic_layout_transparent_and_repr_c() {
+        check_layout::<W>();
+        check_layout::<Pair>();
+
+        // On little-endian targets the first field is the low word.
+        if cfg!(target_endian = "little") {
+            let a = AtomicDouble::new(Pair(u64::MAX, 0));
+            a.fetch_add(Pair(1, 0), SeqCst);
+            assert_eq!(a.load(SeqCst), Pair(0, 1));
+        }
+    }
+
+    #[cfg(all(target_arch = "wasm32", feature = "fallback"))]
+    #[test]
+    fn wasm32_fallback() {
+        assert!(!AtomicDouble::<Bar>::is_lock_free());
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.load(SeqCst), Bar(1, 2));
+        a.store(Bar(3, 4), SeqCst);
+        assert_eq!(a.load(SeqCst), Bar(3, 4));
+        assert_eq!(
+            a.compare_exchange(Bar(3, 4), Bar(5, 6), SeqCst, SeqCst),
+            Ok(Bar(3, 4))
+        );
+        assert_eq!(
+            a.compare_exchange(Bar(3, 4), Bar(7, 8), SeqCst, SeqCst),
+            Err(Bar(5, 6))
+        );
+        assert_eq!(a.load(SeqCst), Bar(5, 6));
+    }
+
+    #[test]
+    fn atomic_bit_set_clear() {
+        let a = AtomicDouble::new(Bar(0, 0));
+        for &bit in &[0, 63, 127] {
+            assert!(!a.bit_set(bit, SeqCst));
+            assert!(a.bit_set(bit, SeqCst));
+        }
+        assert_eq!(a.load(SeqCst), Bar(1 | 1 << 63, 1 << 63));
+        for &bit in &[0, 63, 127] {
+            assert!(a.bit_clear(bit, SeqCst));
+            assert!(!a.bit_clear(bit, SeqCst));
+        }
+        assert_eq!(a.load(SeqCst), Bar(0, 0));
+    }
+
+    #[test]
+    #[should_panic(expected = "bit index out of range")]
+    fn atomic_bit_set_out_of_range() {
+        AtomicDouble::new(Bar(0, 0)).bit_set(128, SeqCst);
+    }
+
+    #[test]
+    fn atomic_nand() {
+        let x = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128;
+        let y = 0xffff_0000_ffff_0000_0f0f_f0f0_0000_ffffu128;
+        let a = AtomicDouble::new(Wide(x));
+        assert_eq!(a.fetch_nand(Wide(y), SeqCst), Wide(x));
+        assert_eq!(a.load(SeqCst), Wide(!(x & y)));
+
+        let b = AtomicDouble::new(Wide(0));
+        assert_eq!(b.fetch_nand(Wide(y), SeqCst), Wide(0));
+        assert_eq!(b.load(SeqCst), Wide(u128::MAX));
+    }
+
+    #[test]
+    fn atomic_max_min() {
+        let a = AtomicDouble::new(Wide(5));
+        assert_eq!(a.fetch_max(Wide(3), SeqCst), Wide(5));
+        assert_eq!(a.load(SeqCst), Wide(5));
+        assert_eq!(a.fetch_max(Wide(u128::MAX), SeqCst), Wide(5));
+        assert_eq!(a.load(SeqCst), Wide(u128::MAX));
+        assert_eq!(a.fetch_min(Wide(7), SeqCst), Wide(u128::MAX));
+        assert_eq!(a.load(SeqCst), Wide(7));
+        assert_eq!(a.fetch_min(Wide(0), SeqCst), Wide(7));
+        assert_eq!(a.load(SeqCst), Wide(0));
+
+        // Two-field structs compare with the last field as most significant.
+        let b = AtomicDouble::new(Bar(u64::MAX, 0));
+        assert_eq!(b.fetch_max(Bar(0, 1), SeqCst), Bar(u64::MAX, 0));
+        assert_eq!(b.load(SeqCst), Bar(0, 1));
+
+        // The padding past a smaller type is kept as it was.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([5, neighbour]);
+        unsafe {
+            let c = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(c.fetch_max(9, SeqCst), 5);
+            assert_eq!(c.fetch_min(3, SeqCst), 9);
+            assert_eq!(c.fetch_max_signed(7, SeqCst), 3);
+            assert_eq!(c.fetch_min_signed(2, SeqCst), 7);
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[test]
+    fn atomic_max_min_changed() {
+        let a = AtomicDouble::new(Wide(5));
+        assert_eq!(a.fetch_max_changed(Wide(9), SeqCst), (Wide(5), true));
+        assert_eq!(a.fetch_max_changed(Wide(7), SeqCst), (Wide(9), false));
+        assert_eq!(a.fetch_max_changed(Wide(9), SeqCst), (Wide(9), false));
+        assert_eq!(a.load(SeqCst), Wide(9));
+        assert_eq!(a.fetch_min_changed(Wide(2), SeqCst), (Wide(9), true));
+        assert_eq!(a.fetch_min_changed(Wide(4), SeqCst), (Wide(2), false));
+        assert_eq!(a.load(SeqCst), Wide(2));
+
+        // Only one thread sets each new maximum.
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    (1..=n)
+                        .filter(|&i| a.fetch_max_changed(Bar(0, i), AcqRel).1)
+                        .count()
+                })
+            })
+            .collect();
+        let changes: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert!((1..=n as usize).contains(&changes));
+        assert_eq!(a.load(SeqCst), Bar(0, n));
+
+        // Padding that isn't zero is kept, and doesn't count as a change.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([5, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(b.fetch_max_changed(9, SeqCst), (5, true));
+            assert_eq!(b.fetch_max_changed(7, SeqCst), (9, false));
+            assert_eq!(b.fetch_min_changed(2, SeqCst), (9, true));
+            assert_eq!(b.fetch_min_changed(2, SeqCst), (2, false));
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
+    }
+
+    #[test]
+    fn atomic_max_min_signed() {
+        let min = Wide(i128::MIN as u128);
+        let max = Wide(i128::MAX as u128);
+
+        let a = AtomicDouble::new(max);
+        assert_eq!(a.fetch_max_signed(min, SeqCst), max);
+        assert_eq!(a.load(SeqCst), max);
+        assert_eq!(a.fetch_min_signed(min, SeqCst), max);
+        assert_eq!(a.load(SeqCst), min);
+        assert_eq!(a.fetch_min_signed(Wide(0), SeqCst), min);
+        assert_eq!(a.load(SeqCst), min);
+        assert_eq!(a.fetch_max_signed(Wide(-1i128 as u128), SeqCst), min);
+        assert_eq!(a.load(SeqCst), Wide(-1i128 as u128));
+
+        // The same bit patterns order the other way round when unsigned.
+        let b = AtomicDouble::new(max);
+        assert_eq!(b.fetch_max(min, SeqCst), max);
+        assert_eq!(b.load(SeqCst), min);
+    }
+
+    #[test]
+    fn atomic_rotate() {
+        let x = 0x8000_0000_0000_0001_0123_4567_89ab_cdefu128;
+        let a = AtomicDouble::new(x);
+        assert_eq!(a.fetch_rotate_left(4, SeqCst), x);
+        assert_eq!(a.load(SeqCst), x.rotate_left(4));
+        assert_eq!(a.fetch_rotate_right(68, SeqCst), x.rotate_left(4));
+        assert_eq!(a.load(SeqCst), x.rotate_right(64));
+        // `n` is taken modulo 128.
+        assert_eq!(a.fetch_rotate_left(128 + 64, Relaxed), x.rotate_right(64));
+        assert_eq!(a.load(SeqCst), x);
+        a.fetch_rotate_right(u32::MAX, SeqCst);
+        assert_eq!(a.load(SeqCst), x.rotate_right(u32::MAX));
+    }
+
+    #[test]
+    #[should_panic(expected = "requires a type of exactly 16 bytes")]
+    fn atomic_rotate_small() {
+        AtomicDouble::new(1u64).fetch_rotate_left(1, SeqCst);
+    }
+
+    #[test]
+    fn atomic_add_f64x2() {
+        let a = AtomicDouble::new([1.5f64, -2.0]);
+        assert_eq!(a.fetch_add_f64x2([0.25, 4.0], SeqCst), [1.5, -2.0]);
+        assert_eq!(a.load(SeqCst), [1.75, 2.0]);
+        assert_eq!(a.fetch_sub_f64x2([1.75, 0.5], SeqCst), [1.75, 2.0]);
+        assert_eq!(a.load(SeqCst), [0.0, 1.5]);
+
+        let a = Arc::new(AtomicDouble::new([0.0f64, 0.0]));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        a.fetch_add_f64x2([0.1, -0.3], Relaxed);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // The additions land in any order, so only the sum is checked.
+        let [x, y] = a.load(SeqCst);
+        let total = (4 * n) as f64;
+        assert!((x - total * 0.1).abs() < 1e-9 * total);
+        assert!((y + total * 0.3).abs() < 1e-9 * total);
+    }
+
+    #[test]
+    #[should_panic(expected = "requires a type of exactly 16 bytes")]
+    fn atomic_add_f64x2_small() {
+        AtomicDouble::new(0.0f64).fetch_add_f64x2([1.0, 1.0], SeqCst);
+    }
+
+    // The bytes of `T` are added as a native-endian `u128`, on big-endian
+    // targets as well.
+    #[test]
+    fn atomic_add_native_endian() {
+        use std::convert::TryInto;
+
+        #[cfg(target_endian = "little")]
+        let (from, to, from_u64) = (u128::from_le_bytes, u128::to_le_bytes, u64::from_le_bytes);
+        #[cfg(target_endian = "big")]
+        let (from, to, from_u64) = (u128::from_be_bytes, u128::to_be_bytes, u64::from_be_bytes);
+
+        let x = [
+            0xff, 0xff, 0, 0, 0, 0, 0, 0, 0xff, 0, 0, 0, 0, 0, 0xff, 0xff,
+        ];
+        let y = [1, 0, 0, 0, 0, 0, 0, 0xff, 1, 0, 0, 0, 0, 0, 0, 1];
+        let a = AtomicDouble::new(x);
+        assert_eq!(a.fetch_add(y, SeqCst), x);
+        assert_eq!(a.load(SeqCst), to(from(x).wrapping_add(from(y))));
+        a.store(x, SeqCst);
+        a.fetch_sub(y, SeqCst);
+        assert_eq!(a.load(SeqCst), to(from(x).wrapping_sub(from(y))));
+        a.store(x, SeqCst);
+        a.fetch_add_u64x2(y, SeqCst);
+        let lanes = |v: [u8; 16]| {
+            let (first, last) = v.split_at(8);
+            [
+                from_u64(first.try_into().unwrap()),
+                from_u64(last.try_into().unwrap()),
+            ]
+        };
+        let [x0, x1] = lanes(x);
+        let [y0, y1] = lanes(y);
+        assert_eq!(
+            lanes(a.load(SeqCst)),
+            [x0.wrapping_add(y0), x1.wrapping_add(y1)]
+        );
+
+        // A smaller `T` wraps within its own bytes.
+        let b = AtomicDouble::new([0xffu8; 8]);
+        b.fetch_add([1, 0, 0, 0, 0, 0, 0, 1], SeqCst);
+        let expected = from_u64([0xff; 8]).wrapping_add(from_u64([1, 0, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(from_u64(b.load(SeqCst)), expected);
+    }
+
+    #[test]
+    fn atomic_add_u64x2() {
+        let a = AtomicDouble::new(Bar(u64::MAX, 5));
+        assert_eq!(a.fetch_add_u64x2(Bar(1, 0), SeqCst), Bar(u64::MAX, 5));
+        assert_eq!(a.load(SeqCst), Bar(0, 5));
+        assert_eq!(a.fetch_add_u64x2(Bar(3, u64::MAX), SeqCst), Bar(0, 5));
+        assert_eq!(a.load(SeqCst), Bar(3, 4));
+
+        // The whole-word add carries from the low lane into the high lane.
+        let b = AtomicDouble::new(Bar(u64::MAX, 5));
+        b.fetch_add(Bar(1, 0), SeqCst);
+        assert_eq!(b.load(SeqCst), Bar(0, 6));
+    }
+
+    #[cfg(all(target_arch = "x86", not(miri)))]
+    #[test]
+    fn x86_cmpxchg8b_tagged_ptr() {
+        use crate::TaggedPtr;
+
+        // A pointer and a tag together are only 8 bytes on 32-bit x86.
+        assert_eq!(std::mem::size_of::<TaggedPtr<i32>>(), 8);
+        assert!(AtomicDouble::<TaggedPtr<i32>>::is_lock_free());
+        assert!(!AtomicDouble::<Bar>::is_lock_free());
+
+        let mut x = 5;
+        let mut y = 10;
+        let a = AtomicDouble::new(TaggedPtr::new(&mut x as *mut i32, 1));
+        let current = a.load(SeqCst);
+        assert_eq!(
+            a.compare_exchange(current, TaggedPtr::new(&mut y, 2), SeqCst, SeqCst),
+            Ok(current)
+        );
+        assert_eq!(
+            a.compare_exchange(current, TaggedPtr::new(&mut y, 3), SeqCst, SeqCst),
+            Err(TaggedPtr::new(&mut y, 2))
+        );
+        assert_eq!(a.swap(current, SeqCst), TaggedPtr::new(&mut y, 2));
+        assert_eq!(a.load(SeqCst), current);
+    }
+
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    #[test]
+    fn aarch64_ldxp_stxp() {
+        use crate::Ordering::{Acquire, Relaxed, Release};
+
+        let mut x: u128 = 1;
+        let dst: *mut u128 = &mut x;
+        unsafe {
+            for &order in &[Relaxed, Acquire, Release, SeqCst] {
+                assert_eq!(super::aarch64_ldxp_stxp(dst, 1, 2, order, false), Ok(1));
+                assert_eq!(super::aarch64_ldxp_stxp(dst, 1, 3, order, false), Err(2));
+                loop {
+                    match super::aarch64_ldxp_stxp(dst, 2, 1, order, true) {
+                        Ok(prev) => break assert_eq!(prev, 2),
+                        Err(prev) => assert_eq!(prev, 2),
+                    }
+                }
+            }
+        }
+        assert_eq!(x, 1);
+    }
+
+    #[cfg(all(target_arch = "aarch64", not(miri)))]
+    #[test]
+    fn aarch64_casp() {
+        if !super::has_lse() {
+            return;
+        }
+        let mut x: u128 = 1 << 64 | 1;
+        let dst: *mut u128 = &mut x;
+        unsafe {
+            assert_eq!(
+                super::aarch64_casp(dst, 1 << 64 | 1, 2, SeqCst),
+                1 << 64 | 1
+            );
+            assert_eq!(super::aarch64_casp(dst, 1, 3, SeqCst), 2);
+        }
+        assert_eq!(x, 2);
+    }
+
+    #[cfg(all(target_arch = "riscv64", target_feature = "zacas", not(miri)))]
+    #[test]
+    fn riscv64_amocas_q() {
+        use crate::Ordering::{AcqRel, Acquire, Relaxed, Release};
+
+        let mut x: u128 = 1 << 64 | 1;
+        let dst: *mut u128 = &mut x;
+        unsafe {
+            for &(success, failure) in &[
+                (Relaxed, Relaxed),
+                (Acquire, Acquire),
+                (Release, Relaxed),
+                (AcqRel, Acquire),
+                (SeqCst, SeqCst),
+            ] {
+                assert_eq!(
+                    super::riscv64_compare_exchange(dst, 1 << 64 | 1, 2, success, failure),
+                    Ok(1 << 64 | 1)
+                );
+                assert_eq!(
+                    super::riscv64_compare_exchange(dst, 1, 3, success, failure),
+                    Err(2)
+                );
+                assert_eq!(
+                    super::riscv64_compare_exchange(dst, 2, 1 << 64 | 1, success, failure),
+                    Ok(2)
+                );
             }
         }
+        assert_eq!(x, 1 << 64 | 1);
     }
 
-    #[cfg(feature = "fallback")]
-    return fallback::atomic_compare_exchange(dst, current, new);
-    #[cfg(not(feature = "fallback"))]
-    panic!("Atomic operations for type `{}` are not available as the `fallback` feature of the `atomicdouble` crate is disabled.", core::any::type_name::<T>());
-}
+    #[test]
+    fn atomic_fetch_update() {
+        let x = Box::into_raw(Box::new(5));
+        let a = AtomicDouble::new(Node {
+            head_ptr: None,
+            head_count: 0,
+        });
+        let bump = |node: Node| {
+            node.head_ptr?;
+            Some(Node {
+                head_count: node.head_count + 1,
+                ..node
+            })
+        };
 
-#[inline]
-unsafe fn map_result<T, U>(r: Result<T, T>) -> Result<U, U> {
-    match r {
-        Ok(x) => Ok(mem::transmute_copy(&x)),
-        Err(x) => Err(mem::transmute_copy(&x)),
+        assert_eq!(
+            a.fetch_update(SeqCst, SeqCst, bump),
+            Err(Node {
+                head_ptr: None,
+                head_count: 0,
+            })
+        );
+
+        let node = Node {
+            head_ptr: NonNull::new(x),
+            head_count: 0,
+        };
+        a.store(node, SeqCst);
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, bump), Ok(node));
+        assert_eq!(a.fetch_update(SeqCst, SeqCst, bump).unwrap().head_count, 1);
+        assert_eq!(a.load(SeqCst).head_count, 2);
+
+        unsafe { drop(Box::from_raw(x)) };
+
+        // The value `f` is given has no padding, so the padding in memory must
+        // not keep failing the exchange.
+        #[repr(C, align(16))]
+        struct Buffer([u64; 2]);
+        let neighbour = 0xaaaa_5555_aaaa_5555;
+        let mut buffer = Buffer([1, neighbour]);
+        unsafe {
+            let b = AtomicDouble::try_from_ptr(buffer.0.as_mut_ptr()).unwrap();
+            assert_eq!(b.fetch_update(SeqCst, SeqCst, |v| Some(v + 1)), Ok(1));
+        }
+        assert_eq!(buffer.0, [2, neighbour]);
     }
-}
 
-#[inline]
-fn strongest_failure_ordering(order: Ordering) -> Ordering {
-    match order {
-        Ordering::Release => Ordering::Relaxed,
-        Ordering::Relaxed => Ordering::Relaxed,
-        Ordering::SeqCst => Ordering::SeqCst,
-        Ordering::Acquire => Ordering::Acquire,
-        Ordering::AcqRel => Ordering::Acquire,
-        _ => Ordering::SeqCst,
+    #[test]
+    #[should_panic(expected = "there is no such thing as a release load")]
+    fn load_release() {
+        AtomicDouble::new(Bar(0, 0)).load(Release);
     }
-}
 
-#[inline]
-pub fn atomic_is_lock_free<T>() -> bool {
-    #[cfg(target_arch = "x86_64")]
-    {
-        if is_x86_feature_detected!("cmpxchg16b") && mem::size_of::<T>() == 16 {
-            return true;
-        }
+    #[test]
+    #[should_panic(expected = "there is no such thing as an acquire/release load")]
+    fn load_acqrel() {
+        AtomicDouble::new(Bar(0, 0)).load(AcqRel);
     }
-    false
-}
-#[inline]
-pub unsafe fn atomic_load<T>(dst: *mut T, order: Ordering) -> T {
-    let fail_order = strongest_failure_ordering(order);
-    let res = compare_exchange_intrinsic::<T>(dst as *mut u128, 0, 0, order, fail_order);
-    match res {
-        Ok(load_val) => mem::transmute_copy(&load_val),
-        Err(load_val) => mem::transmute_copy(&load_val),
+
+    #[test]
+    #[should_panic(expected = "there is no such thing as an acquire store")]
+    fn store_acquire() {
+        AtomicDouble::new(Bar(0, 0)).store(Bar(1, 1), Acquire);
     }
-}
-#[inline]
-pub unsafe fn atomic_store<T>(dst: *mut T, val: T, order: Ordering) {
-    let mut res = Err(0);
-    let mut current: u128 = mem::transmute_copy(&val);
-    let new: u128 = mem::transmute_copy(&val);
-    let fail_order = strongest_failure_ordering(order);
-    while res.is_err() {
-        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
-        match res {
-            Ok(_) => {}
-            Err(load_val) => current = load_val,
-        };
+
+    #[test]
+    #[should_panic(expected = "there is no such thing as an acquire/release store")]
+    fn store_acqrel() {
+        AtomicDouble::new(Bar(0, 0)).store(Bar(1, 1), AcqRel);
     }
-}
 
-#[inline]
-pub unsafe fn atomic_compare_exchange<T>(
-    dst: *mut T,
-    current: T,
-    new: T,
-    success: Ordering,
-    failure: Ordering,
-) -> Result<T, T> {
-    map_result(compare_exchange_intrinsic::<T>(
-        dst as *mut u128,
-        mem::transmute_copy(&current),
-        mem::transmute_copy(&new),
-        success,
-        failure,
-    ))
-}
-#[inline]
-pub unsafe fn atomic_add<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
-    let mut res: Result<u128, u128> = Err(0);
-    let mut current: u128 = 0;
-    let mut new: u128 = mem::transmute_copy(&val);
-    let fail_order = strongest_failure_ordering(order);
-    while res.is_err() {
-        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
-        match res {
-            Ok(load_val) => {
-                return mem::transmute_copy(&load_val);
-            }
-            Err(load_val) => {
-                current = load_val;
-                new = load_val.wrapping_add(mem::transmute_copy(&val));
-            }
-        };
+    #[test]
+    #[should_panic(expected = "there is no such thing as a release failure ordering")]
+    fn compare_exchange_failure_release() {
+        let _ =
+            AtomicDouble::new(Bar(0, 0)).compare_exchange(Bar(0, 0), Bar(1, 1), SeqCst, Release);
     }
-    val
-}
-#[inline]
-pub unsafe fn atomic_sub<T: Copy>(dst: *mut T, val: T, order: Ordering) -> T {
-    let mut res = Err(0);
-    let mut current: u128 = mem::transmute_copy(&val);
-    let mut new: u128 = 0;
-    let fail_order = strongest_failure_ordering(order);
-    while res.is_err() {
-        res = compare_exchange_intrinsic::<T>(dst as *mut u128, current, new, order, fail_order);
-        match res {
-            Ok(load_val) => {
-                return mem::transmute_copy(&load_val);
-            }
-            Err(load_val) => {
-                current = load_val;
-                new = load_val.wrapping_sub(mem::transmute_copy(&val));
-            }
-        };
+
+    #[test]
+    #[should_panic(expected = "there is no such thing as an acquire/release failure ordering")]
+    fn compare_exchange_failure_acqrel() {
+        let _ = AtomicDouble::new(Bar(0, 0)).compare_exchange(Bar(0, 0), Bar(1, 1), SeqCst, AcqRel);
     }
-    val
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::AtomicDouble;
-    use crate::Ordering::SeqCst;
-    use std::boxed::Box;
-    use std::ptr::NonNull;
+    #[test]
+    #[should_panic(expected = "a failure ordering can't be stronger than a success ordering")]
+    fn compare_exchange_failure_stronger() {
+        let _ =
+            AtomicDouble::new(Bar(0, 0)).compare_exchange(Bar(0, 0), Bar(1, 1), Acquire, SeqCst);
+    }
 
-    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-    struct Bar(u64, u64);
+    #[test]
+    #[should_panic(expected = "a failure ordering can't be stronger than a success ordering")]
+    fn compare_exchange_weak_failure_stronger() {
+        let _ = AtomicDouble::new(Bar(0, 0)).compare_exchange_weak(
+            Bar(0, 0),
+            Bar(1, 1),
+            Release,
+            Acquire,
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "a failure ordering can't be stronger than a success ordering")]
+    fn compare_exchange_relaxed_success_seqcst_failure() {
+        let _ =
+            AtomicDouble::new(Bar(0, 0)).compare_exchange(Bar(0, 0), Bar(1, 1), Relaxed, SeqCst);
+    }
+
+    #[test]
+    #[should_panic(expected = "a failure ordering can't be stronger than a success ordering")]
+    fn compare_exchange_same_value_failure_stronger() {
+        // Taken as a load when `new` equals `current`, but still checked.
+        let _ =
+            AtomicDouble::new(Bar(0, 0)).compare_exchange(Bar(0, 0), Bar(0, 0), Acquire, SeqCst);
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no such thing as a release failure ordering")]
+    fn compare_exchange_masked_failure_release() {
+        let _ = AtomicDouble::new(0u128).compare_exchange_masked(0, 1, 0xf, AcqRel, Release);
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no such thing as an acquire/release failure ordering")]
+    fn fetch_update_failure_acqrel() {
+        let _ = AtomicDouble::new(0u128).fetch_update(AcqRel, AcqRel, |_| None);
+    }
+
+    #[test]
+    #[should_panic(expected = "a failure ordering can't be stronger than a success ordering")]
+    fn fetch_update_failure_stronger() {
+        // `f` never asks for a store, which must not hide the bad pair.
+        let _ = AtomicDouble::new(0u128).fetch_update(Relaxed, Acquire, |_| None);
+    }
+
+    #[test]
+    fn atomic_contended() {
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        a.fetch_add(Bar(1, 1), SeqCst);
+                        a.fetch_sub(Bar(0, 1), SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), Bar(8 * n, 0));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn atomic_serde() {
+        let a = AtomicDouble::new(Bar(1, u64::MAX));
+        let json = serde_json::to_string(&a).unwrap();
+        assert_eq!(json, "[1,18446744073709551615]");
+        let b: AtomicDouble<Bar> = serde_json::from_str(&json).unwrap();
+        assert_eq!(b.load(SeqCst), Bar(1, u64::MAX));
+    }
+
+    #[test]
+    fn atomic_as_ptr() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a.as_ptr() as usize % 16, 0);
+        assert_eq!(a.as_ptr(), a.as_mut_ptr() as *const Bar);
+        unsafe {
+            assert_eq!(
+                super::atomic_swap(a.as_mut_ptr(), Bar(3, 4), SeqCst),
+                Bar(1, 2)
+            );
+        }
+        assert_eq!(a.load(SeqCst), Bar(3, 4));
+    }
 
     #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
-    struct SizeBar(u32, u32);
+    #[repr(align(16))]
+    struct AlignedBar(u64, u64);
 
-    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
-    struct Node {
-        head_ptr: Option<NonNull<i32>>,
-        head_count: usize,
+    #[test]
+    fn atomic_from_mut() {
+        let mut x = AlignedBar(1, 2);
+        {
+            let a = AtomicDouble::from_mut(&mut x);
+            assert_eq!(a.swap(AlignedBar(3, 4), SeqCst), AlignedBar(1, 2));
+            a.fetch_add(AlignedBar(1, 1), SeqCst);
+        }
+        assert_eq!(x, AlignedBar(4, 5));
     }
 
+    // Deliberately builds a misaligned reference, so it can't run under Miri.
+    #[cfg(all(debug_assertions, not(miri)))]
     #[test]
-    fn atomic_bar() {
-        let a: AtomicDouble<Bar> = AtomicDouble::default();
-        assert!(AtomicDouble::<Bar>::is_lock_free());
-        a.load(SeqCst);
-        assert_eq!(format!("{:?}", a), "AtomicDouble(Bar(0, 0))");
-        assert_eq!(a.load(SeqCst), Bar(0, 0));
-        a.store(Bar(1, 1), SeqCst);
+    #[should_panic(expected = "not aligned to 16 bytes")]
+    #[allow(clippy::transmute_ptr_to_ref)]
+    fn atomic_misaligned() {
+        let buf = [AtomicDouble::new(0u64), AtomicDouble::new(0u64)];
+        unsafe {
+            // A transmute rather than a deref, which would be caught by the
+            // compiler's own alignment check and abort instead of panicking.
+            let misaligned: &AtomicDouble<u64> =
+                std::mem::transmute((buf.as_ptr() as *const u8).add(8));
+            misaligned.load(SeqCst);
+        }
+    }
+
+    #[test]
+    fn atomic_get_mut_slice() {
+        let mut slots = [
+            AtomicDouble::new(Bar(0, 0)),
+            AtomicDouble::new(Bar(0, 0)),
+            AtomicDouble::new(Bar(0, 0)),
+            AtomicDouble::new(Bar(0, 0)),
+        ];
+        for (i, slot) in AtomicDouble::get_mut_slice(&mut slots)
+            .iter_mut()
+            .enumerate()
+        {
+            *slot = Bar(i as u64, !(i as u64));
+        }
+        for (i, slot) in slots.iter().enumerate() {
+            assert_eq!(slot.load(SeqCst), Bar(i as u64, !(i as u64)));
+        }
+    }
+
+    #[test]
+    fn atomic_from_mut_slice() {
+        let mut values = [AlignedBar(1, 2), AlignedBar(3, 4)];
+        for atomic in AtomicDouble::from_mut_slice(&mut values) {
+            atomic.fetch_add(AlignedBar(1, 0), SeqCst);
+        }
+        assert_eq!(values, [AlignedBar(2, 2), AlignedBar(4, 4)]);
+    }
+
+    #[test]
+    #[should_panic(expected = "requires a type of exactly 16 bytes")]
+    fn atomic_get_mut_slice_small() {
+        AtomicDouble::get_mut_slice(&mut [AtomicDouble::new(SizeBar(1, 2))]);
+    }
+
+    #[test]
+    #[should_panic(expected = "requires a type aligned to at least 16 bytes")]
+    fn atomic_from_mut_unaligned() {
+        let mut x = Bar(1, 2);
+        AtomicDouble::from_mut(&mut x);
+    }
+
+    #[cfg(miri)]
+    #[test]
+    fn miri_compare_exchange_threads() {
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let handles: Vec<_> = (1..3)
+            .map(|i| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    a.compare_exchange(Bar(0, 0), Bar(i, i), SeqCst, SeqCst)
+                        .is_ok()
+                })
+            })
+            .collect();
+        let wins = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|&won| won)
+            .count();
+        assert_eq!(wins, 1);
+        assert_ne!(a.load(SeqCst), Bar(0, 0));
+    }
+
+    #[test]
+    fn atomic_compare_exchange_bool() {
+        let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+        let handles: Vec<_> = (1..3)
+            .map(|i| {
+                let a = a.clone();
+                thread::spawn(move || a.compare_exchange_bool(Bar(0, 0), Bar(i, i), SeqCst, SeqCst))
+            })
+            .collect();
+        let wins: Vec<_> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert!(wins[0] ^ wins[1]);
+        let winner = if wins[0] { 1 } else { 2 };
+        assert_eq!(a.load(SeqCst), Bar(winner, winner));
+    }
+
+    #[test]
+    fn atomic_eq() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let b = AtomicDouble::new(Bar(1, 2));
+        assert_eq!(a, b);
+        b.store(Bar(3, 4), SeqCst);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn atomic_display() {
+        let a = AtomicDouble::new(42u128);
+        assert_eq!(format!("{}", a), "42");
+        assert_eq!(format!("{:>4}", a), "  42");
+    }
+
+    #[test]
+    fn atomic_pointer() {
+        let a = AtomicDouble::new(Bar(1, 2));
+        let s = format!("{:p}", a);
+        assert!(s.starts_with("0x"));
+        assert_eq!(usize::from_str_radix(&s[2..], 16), Ok(a.as_ptr() as usize));
+        assert_eq!(format!("{:p}", a), format!("{:p}", &a));
+    }
+
+    #[test]
+    fn atomic_from() {
+        let a: AtomicDouble<Bar> = Bar(1, 2).into();
+        assert_eq!(a.load(SeqCst), Bar(1, 2));
+    }
+
+    #[test]
+    fn atomic_add_sub_fetch() {
+        let a = AtomicDouble::new(u128::MAX - 1);
+        assert_eq!(a.add_fetch(3, SeqCst), (u128::MAX - 1).wrapping_add(3));
+        assert_eq!(a.load(SeqCst), 1);
+        assert_eq!(a.sub_fetch(2, SeqCst), 1u128.wrapping_sub(2));
+        assert_eq!(a.load(SeqCst), u128::MAX);
+
+        let b = AtomicDouble::new(Bar(u64::MAX, 1));
+        assert_eq!(b.add_fetch(Bar(1, 0), SeqCst), Bar(0, 2));
+        assert_eq!(b.sub_fetch(Bar(1, 0), SeqCst), Bar(u64::MAX, 1));
+    }
+
+    #[test]
+    fn atomic_checked_add_sub() {
+        let a = AtomicDouble::new(u128::MAX - 1);
+        assert_eq!(a.checked_fetch_add(1, SeqCst), Some(u128::MAX - 1));
+        assert_eq!(a.checked_fetch_add(1, SeqCst), None);
+        assert_eq!(a.load(SeqCst), u128::MAX);
+        assert_eq!(a.checked_fetch_sub(u128::MAX, SeqCst), Some(u128::MAX));
+        assert_eq!(a.checked_fetch_sub(1, SeqCst), None);
+        assert_eq!(a.load(SeqCst), 0);
+
+        // The low lane carries into the high lane; only the top of the high
+        // lane overflows.
+        let b = AtomicDouble::new(Bar(u64::MAX, u64::MAX));
+        assert_eq!(b.checked_fetch_add(Bar(1, 0), SeqCst), None);
+        assert_eq!(b.load(SeqCst), Bar(u64::MAX, u64::MAX));
         assert_eq!(
-            a.compare_exchange(Bar(5, 5), Bar(45, 45), SeqCst, SeqCst),
-            Err(Bar(1, 1))
+            b.checked_fetch_sub(Bar(0, 1), SeqCst),
+            Some(Bar(u64::MAX, u64::MAX))
         );
+        assert_eq!(b.load(SeqCst), Bar(u64::MAX, u64::MAX - 1));
+
+        // A smaller type overflows at its own width, not at 128 bits.
+        let c = AtomicDouble::new(u64::MAX - 1);
+        assert_eq!(c.checked_fetch_add(1, SeqCst), Some(u64::MAX - 1));
+        assert_eq!(c.checked_fetch_add(1, SeqCst), None);
+        assert_eq!(c.load(SeqCst), u64::MAX);
+        c.store(1, SeqCst);
+        assert_eq!(c.checked_fetch_sub(2, SeqCst), None);
+        assert_eq!(c.checked_fetch_sub(1, SeqCst), Some(1));
+        assert_eq!(c.checked_fetch_sub(1, SeqCst), None);
+        assert_eq!(c.load(SeqCst), 0);
+        let d = AtomicDouble::new(SizeBar(u32::MAX, u32::MAX));
+        assert_eq!(d.checked_fetch_add(SizeBar(1, 0), SeqCst), None);
+        assert_eq!(d.load(SeqCst), SizeBar(u32::MAX, u32::MAX));
         assert_eq!(
-            a.compare_exchange(Bar(1, 1), Bar(3, 3), SeqCst, SeqCst),
-            Ok(Bar(1, 1))
+            d.checked_fetch_sub(SizeBar(u32::MAX, u32::MAX), SeqCst),
+            Some(SizeBar(u32::MAX, u32::MAX))
         );
-        assert_eq!(a.load(SeqCst), Bar(3, 3));
+        assert_eq!(d.load(SeqCst), SizeBar(0, 0));
+    }
+
+    #[test]
+    fn atomic_saturating_add_sub() {
+        let a = AtomicDouble::new(u128::MAX - 1);
+        assert_eq!(a.saturating_fetch_add(5, SeqCst), u128::MAX - 1);
+        assert_eq!(a.load(SeqCst), u128::MAX);
+        assert_eq!(a.saturating_fetch_add(1, SeqCst), u128::MAX);
+        assert_eq!(a.load(SeqCst), u128::MAX);
+        a.store(3, SeqCst);
+        assert_eq!(a.saturating_fetch_sub(5, SeqCst), 3);
+        assert_eq!(a.load(SeqCst), 0);
+
+        // Saturation pins the whole word, not the lane that overflowed.
+        let b = AtomicDouble::new(Bar(0, u64::MAX));
+        b.saturating_fetch_add(Bar(0, 1), SeqCst);
+        assert_eq!(b.load(SeqCst), Bar(u64::MAX, u64::MAX));
+
+        // A smaller type saturates at its own maximum.
+        let c = AtomicDouble::new(u64::MAX - 1);
+        assert_eq!(c.saturating_fetch_add(5, SeqCst), u64::MAX - 1);
+        assert_eq!(c.load(SeqCst), u64::MAX);
+        assert_eq!(c.saturating_fetch_add(1, SeqCst), u64::MAX);
+        assert_eq!(c.load(SeqCst), u64::MAX);
+        c.store(3, SeqCst);
+        assert_eq!(c.saturating_fetch_sub(5, SeqCst), 3);
+        assert_eq!(c.load(SeqCst), 0);
+        let d = AtomicDouble::new(SizeBar(u32::MAX, u32::MAX - 1));
+        d.saturating_fetch_add(SizeBar(1, 1), SeqCst);
+        assert_eq!(d.load(SeqCst), SizeBar(u32::MAX, u32::MAX));
+        d.store(SizeBar(1, 1), SeqCst);
+        assert_eq!(d.saturating_fetch_sub(SizeBar(2, 2), SeqCst), SizeBar(1, 1));
+        assert_eq!(d.load(SeqCst), SizeBar(0, 0));
     }
 
     #[test]
     fn atomic_sizebar() {
-        assert!(!AtomicDouble::<SizeBar>::is_lock_free());
+        assert_eq!(
+            AtomicDouble::<SizeBar>::is_lock_free(),
+            !cfg!(any(miri, feature = "tsan"))
+        );
+        let a = AtomicDouble::new(SizeBar(1, 2));
+        assert_eq!(a.load(SeqCst), SizeBar(1, 2));
+        assert_eq!(
+            a.compare_exchange(SizeBar(1, 2), SizeBar(3, 4), SeqCst, SeqCst),
+            Ok(SizeBar(1, 2))
+        );
+        assert_eq!(a.swap(SizeBar(u32::MAX, 6), SeqCst), SizeBar(3, 4));
+        assert_eq!(a.fetch_max(SizeBar(0, 5), SeqCst), SizeBar(u32::MAX, 6));
+        assert_eq!(a.load(SeqCst), SizeBar(u32::MAX, 6));
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct ThreeBar(u32, u32, u32);
+
+    #[test]
+    fn atomic_threebar() {
+        assert_eq!(
+            AtomicDouble::<ThreeBar>::is_lock_free(),
+            !cfg!(any(miri, feature = "tsan"))
+        );
+        let a = AtomicDouble::new(ThreeBar(1, 2, 3));
+        assert_eq!(a.load(SeqCst), ThreeBar(1, 2, 3));
+        a.store(ThreeBar(4, 5, 6), SeqCst);
+        assert_eq!(
+            a.compare_exchange(ThreeBar(1, 2, 3), ThreeBar(0, 0, 0), SeqCst, SeqCst),
+            Err(ThreeBar(4, 5, 6))
+        );
+        assert_eq!(
+            a.compare_exchange(ThreeBar(4, 5, 6), ThreeBar(7, 8, 9), SeqCst, SeqCst),
+            Ok(ThreeBar(4, 5, 6))
+        );
+        assert_eq!(
+            a.fetch_add(ThreeBar(1, 1, u32::MAX), SeqCst),
+            ThreeBar(7, 8, 9)
+        );
+        assert_eq!(a.load(SeqCst), ThreeBar(8, 9, 8));
     }
 
     #[test]
@@ -213,7 +4205,10 @@ mod tests {
         };
 
         let a: AtomicDouble<Node> = AtomicDouble::new(temp_node_x);
-        assert!(AtomicDouble::<Node>::is_lock_free());
+        assert_eq!(
+            AtomicDouble::<Node>::is_lock_free(),
+            !cfg!(any(miri, feature = "tsan"))
+        );
 
         let load_test = a.load(SeqCst);
         unsafe {
@@ -249,5 +4244,37 @@ mod tests {
             SeqCst,
         );
         assert_eq!(a.load(SeqCst).head_count, usize::MAX);
+
+        unsafe { drop(Box::from_raw(temp_node_y.head_ptr.unwrap().as_ptr())) };
+    }
+}
+
+#[cfg(all(test, feature = "loom"))]
+mod loom_tests {
+    use crate::AtomicDouble;
+    use crate::Ordering::SeqCst;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+    struct Bar(u64, u64);
+
+    #[test]
+    fn compare_exchange_race() {
+        loom::model(|| {
+            let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+            let b = a.clone();
+            let t = thread::spawn(move || {
+                b.compare_exchange(Bar(0, 0), Bar(1, 1), SeqCst, SeqCst)
+                    .is_ok()
+            });
+            let won = a
+                .compare_exchange(Bar(0, 0), Bar(2, 2), SeqCst, SeqCst)
+                .is_ok();
+            let other_won = t.join().unwrap();
+            assert!(won != other_won);
+            let expected = if won { Bar(2, 2) } else { Bar(1, 1) };
+            assert_eq!(a.load(SeqCst), expected);
+        });
     }
 }
@@ -0,0 +1,354 @@
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+
+use bytemuck::Pod;
+
+use crate::{AtomicDouble, Ordering};
+
+// `T`'s bytes, zero-extended, read as a native-endian `u128`: the same word an
+// `AtomicDouble<T>` works on. `Pod` guarantees that every byte is initialized.
+#[inline]
+fn to_bits<T: Pod>(value: T) -> u128 {
+    let mut bytes = [0u8; 16];
+    bytes[..mem::size_of::<T>()].copy_from_slice(bytemuck::bytes_of(&value));
+    u128::from_ne_bytes(bytes)
+}
+
+// `Pod` also guarantees that any bytes make a valid `T`, so this is the
+// checked counterpart of `transmute_copy`.
+#[inline]
+fn from_bits<T: Pod>(bits: u128) -> T {
+    bytemuck::pod_read_unaligned(&bits.to_ne_bytes()[..mem::size_of::<T>()])
+}
+
+/// An `AtomicDouble<T>` restricted to `T: bytemuck::Pod`, available with the
+/// `bytemuck` feature.
+///
+/// `AtomicDouble<T>` reinterprets the bytes of `T` as a `u128` and back with
+/// `transmute_copy`, and leaves it to the caller to make sure that is sound
+/// (see the crate's notes on layout). `Pod` rules out at compile time the
+/// types for which it isn't: those with padding, whose bytes aren't all
+/// initialized, and those with invalid bit patterns, such as `bool`, `char`,
+/// references, `NonNull` and `Option<NonNull<_>>`. For the rest, this type
+/// stores an `AtomicDouble<u128>` and converts with `bytemuck`, so none of its
+/// operations rely on an unchecked transmute.
+///
+/// The operations behave exactly as those of `AtomicDouble<T>`, including
+/// the arithmetic on the bytes of `T` as one native-endian `u128`.
+///
+/// Several of the types this crate itself uses with `AtomicDouble` aren't
+/// `Pod`, and stay with `AtomicDouble`:
+///
+/// - `TaggedPtr<T>` and the `DoubleWordPtr` pairs hold raw pointers, and the
+///   head of `collections::TreiberStack` is such a pair.
+/// - Node-like structs holding an `Option<NonNull<_>>`, which has a niche,
+///   as in the crate's own tests.
+/// - Tuples such as `(u64, u64)`, whose layout isn't guaranteed; an array or a
+///   `#[repr(C)]` struct without padding is `Pod` instead.
+///
+/// ```
+/// use atomicdouble::AtomicDoublePod;
+/// use atomicdouble::Ordering::SeqCst;
+///
+/// let a = AtomicDoublePod::new([1u64, 2u64]);
+/// assert_eq!(a.fetch_add([1, 0], SeqCst), [1, 2]);
+/// assert_eq!(a.load(SeqCst), [2, 2]);
+/// ```
+pub struct AtomicDoublePod<T: Pod> {
+    inner: AtomicDouble<u128>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: Pod> AtomicDoublePod<T> {
+    // Referenced from the constructor, like `AtomicDouble::SIZE_CHECK`.
+    const SIZE_CHECK: () = assert!(
+        mem::size_of::<T>() <= 16,
+        "`AtomicDoublePod<T>` requires a type of at most 16 bytes"
+    );
+
+    /// Creates a new `AtomicDoublePod`.
+    ///
+    /// Unlike `AtomicDouble::new` this isn't a `const fn`, as the `bytemuck`
+    /// conversions aren't.
+    #[inline]
+    pub fn new(v: T) -> AtomicDoublePod<T> {
+        let () = Self::SIZE_CHECK;
+        AtomicDoublePod {
+            inner: AtomicDouble::new(to_bits(v)),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Checks if `AtomicDoublePod` objects are lock-free.
+    #[inline]
+    pub fn is_lock_free() -> bool {
+        AtomicDouble::<u128>::is_lock_free()
+    }
+
+    /// Returns a mutable reference to the value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        let bytes = bytemuck::bytes_of_mut(self.inner.get_mut());
+        // The `u128` is aligned to 16 bytes, more than any `T` of at most 16
+        // bytes needs.
+        bytemuck::from_bytes_mut(&mut bytes[..mem::size_of::<T>()])
+    }
+
+    /// Consumes the atomic and returns the value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        from_bits(self.inner.into_inner())
+    }
+
+    /// Loads the value. See `AtomicDouble::load`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> T {
+        from_bits(self.inner.load(order))
+    }
+
+    /// Stores a value. See `AtomicDouble::store`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, val: T, order: Ordering) {
+        self.inner.store(to_bits(val), order)
+    }
+
+    /// Stores a value, returning the previous one. See `AtomicDouble::swap`.
+    #[inline]
+    pub fn swap(&self, val: T, order: Ordering) -> T {
+        from_bits(self.inner.swap(to_bits(val), order))
+    }
+
+    /// Stores `new` if the current value is the same as `current`. See
+    /// `AtomicDouble::compare_exchange`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.inner
+            .compare_exchange(to_bits(current), to_bits(new), success, failure)
+            .map(from_bits)
+            .map_err(from_bits)
+    }
+
+    /// Stores `new` if the current value is the same as `current`, and may
+    /// fail spuriously. See `AtomicDouble::compare_exchange_weak`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.inner
+            .compare_exchange_weak(to_bits(current), to_bits(new), success, failure)
+            .map(from_bits)
+            .map_err(from_bits)
+    }
+
+    /// Applies `f` to the value until the exchange succeeds or `f` returns
+    /// `None`. See `AtomicDouble::fetch_update`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `fetch_order` is `Release`, `AcqRel` or stronger than
+    /// `set_order`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn fetch_update<F>(
+        &self,
+        set_order: Ordering,
+        fetch_order: Ordering,
+        mut f: F,
+    ) -> Result<T, T>
+    where
+        F: FnMut(T) -> Option<T>,
+    {
+        self.inner
+            .fetch_update(set_order, fetch_order, |bits| {
+                f(from_bits(bits)).map(to_bits)
+            })
+            .map(from_bits)
+            .map_err(from_bits)
+    }
+
+    /// Adds to the value, returning the previous one. See
+    /// `AtomicDouble::fetch_add`.
+    #[inline]
+    pub fn fetch_add(&self, val: T, order: Ordering) -> T {
+        from_bits(self.inner.fetch_add(to_bits(val), order))
+    }
+
+    /// Subtracts from the value, returning the previous one. See
+    /// `AtomicDouble::fetch_sub`.
+    #[inline]
+    pub fn fetch_sub(&self, val: T, order: Ordering) -> T {
+        from_bits(self.inner.fetch_sub(to_bits(val), order))
+    }
+
+    /// Bitwise "and" with the value, returning the previous one.
+    #[inline]
+    pub fn fetch_and(&self, val: T, order: Ordering) -> T {
+        from_bits(self.inner.fetch_and(to_bits(val), order))
+    }
+
+    /// Bitwise "or" with the value, returning the previous one.
+    #[inline]
+    pub fn fetch_or(&self, val: T, order: Ordering) -> T {
+        from_bits(self.inner.fetch_or(to_bits(val), order))
+    }
+
+    /// Bitwise "xor" with the value, returning the previous one.
+    #[inline]
+    pub fn fetch_xor(&self, val: T, order: Ordering) -> T {
+        from_bits(self.inner.fetch_xor(to_bits(val), order))
+    }
+}
+
+impl<T: Pod + Default> Default for AtomicDoublePod<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Pod + fmt::Debug> fmt::Debug for AtomicDoublePod<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.load(Ordering::SeqCst), f)
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::AtomicDoublePod;
+    use crate::AtomicDouble;
+    use crate::Ordering::SeqCst;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Copy, Clone, Debug, Default, PartialEq)]
+    #[repr(C)]
+    struct Pair {
+        first: u64,
+        second: u64,
+    }
+
+    unsafe impl bytemuck::Zeroable for Pair {}
+    unsafe impl bytemuck::Pod for Pair {}
+
+    #[test]
+    fn pod_operations() {
+        let mut a = AtomicDoublePod::new(Pair {
+            first: 1,
+            second: 2,
+        });
+        assert_eq!(
+            a.load(SeqCst),
+            Pair {
+                first: 1,
+                second: 2
+            }
+        );
+        assert_eq!(
+            a.swap(
+                Pair {
+                    first: 3,
+                    second: 4
+                },
+                SeqCst
+            ),
+            Pair {
+                first: 1,
+                second: 2
+            }
+        );
+        assert_eq!(
+            a.compare_exchange(Pair::default(), Pair::default(), SeqCst, SeqCst),
+            Err(Pair {
+                first: 3,
+                second: 4
+            })
+        );
+        assert_eq!(
+            a.fetch_update(SeqCst, SeqCst, |p| Some(Pair {
+                first: p.second,
+                second: p.first,
+            })),
+            Ok(Pair {
+                first: 3,
+                second: 4
+            })
+        );
+        a.get_mut().first += 10;
+        assert_eq!(
+            a.into_inner(),
+            Pair {
+                first: 14,
+                second: 3
+            }
+        );
+        assert_eq!(format!("{:?}", AtomicDoublePod::<u32>::default()), "0");
+    }
+
+    #[test]
+    fn pod_matches_atomic_double() {
+        // Smaller types are zero-extended, and the arithmetic and bitwise
+        // operations see the same word as `AtomicDouble<T>` does.
+        let values = [0u32, 1, 0x8000_0000, u32::MAX];
+        for &x in &values {
+            for &y in &values {
+                let pod = AtomicDoublePod::new(x);
+                let plain = AtomicDouble::new(x);
+                assert_eq!(pod.fetch_add(y, SeqCst), plain.fetch_add(y, SeqCst));
+                assert_eq!(pod.fetch_xor(y, SeqCst), plain.fetch_xor(y, SeqCst));
+                assert_eq!(pod.fetch_sub(y, SeqCst), plain.fetch_sub(y, SeqCst));
+                assert_eq!(pod.fetch_or(y, SeqCst), plain.fetch_or(y, SeqCst));
+                assert_eq!(pod.fetch_and(y, SeqCst), plain.fetch_and(y, SeqCst));
+                assert_eq!(pod.load(SeqCst), plain.load(SeqCst));
+            }
+        }
+    }
+
+    #[test]
+    fn pod_concurrent_fetch_add() {
+        let a = Arc::new(AtomicDoublePod::new([0u64; 2]));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        a.fetch_add([1, 1], SeqCst);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(a.load(SeqCst), [4 * n, 4 * n]);
+    }
+}
@@ -0,0 +1,232 @@
+use core::fmt;
+
+use crate::{AtomicDouble, Ordering};
+
+// The value, zero-extended to a full word. Without the zeroed bytes a `T`
+// smaller than 8 bytes would leave uninitialized padding in the pair, which
+// `compare_exchange` would compare too. Padding inside `T` is uninitialized
+// again once the value is written, so `T` must not have any.
+#[derive(Clone, Copy)]
+#[repr(C)]
+union Word<T: Copy> {
+    value: T,
+    bits: u64,
+}
+
+#[derive(Clone, Copy)]
+#[repr(C)]
+struct Stamped<T: Copy> {
+    value: Word<T>,
+    stamp: u64,
+}
+
+impl<T: Copy> Stamped<T> {
+    #[inline]
+    const fn new(value: T, stamp: u64) -> Stamped<T> {
+        let mut word = Word { bits: 0 };
+        word.value = value;
+        Stamped { value: word, stamp }
+    }
+
+    #[inline]
+    fn get(self) -> (T, u64) {
+        (unsafe { self.value.value }, self.stamp)
+    }
+}
+
+/// A value paired with a 64-bit stamp that changes on every successful
+/// `compare_and_set`, like Java's `AtomicStampedReference`.
+///
+/// A plain compare-and-swap can't tell a value that was changed and then
+/// changed back from one that was never touched, which is the ABA problem.
+/// `compare_and_set` also checks the stamp, and bumps it on success, so such a
+/// value no longer matches.
+///
+/// The value and the stamp are held together in one `AtomicDouble`, so `T`
+/// can be at most 8 bytes large; a larger `T` is rejected at compile time.
+/// Values are compared by their bytes, as with `AtomicDouble::compare_exchange`,
+/// so as for `AtomicDouble`, `T` must not have padding bytes (see the crate's
+/// notes on layout): those are uninitialized, and would be compared too.
+///
+/// ```
+/// use atomicdouble::AtomicStamped;
+/// use atomicdouble::Ordering::SeqCst;
+///
+/// let a = AtomicStamped::new(1u32, 0);
+/// assert_eq!(a.compare_and_set(1, 2, 0), Ok(()));
+/// assert_eq!(a.load(SeqCst), (2, 1));
+/// // A stale stamp fails even though the value matches.
+/// assert_eq!(a.compare_and_set(2, 3, 0), Err((2, 1)));
+/// ```
+pub struct AtomicStamped<T: Copy> {
+    inner: AtomicDouble<Stamped<T>>,
+}
+
+impl<T: Copy> AtomicStamped<T> {
+    // Referenced from the constructors, like `AtomicDouble::SIZE_CHECK`.
+    const SIZE_CHECK: () = assert!(
+        core::mem::size_of::<T>() <= 8,
+        "`AtomicStamped<T>` requires a type of at most 8 bytes"
+    );
+
+    /// Creates a new `AtomicStamped` holding `value` and `stamp`.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub const fn new(value: T, stamp: u64) -> AtomicStamped<T> {
+        let () = Self::SIZE_CHECK;
+        AtomicStamped {
+            inner: AtomicDouble::new(Stamped::new(value, stamp)),
+        }
+    }
+
+    /// Creates a new `AtomicStamped` holding `value` and `stamp`.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn new(value: T, stamp: u64) -> AtomicStamped<T> {
+        let () = Self::SIZE_CHECK;
+        AtomicStamped {
+            inner: AtomicDouble::new(Stamped::new(value, stamp)),
+        }
+    }
+
+    /// Checks if `AtomicStamped` objects of this type are lock-free.
+    #[inline]
+    pub fn is_lock_free() -> bool {
+        AtomicDouble::<Stamped<T>>::is_lock_free()
+    }
+
+    /// Loads the value and the stamp.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> (T, u64) {
+        self.inner.load(order).get()
+    }
+
+    /// Stores a value and a stamp, as is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, value: T, stamp: u64, order: Ordering) {
+        self.inner.store(Stamped::new(value, stamp), order)
+    }
+
+    /// Stores `new_value` if the current value is `expected_value` and the
+    /// current stamp is `expected_stamp`.
+    ///
+    /// On success the stamp becomes `expected_stamp` incremented by one
+    /// (wrapping). On failure the current value and stamp are returned in
+    /// `Err`, ready to be fed back in. Like the Java method it is named after,
+    /// this is always `SeqCst`.
+    #[must_use = "the result of a compare-and-set indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_and_set(
+        &self,
+        expected_value: T,
+        new_value: T,
+        expected_stamp: u64,
+    ) -> Result<(), (T, u64)> {
+        let current = Stamped::new(expected_value, expected_stamp);
+        let new = Stamped::new(new_value, expected_stamp.wrapping_add(1));
+        self.inner
+            .compare_exchange(current, new, Ordering::SeqCst, Ordering::SeqCst)
+            .map(|_| ())
+            .map_err(Stamped::get)
+    }
+
+    /// Consumes the atomic and returns the value and the stamp.
+    #[inline]
+    pub fn into_inner(self) -> (T, u64) {
+        self.inner.into_inner().get()
+    }
+}
+
+impl<T: Copy + Default> Default for AtomicStamped<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default(), 0)
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for AtomicStamped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (value, stamp) = self.load(Ordering::SeqCst);
+        f.debug_struct("AtomicStamped")
+            .field("value", &value)
+            .field("stamp", &stamp)
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::AtomicStamped;
+    use crate::AtomicDouble;
+    use crate::Ordering::SeqCst;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn compare_and_set() {
+        let a = AtomicStamped::new(1u8, u64::MAX);
+        assert_eq!(a.load(SeqCst), (1, u64::MAX));
+        assert_eq!(a.compare_and_set(2, 3, u64::MAX), Err((1, u64::MAX)));
+        assert_eq!(a.compare_and_set(1, 3, 0), Err((1, u64::MAX)));
+        // The stamp wraps around.
+        assert_eq!(a.compare_and_set(1, 3, u64::MAX), Ok(()));
+        assert_eq!(a.load(SeqCst), (3, 0));
+        a.store(4, 10, SeqCst);
+        assert_eq!(format!("{:?}", a), "AtomicStamped { value: 4, stamp: 10 }");
+        assert_eq!(a.into_inner(), (4, 10));
+        assert_eq!(AtomicStamped::<u8>::default().load(SeqCst), (0, 0));
+    }
+
+    #[test]
+    fn defeats_aba() {
+        // Thread 1 reads A, then thread 2 changes A to B and back to A.
+        let plain = AtomicDouble::new(0xAu64);
+        let stamped = AtomicStamped::new(0xAu64, 0);
+        let seen = plain.load(SeqCst);
+        let (seen_value, seen_stamp) = stamped.load(SeqCst);
+
+        assert!(plain.compare_exchange(0xA, 0xB, SeqCst, SeqCst).is_ok());
+        assert!(plain.compare_exchange(0xB, 0xA, SeqCst, SeqCst).is_ok());
+        assert_eq!(stamped.compare_and_set(0xA, 0xB, 0), Ok(()));
+        assert_eq!(stamped.compare_and_set(0xB, 0xA, 1), Ok(()));
+
+        // The plain CAS of thread 1 wrongly succeeds, the stamped one fails.
+        assert!(plain.compare_exchange(seen, 0xC, SeqCst, SeqCst).is_ok());
+        assert_eq!(
+            stamped.compare_and_set(seen_value, 0xC, seen_stamp),
+            Err((0xA, 2))
+        );
+    }
+
+    #[test]
+    fn concurrent_compare_and_set() {
+        let a = Arc::new(AtomicStamped::new(0u32, 0));
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let a = a.clone();
+                thread::spawn(move || {
+                    for _ in 0..n {
+                        let (mut value, mut stamp) = a.load(SeqCst);
+                        while let Err(actual) = a.compare_and_set(value, value ^ 1, stamp) {
+                            (value, stamp) = actual;
+                        }
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        // Every success bumped the stamp exactly once.
+        assert_eq!(a.load(SeqCst), (0, 4 * n));
+    }
+}
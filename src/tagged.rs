@@ -0,0 +1,522 @@
+use core::fmt;
+use core::ptr::{self, NonNull};
+
+use crate::{AtomicDouble, Ordering};
+
+/// A raw pointer paired with a tag that is bumped on every successful exchange.
+///
+/// Comparing the tag as well as the pointer is what lets an `AtomicTaggedPtr`
+/// tell apart a pointer that was popped and pushed back in the meantime, which
+/// would otherwise lead to the ABA problem.
+#[repr(C)]
+pub struct TaggedPtr<T> {
+    ptr: *mut T,
+    tag: usize,
+}
+
+impl<T> TaggedPtr<T> {
+    /// Creates a new `TaggedPtr` from a pointer and a tag.
+    #[inline]
+    pub const fn new(ptr: *mut T, tag: usize) -> TaggedPtr<T> {
+        TaggedPtr { ptr, tag }
+    }
+
+    /// Creates a null `TaggedPtr` with a tag of zero.
+    #[inline]
+    pub const fn null() -> TaggedPtr<T> {
+        TaggedPtr::new(ptr::null_mut(), 0)
+    }
+
+    /// Returns the pointer part.
+    #[inline]
+    pub fn ptr(self) -> *mut T {
+        self.ptr
+    }
+
+    /// Returns the tag part.
+    #[inline]
+    pub fn tag(self) -> usize {
+        self.tag
+    }
+
+    /// Returns `true` if the pointer part is null.
+    #[inline]
+    pub fn is_null(self) -> bool {
+        self.ptr.is_null()
+    }
+}
+
+impl<T> Clone for TaggedPtr<T> {
+    #[inline]
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TaggedPtr<T> {}
+
+impl<T> PartialEq for TaggedPtr<T> {
+    #[inline]
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr && self.tag == other.tag
+    }
+}
+
+impl<T> Eq for TaggedPtr<T> {}
+
+impl<T> Default for TaggedPtr<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::null()
+    }
+}
+
+impl<T> fmt::Debug for TaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedPtr")
+            .field("ptr", &self.ptr)
+            .field("tag", &self.tag)
+            .finish()
+    }
+}
+
+/// A value made of an optional pointer and a tag, for use in an
+/// `AtomicDouble`.
+///
+/// This is the shape of the crate's central use case: pairing a pointer with
+/// a counter that changes on every exchange avoids the ABA problem. Any `Copy`
+/// type of that shape, such as a struct holding an `Option<NonNull<T>>` and a
+/// `usize`, can implement this trait to get the `compare_exchange_ptr` helpers
+/// of `AtomicDouble`, which bump the tag automatically. It is implemented for
+/// `TaggedPtr<T>` and for `(Option<NonNull<T>>, usize)`.
+pub trait DoubleWordPtr: Copy {
+    /// The type the pointer points to.
+    type Target;
+
+    /// Returns the pointer part, or `None` if it is null.
+    fn ptr(self) -> Option<NonNull<Self::Target>>;
+
+    /// Returns the tag part.
+    fn tag(self) -> usize;
+
+    /// Builds a value from a pointer and a tag.
+    fn with(ptr: Option<NonNull<Self::Target>>, tag: usize) -> Self;
+}
+
+impl<T> DoubleWordPtr for TaggedPtr<T> {
+    type Target = T;
+
+    #[inline]
+    fn ptr(self) -> Option<NonNull<T>> {
+        NonNull::new(self.ptr)
+    }
+
+    #[inline]
+    fn tag(self) -> usize {
+        self.tag
+    }
+
+    #[inline]
+    fn with(ptr: Option<NonNull<T>>, tag: usize) -> Self {
+        TaggedPtr::new(ptr.map_or(ptr::null_mut(), NonNull::as_ptr), tag)
+    }
+}
+
+impl<T> DoubleWordPtr for (Option<NonNull<T>>, usize) {
+    type Target = T;
+
+    #[inline]
+    fn ptr(self) -> Option<NonNull<T>> {
+        self.0
+    }
+
+    #[inline]
+    fn tag(self) -> usize {
+        self.1
+    }
+
+    #[inline]
+    fn with(ptr: Option<NonNull<T>>, tag: usize) -> Self {
+        (ptr, tag)
+    }
+}
+
+impl<P: DoubleWordPtr> AtomicDouble<P> {
+    /// Stores a pointer if the current value is the same as `current`,
+    /// comparing both the pointer and the tag.
+    ///
+    /// The value stored is `new` with `current.tag()` incremented by one
+    /// (wrapping). Otherwise this is `compare_exchange`: the previous value is
+    /// returned in `Ok` on success, and the current value in `Err` on failure,
+    /// ready to be fed back in as `current`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_ptr(
+        &self,
+        current: P,
+        new: Option<NonNull<P::Target>>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<P, P> {
+        let new = P::with(new, current.tag().wrapping_add(1));
+        self.compare_exchange(current, new, success, failure)
+    }
+
+    /// Stores a pointer if the current value is the same as `current`, bumping
+    /// the tag like `compare_exchange_ptr`.
+    ///
+    /// Like `compare_exchange_weak`, this may fail spuriously, so it is meant
+    /// to be retried in a loop.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_weak_ptr(
+        &self,
+        current: P,
+        new: Option<NonNull<P::Target>>,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<P, P> {
+        let new = P::with(new, current.tag().wrapping_add(1));
+        self.compare_exchange_weak(current, new, success, failure)
+    }
+}
+
+/// An atomic `TaggedPtr<T>` whose `compare_exchange` bumps the tag on success.
+pub struct AtomicTaggedPtr<T> {
+    inner: AtomicDouble<TaggedPtr<T>>,
+}
+
+// Like `AtomicPtr<T>`, the pointer is only ever handed out, never dereferenced.
+unsafe impl<T> Send for AtomicTaggedPtr<T> {}
+unsafe impl<T> Sync for AtomicTaggedPtr<T> {}
+
+impl<T> Default for AtomicTaggedPtr<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(ptr::null_mut())
+    }
+}
+
+impl<T> fmt::Debug for AtomicTaggedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicTaggedPtr")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+impl<T> AtomicTaggedPtr<T> {
+    /// Creates a new `AtomicTaggedPtr` holding `ptr` with a tag of zero.
+    #[cfg(not(feature = "loom"))]
+    #[inline]
+    pub const fn new(ptr: *mut T) -> AtomicTaggedPtr<T> {
+        AtomicTaggedPtr {
+            inner: AtomicDouble::new(TaggedPtr::new(ptr, 0)),
+        }
+    }
+
+    /// Creates a new `AtomicTaggedPtr` holding `ptr` with a tag of zero.
+    #[cfg(feature = "loom")]
+    #[inline]
+    pub fn new(ptr: *mut T) -> AtomicTaggedPtr<T> {
+        AtomicTaggedPtr {
+            inner: AtomicDouble::new(TaggedPtr::new(ptr, 0)),
+        }
+    }
+
+    /// Checks if `AtomicTaggedPtr` objects are lock-free.
+    #[inline]
+    pub fn is_lock_free() -> bool {
+        AtomicDouble::<TaggedPtr<T>>::is_lock_free()
+    }
+
+    /// Loads the tagged pointer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> TaggedPtr<T> {
+        self.inner.load(order)
+    }
+
+    /// Stores a tagged pointer, tag included, as is.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, val: TaggedPtr<T>, order: Ordering) {
+        self.inner.store(val, order)
+    }
+
+    /// Stores `new` if the current value is the same as `current`, comparing
+    /// both the pointer and the tag.
+    ///
+    /// On success the stored tag is `current.tag()` incremented by one
+    /// (wrapping), and the previous value is returned in `Ok`. On failure the
+    /// current value is returned in `Err`, ready to be fed back in as `current`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: TaggedPtr<T>,
+        new: *mut T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<TaggedPtr<T>, TaggedPtr<T>> {
+        self.inner
+            .compare_exchange_ptr(current, NonNull::new(new), success, failure)
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::{AtomicTaggedPtr, DoubleWordPtr, TaggedPtr};
+    use crate::AtomicDouble;
+    use crate::Ordering::{Acquire, Relaxed, Release, SeqCst};
+    use std::ptr::{self, NonNull};
+    use std::sync::Arc;
+    use std::thread;
+
+    struct Node {
+        value: usize,
+        next: *mut Node,
+    }
+
+    // Lets popped nodes be sent back to the main thread to be freed.
+    struct SendPtr(*mut Node);
+    unsafe impl Send for SendPtr {}
+
+    struct Stack {
+        head: AtomicTaggedPtr<Node>,
+    }
+
+    impl Stack {
+        fn push(&self, value: usize) {
+            let node = Box::into_raw(Box::new(Node {
+                value,
+                next: ptr::null_mut(),
+            }));
+            let mut head = self.head.load(Relaxed);
+            loop {
+                unsafe { (*node).next = head.ptr() };
+                match self.head.compare_exchange(head, node, Release, Relaxed) {
+                    Ok(_) => return,
+                    Err(actual) => head = actual,
+                }
+            }
+        }
+
+        // Popped nodes are handed back rather than freed, as another thread may
+        // still be reading `next` through a stale head.
+        fn pop(&self) -> Option<*mut Node> {
+            let mut head = self.head.load(Acquire);
+            loop {
+                if head.is_null() {
+                    return None;
+                }
+                // `next` may be stale if `head` was popped and pushed again in
+                // the meantime, but then the tag has moved on and the CAS fails.
+                let next = unsafe { (*head.ptr()).next };
+                match self.head.compare_exchange(head, next, Acquire, Acquire) {
+                    Ok(_) => return Some(head.ptr()),
+                    Err(actual) => head = actual,
+                }
+            }
+        }
+    }
+
+    // The same stack, over a user-defined head type.
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+    struct Head {
+        ptr: Option<NonNull<Node>>,
+        pops: usize,
+    }
+
+    // Only the thread that pops a node dereferences it, as with `SendPtr`.
+    unsafe impl Send for Head {}
+
+    impl DoubleWordPtr for Head {
+        type Target = Node;
+
+        fn ptr(self) -> Option<NonNull<Node>> {
+            self.ptr
+        }
+
+        fn tag(self) -> usize {
+            self.pops
+        }
+
+        fn with(ptr: Option<NonNull<Node>>, pops: usize) -> Self {
+            Head { ptr, pops }
+        }
+    }
+
+    struct HeadStack {
+        head: AtomicDouble<Head>,
+    }
+
+    impl HeadStack {
+        fn push(&self, value: usize) {
+            let node = NonNull::from(Box::leak(Box::new(Node {
+                value,
+                next: ptr::null_mut(),
+            })));
+            let mut head = self.head.load(Relaxed);
+            loop {
+                unsafe {
+                    (*node.as_ptr()).next = head.ptr.map_or(ptr::null_mut(), NonNull::as_ptr)
+                };
+                match self
+                    .head
+                    .compare_exchange_weak_ptr(head, Some(node), Release, Relaxed)
+                {
+                    Ok(_) => return,
+                    Err(actual) => head = actual,
+                }
+            }
+        }
+
+        fn pop(&self) -> Option<*mut Node> {
+            let mut head = self.head.load(Acquire);
+            loop {
+                let node = head.ptr?;
+                let next = NonNull::new(unsafe { (*node.as_ptr()).next });
+                match self.head.compare_exchange_ptr(head, next, Acquire, Acquire) {
+                    Ok(_) => return Some(node.as_ptr()),
+                    Err(actual) => head = actual,
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn double_word_ptr() {
+        let mut x = 5;
+        let x = NonNull::from(&mut x);
+        let a = AtomicDouble::new((None, 7));
+        assert_eq!(
+            a.compare_exchange_ptr((None, 7), Some(x), SeqCst, SeqCst),
+            Ok((None, 7))
+        );
+        assert_eq!(a.load(SeqCst), (Some(x), 8));
+        assert_eq!(
+            a.compare_exchange_ptr((Some(x), 7), None, SeqCst, SeqCst),
+            Err((Some(x), 8))
+        );
+
+        let t = TaggedPtr::<i32>::with(Some(x), usize::MAX);
+        assert_eq!(t.ptr(), x.as_ptr());
+        assert_eq!(DoubleWordPtr::ptr(t), Some(x));
+        let a = AtomicDouble::new(t);
+        assert_eq!(a.compare_exchange_ptr(t, None, SeqCst, SeqCst), Ok(t));
+        // The tag wraps around.
+        assert_eq!(a.load(SeqCst), TaggedPtr::null());
+    }
+
+    #[test]
+    fn double_word_ptr_treiber_stack() {
+        let stack = Arc::new(HeadStack {
+            head: AtomicDouble::new(Head { ptr: None, pops: 0 }),
+        });
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    for i in 0..n {
+                        stack.push(t * n + i);
+                        popped.extend(stack.pop().map(SendPtr));
+                    }
+                    popped
+                })
+            })
+            .collect();
+        let mut nodes = Vec::new();
+        for handle in handles {
+            nodes.extend(handle.join().unwrap());
+        }
+        while let Some(node) = stack.pop() {
+            nodes.push(SendPtr(node));
+        }
+        let mut values: Vec<_> = nodes
+            .into_iter()
+            .map(|node| unsafe { Box::from_raw(node.0) }.value)
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..4 * n).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn tagged_ptr_compare_exchange() {
+        let mut x = 5;
+        let mut y = 10;
+        let a = AtomicTaggedPtr::new(&mut x as *mut i32);
+        assert_eq!(
+            AtomicTaggedPtr::<i32>::is_lock_free(),
+            !cfg!(any(miri, feature = "tsan"))
+        );
+
+        let current = a.load(SeqCst);
+        assert_eq!(current, TaggedPtr::new(&mut x, 0));
+        assert_eq!(
+            a.compare_exchange(current, &mut y, SeqCst, SeqCst),
+            Ok(current)
+        );
+        assert_eq!(a.load(SeqCst), TaggedPtr::new(&mut y, 1));
+
+        // Swapping back to `x` still leaves a stale `current` rejected.
+        assert_eq!(
+            a.compare_exchange(TaggedPtr::new(&mut y, 1), &mut x, SeqCst, SeqCst),
+            Ok(TaggedPtr::new(&mut y, 1))
+        );
+        assert_eq!(
+            a.compare_exchange(current, &mut y, SeqCst, SeqCst),
+            Err(TaggedPtr::new(&mut x, 2))
+        );
+    }
+
+    #[test]
+    fn treiber_stack() {
+        let stack = Arc::new(Stack {
+            head: AtomicTaggedPtr::default(),
+        });
+        let n = if cfg!(miri) { 10 } else { 1000 };
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    let mut popped = Vec::new();
+                    for i in 0..n {
+                        stack.push(t * n + i);
+                        popped.extend(stack.pop().map(SendPtr));
+                    }
+                    popped
+                })
+            })
+            .collect();
+        let mut nodes = Vec::new();
+        for handle in handles {
+            nodes.extend(handle.join().unwrap());
+        }
+        while let Some(node) = stack.pop() {
+            nodes.push(SendPtr(node));
+        }
+        let mut values: Vec<_> = nodes
+            .into_iter()
+            .map(|node| unsafe { Box::from_raw(node.0) }.value)
+            .collect();
+        values.sort_unstable();
+        assert_eq!(values, (0..4 * n).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,265 @@
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem;
+
+use crate::{ops, AtomicDouble, Ordering};
+
+/// A single-threaded counterpart to `AtomicDouble<T>` with the same methods.
+///
+/// Every operation is a plain read or write through an `UnsafeCell`, so an
+/// `UnsyncDouble` costs no more than a `Cell<T>`, and like a `Cell` it is not
+/// `Sync`. The methods take the same arguments as those of `AtomicDouble` and
+/// give the same results, which lets an algorithm be written once and run
+/// over either type, for instance picked by a type parameter or a feature.
+///
+/// The `Ordering` arguments have no effect, but are still checked the same
+/// way, so that an ordering `AtomicDouble` would reject panics here too.
+/// Values are compared and added as their 128-bit representation, as for
+/// `AtomicDouble`, and `T` can likewise be at most 16 bytes large.
+pub struct UnsyncDouble<T> {
+    v: UnsafeCell<T>,
+}
+
+impl<T> UnsyncDouble<T> {
+    /// Creates a new `UnsyncDouble`.
+    ///
+    /// As for `AtomicDouble::new`, a `T` larger than 16 bytes is rejected at
+    /// compile time.
+    #[inline]
+    pub const fn new(v: T) -> UnsyncDouble<T> {
+        let () = AtomicDouble::<T>::SIZE_CHECK;
+        UnsyncDouble {
+            v: UnsafeCell::new(v),
+        }
+    }
+
+    /// Returns a mutable reference to the underlying value.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.v.get_mut()
+    }
+
+    /// Consumes the `UnsyncDouble` and returns the contained value.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.v.into_inner()
+    }
+}
+
+impl<T: Copy> UnsyncDouble<T> {
+    /// Loads the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Release` or `AcqRel`.
+    #[inline]
+    pub fn load(&self, order: Ordering) -> T {
+        ops::check_load_ordering(order);
+        unsafe { *self.v.get() }
+    }
+
+    /// Stores a value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `order` is `Acquire` or `AcqRel`.
+    #[inline]
+    pub fn store(&self, val: T, order: Ordering) {
+        ops::check_store_ordering(order);
+        unsafe { *self.v.get() = val }
+    }
+
+    /// Stores a value, returning the previous value.
+    #[inline]
+    pub fn swap(&self, val: T, _order: Ordering) -> T {
+        unsafe { mem::replace(&mut *self.v.get(), val) }
+    }
+
+    /// Stores `new` if the current value is the same as `current`.
+    ///
+    /// Like `AtomicDouble::compare_exchange`, the values are compared bytewise
+    /// and the previous value is returned in `Ok` if `new` was written, and in
+    /// `Err` otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        ops::check_compare_exchange_ordering(success, failure);
+        let prev = unsafe { *self.v.get() };
+        if ops::same_value(&prev, &current) {
+            unsafe { *self.v.get() = new };
+            Ok(prev)
+        } else {
+            Err(prev)
+        }
+    }
+
+    /// Stores `new` if the current value is the same as `current`.
+    ///
+    /// This never fails spuriously, so it is the same as `compare_exchange`.
+    /// It exists so that loops written for `AtomicDouble` work unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `failure` is `Release`, `AcqRel` or stronger than `success`.
+    #[must_use = "the result of a compare-exchange indicates whether the swap happened and must be checked"]
+    #[inline]
+    pub fn compare_exchange_weak(
+        &self,
+        current: T,
+        new: T,
+        success: Ordering,
+        failure: Ordering,
+    ) -> Result<T, T> {
+        self.compare_exchange(current, new, success, failure)
+    }
+
+    /// Add to the current value, returning the previous value.
+    ///
+    /// As for `AtomicDouble::fetch_add`, the value is added as one 128-bit
+    /// unsigned integer, with wrapping.
+    #[inline]
+    pub fn fetch_add(&self, val: T, _order: Ordering) -> T {
+        unsafe {
+            let prev = *self.v.get();
+            let sum = ops::to_u128(&prev).wrapping_add(ops::to_u128(&val));
+            *self.v.get() = mem::transmute_copy(&sum);
+            prev
+        }
+    }
+
+    /// Subtract from the current value, returning the previous value.
+    #[inline]
+    pub fn fetch_sub(&self, val: T, _order: Ordering) -> T {
+        unsafe {
+            let prev = *self.v.get();
+            let diff = ops::to_u128(&prev).wrapping_sub(ops::to_u128(&val));
+            *self.v.get() = mem::transmute_copy(&diff);
+            prev
+        }
+    }
+}
+
+impl<T: Copy + Default> Default for UnsyncDouble<T> {
+    #[inline]
+    fn default() -> Self {
+        Self::new(T::default())
+    }
+}
+
+impl<T: Copy> From<T> for UnsyncDouble<T> {
+    #[inline]
+    fn from(v: T) -> Self {
+        Self::new(v)
+    }
+}
+
+impl<T: Copy + fmt::Debug> fmt::Debug for UnsyncDouble<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("UnsyncDouble")
+            .field(&self.load(Ordering::SeqCst))
+            .finish()
+    }
+}
+
+#[cfg(all(test, not(feature = "loom")))]
+mod tests {
+    use super::UnsyncDouble;
+    use crate::AtomicDouble;
+    use crate::Ordering::{self, AcqRel, Relaxed, SeqCst};
+
+    #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+    struct Bar(u64, u64);
+
+    // The operations both types share, so one sequence can be run over each.
+    trait Double<T> {
+        fn load(&self, order: Ordering) -> T;
+        fn store(&self, val: T, order: Ordering);
+        fn compare_exchange(&self, current: T, new: T, s: Ordering, f: Ordering) -> Result<T, T>;
+        fn fetch_add(&self, val: T, order: Ordering) -> T;
+    }
+
+    macro_rules! impl_double {
+        ($ty:ident) => {
+            impl<T: Copy> Double<T> for $ty<T> {
+                fn load(&self, order: Ordering) -> T {
+                    self.load(order)
+                }
+                fn store(&self, val: T, order: Ordering) {
+                    self.store(val, order)
+                }
+                fn compare_exchange(
+                    &self,
+                    current: T,
+                    new: T,
+                    s: Ordering,
+                    f: Ordering,
+                ) -> Result<T, T> {
+                    self.compare_exchange(current, new, s, f)
+                }
+                fn fetch_add(&self, val: T, order: Ordering) -> T {
+                    self.fetch_add(val, order)
+                }
+            }
+        };
+    }
+    impl_double!(AtomicDouble);
+    impl_double!(UnsyncDouble);
+
+    fn run<T: Copy, D: Double<T>>(d: &D, a: T, b: T) -> Vec<Result<T, T>> {
+        let mut out = vec![Ok(d.load(SeqCst))];
+        d.store(a, Relaxed);
+        out.push(Ok(d.fetch_add(b, SeqCst)));
+        out.push(Ok(d.load(Relaxed)));
+        out.push(d.compare_exchange(a, b, SeqCst, Relaxed));
+        let current = d.load(SeqCst);
+        out.push(d.compare_exchange(current, b, AcqRel, Relaxed));
+        out.push(Ok(d.fetch_add(b, Relaxed)));
+        out.push(Ok(d.load(SeqCst)));
+        out
+    }
+
+    #[test]
+    fn matches_atomic_double() {
+        let (a, b) = (Bar(u64::MAX, 1), Bar(3, 4));
+        assert_eq!(
+            run(&UnsyncDouble::new(Bar(5, 6)), a, b),
+            run(&AtomicDouble::new(Bar(5, 6)), a, b)
+        );
+        // Smaller types are compared and added with zero padding as well.
+        let (a, b) = (u32::MAX, 7u32);
+        assert_eq!(
+            run(&UnsyncDouble::new(1u32), a, b),
+            run(&AtomicDouble::new(1u32), a, b)
+        );
+    }
+
+    #[test]
+    fn unsync_double() {
+        let mut u = UnsyncDouble::new(Bar(1, 2));
+        assert_eq!(u.swap(Bar(3, 4), SeqCst), Bar(1, 2));
+        assert_eq!(u.fetch_sub(Bar(1, 0), SeqCst), Bar(3, 4));
+        assert_eq!(
+            u.compare_exchange_weak(Bar(2, 4), Bar(0, 0), SeqCst, SeqCst),
+            Ok(Bar(2, 4))
+        );
+        *u.get_mut() = Bar(9, 9);
+        assert_eq!(format!("{:?}", u), "UnsyncDouble(Bar(9, 9))");
+        assert_eq!(u.into_inner(), Bar(9, 9));
+    }
+
+    #[test]
+    #[should_panic]
+    fn unsync_double_checks_orderings() {
+        UnsyncDouble::new(0u64).load(Ordering::Release);
+    }
+}
@@ -0,0 +1,241 @@
+// Randomized sequences of `load`, `store`, `swap`, `compare_exchange` and
+// `fetch_add` on one `AtomicDouble<Bar>` shared by several threads, checked
+// against invariants that torn values or lost updates would break.
+//
+// Every run uses a new seed, printed when a check fails. To replay a failure,
+// or to run longer:
+//
+//     ATOMICDOUBLE_FUZZ_SEED=<seed> ATOMICDOUBLE_FUZZ_OPS=1000000 \
+//         cargo test --release --test cas_fuzz
+//
+// The same runs against the fallback with `--no-default-features --features
+// seqlock-fallback` and the like, or `--features tsan` to force it.
+
+// Under loom `AtomicDouble::new` has to be called inside `loom::model`.
+#![cfg(not(feature = "loom"))]
+
+use std::env;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use atomicdouble::AtomicDouble;
+use atomicdouble::Ordering::{self, AcqRel, Acquire, Relaxed, Release, SeqCst};
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct Bar(u64, u64);
+
+const THREADS: u64 = 4;
+
+// xorshift64*, which is plenty for picking operations.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+
+    fn order(&mut self, orders: &[Ordering]) -> Ordering {
+        orders[self.below(orders.len() as u64) as usize]
+    }
+}
+
+fn seed() -> u64 {
+    env::var("ATOMICDOUBLE_FUZZ_SEED")
+        .ok()
+        .map(|s| s.parse().expect("ATOMICDOUBLE_FUZZ_SEED must be a u64"))
+        .unwrap_or_else(|| {
+            // Miri has no real-time clock with isolation enabled.
+            if cfg!(miri) {
+                return 0x5eed;
+            }
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64
+        })
+        // xorshift gets stuck at zero.
+        | 1
+}
+
+fn ops_per_thread() -> u64 {
+    env::var("ATOMICDOUBLE_FUZZ_OPS")
+        .ok()
+        .map(|s| s.parse().expect("ATOMICDOUBLE_FUZZ_OPS must be a u64"))
+        .unwrap_or(if cfg!(miri) { 50 } else { 20_000 })
+}
+
+// Runs `body` on every thread with its own generator, all released at once.
+fn run(name: &str, body: fn(&AtomicDouble<Bar>, &mut Rng, u64, u64) -> u64) -> (Bar, u64) {
+    let seed = seed();
+    let ops = ops_per_thread();
+    let a = Arc::new(AtomicDouble::new(Bar(0, 0)));
+    let barrier = Arc::new(Barrier::new(THREADS as usize));
+    let handles: Vec<_> = (0..THREADS)
+        .map(|t| {
+            let a = a.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let mut rng = Rng(seed.wrapping_add(t.wrapping_mul(0x9e37_79b9_7f4a_7c15)) | 1);
+                barrier.wait();
+                body(&a, &mut rng, t, ops)
+            })
+        })
+        .collect();
+    let mut total = 0;
+    for handle in handles {
+        match handle.join() {
+            Ok(n) => total += n,
+            Err(_) => panic!("{} failed with ATOMICDOUBLE_FUZZ_SEED={}", name, seed),
+        }
+    }
+    println!("{}: seed {}", name, seed);
+    (a.load(SeqCst), total)
+}
+
+// Every value ever stored has both halves equal, so a value that doesn't must
+// have been torn. The deltas are small enough that the low half never carries
+// into the high one.
+fn check(v: Bar) -> Bar {
+    assert_eq!(v.0, v.1, "torn value {:?}", v);
+    v
+}
+
+fn mixed(a: &AtomicDouble<Bar>, rng: &mut Rng, _t: u64, ops: u64) -> u64 {
+    for _ in 0..ops {
+        let x = rng.below(1 << 32);
+        let d = rng.below(1 << 8);
+        match rng.below(5) {
+            0 => {
+                check(a.load(rng.order(&[Relaxed, Acquire, SeqCst])));
+            }
+            1 => a.store(Bar(x, x), rng.order(&[Relaxed, Release, SeqCst])),
+            2 => {
+                check(a.swap(
+                    Bar(x, x),
+                    rng.order(&[Relaxed, Acquire, Release, AcqRel, SeqCst]),
+                ));
+            }
+            3 => {
+                let current = if rng.below(2) == 0 {
+                    check(a.load(Relaxed))
+                } else {
+                    Bar(x, x)
+                };
+                let res = a.compare_exchange(current, Bar(x, x), AcqRel, Acquire);
+                match res {
+                    Ok(prev) => assert_eq!(prev, current),
+                    Err(actual) => assert_ne!(check(actual), current),
+                }
+            }
+            _ => {
+                check(a.fetch_add(Bar(d, d), rng.order(&[Relaxed, AcqRel, SeqCst])));
+            }
+        }
+    }
+    0
+}
+
+// Without stores, every update adds its delta, so the final value is the sum
+// of the deltas of all updates, whichever way they interleaved. Returns this
+// thread's share of it.
+fn adds(a: &AtomicDouble<Bar>, rng: &mut Rng, _t: u64, ops: u64) -> u64 {
+    let mut sum = 0;
+    for _ in 0..ops {
+        let d = rng.below(1 << 8);
+        match rng.below(4) {
+            0 => {
+                check(a.load(rng.order(&[Relaxed, Acquire, SeqCst])));
+            }
+            1 => {
+                let mut current = check(a.load(Relaxed));
+                loop {
+                    let new = Bar(current.0 + d, current.1 + d);
+                    match a.compare_exchange(current, new, AcqRel, Relaxed) {
+                        Ok(_) => break,
+                        Err(actual) => current = check(actual),
+                    }
+                }
+                sum += d;
+            }
+            2 => {
+                let mut current = check(a.load(Relaxed));
+                loop {
+                    let new = Bar(current.0 + d, current.1 + d);
+                    match a.compare_exchange_weak(current, new, Release, Relaxed) {
+                        Ok(_) => break,
+                        Err(actual) => current = check(actual),
+                    }
+                }
+                sum += d;
+            }
+            _ => {
+                check(a.fetch_add(Bar(d, d), rng.order(&[Relaxed, AcqRel, SeqCst])));
+                sum += d;
+            }
+        }
+    }
+    sum
+}
+
+// Each thread owns one bit of the low half, and toggles it with
+// `compare_exchange` while counting in the high half with `fetch_add`. A lost
+// or duplicated update shows in the count, or in a bit that doesn't match the
+// owner's own record of it.
+fn owned_bits(a: &AtomicDouble<Bar>, rng: &mut Rng, t: u64, ops: u64) -> u64 {
+    let bit = 1 << t;
+    let mut set = false;
+    let mut count = 0;
+    for _ in 0..ops {
+        if rng.below(2) == 0 {
+            let mut current = a.load(Acquire);
+            loop {
+                assert_eq!(
+                    current.0 & bit != 0,
+                    set,
+                    "bit {} changed under its owner",
+                    t
+                );
+                match a.compare_exchange(current, Bar(current.0 ^ bit, current.1), AcqRel, Acquire)
+                {
+                    Ok(_) => break,
+                    Err(actual) => current = actual,
+                }
+            }
+            set = !set;
+        } else {
+            let prev = a.fetch_add(Bar(0, 1), AcqRel);
+            assert_eq!(prev.0 & bit != 0, set, "bit {} changed under its owner", t);
+            count += 1;
+        }
+    }
+    let last = a.load(Acquire);
+    assert_eq!(last.0 & bit != 0, set, "bit {} changed under its owner", t);
+    count
+}
+
+#[test]
+fn mixed_operations_never_tear() {
+    let (last, _) = run("mixed_operations_never_tear", mixed);
+    check(last);
+}
+
+#[test]
+fn concurrent_updates_are_never_lost() {
+    let (last, sum) = run("concurrent_updates_are_never_lost", adds);
+    assert_eq!(last, Bar(sum, sum));
+}
+
+#[test]
+fn owned_bits_and_counter() {
+    let (last, count) = run("owned_bits_and_counter", owned_bits);
+    assert_eq!(last.1, count);
+    assert_eq!(last.0 & !((1 << THREADS) - 1), 0);
+}
@@ -0,0 +1,15 @@
+use atomicdouble::AtomicDoublePod;
+use core::ptr::NonNull;
+
+// `Option<NonNull<_>>` has a niche, so not every bit pattern is a valid value.
+struct Node {
+    next: Option<NonNull<Node>>,
+    count: u64,
+}
+
+fn main() {
+    let _ = AtomicDoublePod::new(Node {
+        next: None,
+        count: 0,
+    });
+}
@@ -0,0 +1,20 @@
+// Miri can't run the compiler that these tests drive, and under loom
+// `AtomicDouble::new` is not a `const fn`. With `std` the expected errors
+// point into `std` rather than `core`.
+#[cfg(not(any(miri, feature = "loom", feature = "std")))]
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
+
+// `AtomicDoublePod` rejects types that aren't `Pod`.
+#[cfg(all(
+    feature = "bytemuck",
+    not(any(miri, feature = "loom", feature = "std"))
+))]
+#[test]
+fn ui_bytemuck() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui-bytemuck/*.rs");
+}
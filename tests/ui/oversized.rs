@@ -0,0 +1,9 @@
+use atomicdouble::AtomicDouble;
+
+// `trybuild` only runs `cargo check`, which doesn't evaluate the constants of
+// generic functions, so the constructor is evaluated in a static instead.
+static A: AtomicDouble<[u8; 17]> = AtomicDouble::new([0; 17]);
+
+fn main() {
+    A.load(atomicdouble::Ordering::SeqCst);
+}
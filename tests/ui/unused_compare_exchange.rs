@@ -0,0 +1,11 @@
+#![deny(unused_must_use)]
+
+use atomicdouble::AtomicDouble;
+use atomicdouble::Ordering::SeqCst;
+
+fn main() {
+    let a = AtomicDouble::new(0u128);
+    a.compare_exchange(0, 1, SeqCst, SeqCst);
+    a.compare_exchange_weak(1, 2, SeqCst, SeqCst);
+    a.fetch_update(SeqCst, SeqCst, |x| Some(x + 1));
+}